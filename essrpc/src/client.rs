@@ -0,0 +1,181 @@
+//! Helpers for generated clients.
+//!
+//! [ClientInterceptors](trait.ClientInterceptor.html) add behavior to
+//! every call made by a generated client, such as attaching
+//! [Metadata](../struct.Metadata.html), for example
+//! ```ignore
+//! let client = FooRPCClient::new(transport)
+//!     .with_interceptor(Logger)
+//!     .with_interceptor(Retry::new(3));
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use crate::CallContext;
+use crate::{Metadata, MethodId, RPCError, Result};
+
+/// Describes a call being made by a generated client. Passed to a
+/// [ClientInterceptor](trait.ClientInterceptor.html).
+#[derive(Debug)]
+pub struct ClientCall {
+    method: MethodId,
+    attempt: u32,
+    metadata: Metadata,
+    response_metadata: Metadata,
+}
+
+impl ClientCall {
+    /// Used by generated clients.
+    pub fn new(method: MethodId) -> Self {
+        ClientCall {
+            method,
+            attempt: 0,
+            metadata: Metadata::new(),
+            response_metadata: Metadata::new(),
+        }
+    }
+
+    /// Used by generated clients.
+    #[cfg(feature = "std")]
+    pub fn with_context(mut self, context: &CallContext) -> Self {
+        self.metadata = context.request_metadata();
+        self
+    }
+
+    /// The method being called.
+    pub fn method(&self) -> MethodId {
+        self.method
+    }
+
+    /// The number of times the call has been retried, zero for the
+    /// first attempt.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Metadata to be sent with the call.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Metadata to be sent with the call, which may be modified in
+    /// `before_call`.
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+
+    /// Metadata received with the response, available in
+    /// `after_call`.
+    pub fn response_metadata(&self) -> &Metadata {
+        &self.response_metadata
+    }
+
+    /// Used by generated clients.
+    pub fn set_response_metadata(&mut self, metadata: Metadata) {
+        self.response_metadata = metadata;
+    }
+}
+
+/// What a client should do once a call has completed. Returned by
+/// [ClientInterceptor::after_call](trait.ClientInterceptor.html#method.after_call).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AfterCall {
+    /// Return the result to the caller.
+    Done,
+    /// Make the call again, with the same parameters.
+    Retry,
+}
+
+/// Behavior added to every call of a generated client, such as
+/// logging, measuring latency, or retrying failed calls. Interceptors
+/// are added to a client with `with_interceptor`. `before_call` and
+/// `on_param` are invoked in the order the interceptors were added,
+/// and `after_call` in the reverse order.
+///
+/// Interceptors must not make calls on the client they are added to.
+pub trait ClientInterceptor {
+    /// Called before the call is transmitted. Metadata to be sent
+    /// with the call may be set with `call.metadata_mut()`. Returning
+    /// an error aborts the call: nothing is transmitted and the error
+    /// is passed to `after_call` as the call's error.
+    fn before_call(&mut self, _call: &mut ClientCall) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with each parameter of the call as it is added to the
+    /// transport. The value may be serialized with any serde
+    /// serializer.
+    fn on_param(
+        &mut self,
+        _call: &ClientCall,
+        _name: &'static str,
+        _value: &dyn erased_serde::Serialize,
+    ) {
+    }
+
+    /// Called once the call has completed, with its error if it
+    /// failed on the client, in transport, or with an
+    /// `RPCError`. Errors returned by the server implementation
+    /// itself arrive as successful responses. The error may be
+    /// replaced or modified. Returning `AfterCall::Retry` makes the
+    /// call again once all interceptors have been invoked.
+    fn after_call(&mut self, _call: &ClientCall, _error: Option<&mut RPCError>) -> AfterCall {
+        AfterCall::Done
+    }
+}
+
+/// A stack of [ClientInterceptors](trait.ClientInterceptor.html). Used
+/// by generated clients.
+#[derive(Default)]
+pub struct ClientInterceptors {
+    stack: Vec<Box<dyn ClientInterceptor + Send>>,
+}
+
+impl ClientInterceptors {
+    pub fn new() -> Self {
+        ClientInterceptors { stack: Vec::new() }
+    }
+
+    /// Add an interceptor to the top of the stack.
+    pub fn push(&mut self, interceptor: impl ClientInterceptor + Send + 'static) {
+        self.stack.push(Box::new(interceptor));
+    }
+
+    pub fn before_call(&mut self, call: &mut ClientCall) -> Result<()> {
+        self.stack.iter_mut().try_for_each(|i| i.before_call(call))
+    }
+
+    pub fn on_param(
+        &mut self,
+        call: &ClientCall,
+        name: &'static str,
+        value: &impl serde::Serialize,
+    ) {
+        for i in self.stack.iter_mut() {
+            i.on_param(call, name, value);
+        }
+    }
+
+    /// Invoke `after_call` on each interceptor. Returns the result of
+    /// the call, or `None` if the call should be retried.
+    pub fn after_call<T>(
+        &mut self,
+        call: &mut ClientCall,
+        mut ret: Result<T>,
+    ) -> Option<Result<T>> {
+        let mut retry = false;
+        for i in self.stack.iter_mut().rev() {
+            if i.after_call(call, ret.as_mut().err()) == AfterCall::Retry {
+                retry = true;
+            }
+        }
+        if retry {
+            call.attempt += 1;
+            None
+        } else {
+            Some(ret)
+        }
+    }
+}
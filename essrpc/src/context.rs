@@ -0,0 +1,122 @@
+//! Context of a call, shared by clients and servers.
+use std::time::{Duration, Instant};
+
+use crate::server::{Connection, PeerAddr, PeerCredentials, ShutdownHandle};
+use crate::Metadata;
+
+/// Context of a call, received by a server implementation through a
+/// parameter marked `#[essrpc(context)]`:
+/// ```ignore
+/// #[essrpc]
+/// pub trait Foo {
+///     fn bar(&self, #[essrpc(context)] ctx: &CallContext, a: i32) -> Result<i32, RemoteError>;
+/// }
+/// ```
+/// The parameter is not transmitted. A client passes its own context,
+/// whose metadata and deadline are sent with the call, e.g.
+/// `client.bar(&CallContext::new().with_timeout(Duration::from_secs(5)), 1)`.
+#[derive(Clone, Debug, Default)]
+pub struct CallContext {
+    connection: Option<Connection>,
+    metadata: Metadata,
+    deadline: Option<Instant>,
+    shutdown: Option<ShutdownHandle>,
+}
+
+impl CallContext {
+    /// Metadata key holding the time remaining until the deadline, in
+    /// milliseconds.
+    pub const TIMEOUT_KEY: &'static str = "essrpc-timeout-ms";
+
+    /// Create an empty context, to be passed to a client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add metadata to be sent with the call.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key, value);
+        self
+    }
+
+    /// Set the time by which the call should complete.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set the time by which the call should complete, relative to now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Used by generated servers.
+    pub fn for_call(
+        connection: Option<&Connection>,
+        metadata: &Metadata,
+        shutdown: Option<&ShutdownHandle>,
+    ) -> Self {
+        let deadline = metadata
+            .get(Self::TIMEOUT_KEY)
+            .and_then(|ms| ms.parse().ok())
+            .map(|ms| Instant::now() + Duration::from_millis(ms));
+        CallContext {
+            connection: connection.cloned(),
+            metadata: metadata.clone(),
+            deadline,
+            shutdown: shutdown.cloned(),
+        }
+    }
+
+    /// Metadata to be sent with a call, including the deadline. Used
+    /// by generated clients.
+    pub fn request_metadata(&self) -> Metadata {
+        let mut metadata = self.metadata.clone();
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            metadata.insert(Self::TIMEOUT_KEY, remaining.as_millis().to_string());
+        }
+        metadata
+    }
+
+    /// Address of the peer, if the call was received on a connection
+    /// accepted by a [Listener](server/struct.Listener.html).
+    pub fn peer(&self) -> Option<&PeerAddr> {
+        self.connection.as_ref().map(Connection::peer)
+    }
+
+    /// Identifier of the connection, if the call was received on a
+    /// connection accepted by a [Listener](server/struct.Listener.html).
+    pub fn connection_id(&self) -> Option<u64> {
+        self.connection.as_ref().map(Connection::id)
+    }
+
+    /// Credentials of the peer, if the call was received on a Unix
+    /// socket connection accepted by a [Listener](server/struct.Listener.html).
+    pub fn peer_credentials(&self) -> Option<&PeerCredentials> {
+        self.connection.as_ref().and_then(Connection::credentials)
+    }
+
+    /// Metadata sent by the client with the call.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// The time by which the client expects the call to complete, if
+    /// it gave one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Whether the call should be abandoned, because its deadline has
+    /// passed or the server or listener is shutting down.
+    pub fn is_cancelled(&self) -> bool {
+        let expired = matches!(self.deadline, Some(deadline) if Instant::now() >= deadline);
+        let shutdown = self
+            .shutdown
+            .iter()
+            .chain(self.connection.iter().filter_map(|c| c.shutdown.as_ref()))
+            .any(ShutdownHandle::is_triggered);
+        expired || shutdown
+    }
+}
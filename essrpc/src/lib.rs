@@ -8,6 +8,15 @@
 //! * Uses only parameter and returns types which implement `Serialize`
 //! * Is not unsafe
 //!
+//! [RemoteError](struct.RemoteError.html) is provided as a ready-made
+//! error type. Methods may also return `anyhow::Result`, `eyre::Result`
+//! or `Result<T, Box<dyn Error + Send + Sync>>`, in which case errors
+//! are transmitted as a
+//! [GenericSerializableError](struct.GenericSerializableError.html), or
+//! a non-`Result` type, in which case the client panics (or returns a
+//! default value) if the RPC call fails. See the
+//! [essrpc](attr.essrpc.html) attribute for details.
+//!
 //! The `essrpc` macro generates for a trait an RPC client and a
 //! server. For a trait named `Foo`, the macro will generate
 //! `FooRPCClient` which implements both
@@ -44,6 +53,14 @@
 //! }
 //! ```
 //!
+//! A client makes one call at a time: it holds its transport from
+//! sending a call until the response has been read, so calls made
+//! from several threads through one client wait for each other, even
+//! if the server serves calls concurrently (see
+//! [ConcurrentServer](server/struct.ConcurrentServer.html)). Use a
+//! client, on a connection of its own, for each caller which should
+//! not be held up by the others.
+//!
 //! # Asynchronous Clients
 //!
 //! By default, the `#[essrpc]` attribute generates a synchronous
@@ -67,38 +84,70 @@
 //! `FooAsyncRPCClient` struct implementing both `FooAsync` and
 //! [AsyncRPCClient](trait.AsyncRPCClient.html).
 //!
+//! # no_std
+//!
+//! The core traits, generated clients and generated servers work in
+//! `no_std` environments with an allocator when the default `std`
+//! feature is disabled. Generated servers are then simpler: they have
+//! no interceptors, shutdown handles or call contexts, do not catch
+//! panics, and do not make request metadata available. The
+//! [server](server/index.html) module and all transports except
+//! [PostcardTransport](transports/struct.PostcardTransport.html)
+//! require `std`. Building without `std` requires Rust 1.81.
+//!
 
 // We do not do doctests on the examples above because with all the
 // macros and generated code, it is simply too much effort to get things working.
 
 // type_repetitation_in_bounds appears to be getting false positives and it's suggestions don't compile
 #![allow(clippy::type_repetition_in_bounds)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
 extern crate essrpc_macros;
 
 // We would like to mark as #[doc(inline)] and define the
 // on the macro definition site, but this does not work properly on macros
 pub use essrpc_macros::essrpc;
 
-use std::fmt;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use core::fmt;
+// core::error is only stable from Rust 1.81, which is needed without
+// std.
+#[cfg(not(feature = "std"))]
+use core::error::Error as StdError;
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
 
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "async_client")]
 use async_trait::async_trait;
 #[cfg(feature = "async_client")]
-use std::future::Future;
+use core::future::Future;
 #[cfg(feature = "async_client")]
-use std::pin::Pin;
-
+use core::pin::Pin;
+
+pub mod client;
+#[cfg(feature = "std")]
+mod context;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "std")]
+pub mod server;
 pub mod transports;
 
-type Result<T> = std::result::Result<T, RPCError>;
+#[cfg(feature = "std")]
+pub use context::CallContext;
+
+type Result<T> = core::result::Result<T, RPCError>;
 
 /// Identifies a method by both a name and an index. The Indices are
 /// automatically generated in the order methods are listed on the trait.
 /// Used when implementing [ClientTransport](trait.ClientTransport.html)
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct MethodId {
     pub name: &'static str,
     pub num: u32,
@@ -112,6 +161,69 @@ pub enum PartialMethodId {
     Num(u32),
 }
 
+/// Metadata attached to a call or its response, such as
+/// authentication tokens or trace ids, carried alongside the method
+/// and parameters by transports which support it. Clients set it per
+/// call with a [ClientInterceptor](client/trait.ClientInterceptor.html);
+/// servers read it with
+/// [request_metadata](server/fn.request_metadata.html).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Metadata(BTreeMap<String, String>);
+
+impl Metadata {
+    pub fn new() -> Self {
+        Metadata(BTreeMap::new())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Set the value of `key`, returning its previous value.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.0.insert(key.into(), value.into())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> core::iter::FromIterator<(K, V)> for Metadata {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Metadata(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        )
+    }
+}
+
+// Default for transports which cannot carry request metadata.
+pub(crate) fn metadata_unsupported(metadata: &Metadata) -> Result<()> {
+    if metadata.is_empty() {
+        Ok(())
+    } else {
+        Err(RPCError::new(
+            RPCErrorKind::Other,
+            "transport does not support metadata",
+        ))
+    }
+}
+
 /// Trait for RPC transport (client). ESSRPC attempts to make as few
 /// assumptions about the transport as possible. A transport may work
 /// across a network, via any IPC mechanism, or purely in memory
@@ -131,6 +243,12 @@ pub trait ClientTransport {
     /// Begin calling the given method. The transport may begin transmitting over the wire,
     /// or it may may wait until the call to `tx_finalize`.
     fn tx_begin_call(&mut self, method: MethodId) -> Result<Self::TXState>;
+    /// Attach metadata to a method call started with
+    /// `tx_begin_call`. Called before any parameters are added. The
+    /// default implementation accepts only empty metadata.
+    fn tx_set_metadata(&mut self, metadata: &Metadata, _state: &mut Self::TXState) -> Result<()> {
+        metadata_unsupported(metadata)
+    }
     /// Add a parameter to a method call started with
     /// `tx_begin_call`. This method is guaranteed to be called only
     /// after `tx_begin_call` and to be called appropriately for each
@@ -151,10 +269,19 @@ pub trait ClientTransport {
 
     /// Read the return value of a method call. Always called after
     /// `tx_finalize`. `state` is the object returned by
-    /// `tx_finalize`.
+    /// `tx_finalize`. Some transports (such as `BincodeTransport`
+    /// with request ids) allow several calls to be finalized before
+    /// their responses are read, in any order.
     fn rx_response<T>(&mut self, state: Self::FinalState) -> Result<T>
     where
         for<'de> T: Deserialize<'de>;
+
+    /// Take the metadata of the response most recently returned by
+    /// `rx_response`. The default implementation returns empty
+    /// metadata.
+    fn rx_response_metadata(&mut self) -> Metadata {
+        Metadata::new()
+    }
 }
 
 #[cfg(feature = "async_client")]
@@ -172,6 +299,16 @@ pub trait AsyncClientTransport: Send {
     /// Begin calling the given method. The transport may begin transmitting over the wire,
     /// or it may may wait until the call to `tx_finalize`.
     async fn tx_begin_call(&mut self, method: MethodId) -> Result<Self::TXState>;
+    /// Attach metadata to a method call started with
+    /// `tx_begin_call`. Called before any parameters are added. The
+    /// default implementation accepts only empty metadata.
+    async fn tx_set_metadata(
+        &mut self,
+        metadata: &Metadata,
+        _state: &mut Self::TXState,
+    ) -> Result<()> {
+        metadata_unsupported(metadata)
+    }
     /// Add a parameter to a method call started with
     /// `tx_begin_call`. This method is guaranteed to be called only
     /// after `tx_begin_call` and to be called appropriately for each
@@ -198,6 +335,13 @@ pub trait AsyncClientTransport: Send {
     where
         for<'de> T: Deserialize<'de>,
         T: 'static;
+
+    /// Take the metadata of the response most recently returned by
+    /// `rx_response`. The default implementation returns empty
+    /// metadata.
+    async fn rx_response_metadata(&mut self) -> Metadata {
+        Metadata::new()
+    }
 }
 
 /// Trait for RPC transport (server). ESSRPC attempts to make as few
@@ -212,6 +356,11 @@ pub trait ServerTransport {
     /// Begin reading a method cal on the server. Returns the method
     /// name or identifier and internal state.
     fn rx_begin_call(&mut self) -> Result<(PartialMethodId, Self::RXState)>;
+    /// Read the metadata sent with a call, after `rx_begin_call`. The
+    /// default implementation returns empty metadata.
+    fn rx_metadata(&mut self, _state: &mut Self::RXState) -> Result<Metadata> {
+        Ok(Metadata::new())
+    }
     /// Read a method parameter after a an `rx_begin_call`. Parameters
     /// are always read in order, so some transports may choose to
     /// ignore the name.
@@ -219,8 +368,60 @@ pub trait ServerTransport {
     where
         for<'de> T: serde::Deserialize<'de>;
 
+    /// Called once every parameter of a call has been read, before
+    /// the call is executed. Transports may use it to check that the
+    /// call held nothing more. The default implementation does
+    /// nothing.
+    fn rx_end_call(&mut self, _state: &mut Self::RXState) -> Result<()> {
+        Ok(())
+    }
+
+    /// Attach metadata to the next response, before `tx_response`.
+    /// Transports which cannot carry response metadata discard it,
+    /// as does the default implementation.
+    fn tx_set_response_metadata(&mut self, _metadata: &Metadata) -> Result<()> {
+        Ok(())
+    }
+
     /// Transmit a response (from the server side) to a method call.
     fn tx_response(&mut self, value: impl Serialize) -> Result<()>;
+
+    /// Transmit a response like `tx_response`, returning the size in
+    /// bytes of the encoded response message if the transport knows
+    /// it. Generated servers use this to pass the size to their
+    /// interceptors. The default implementation calls `tx_response`
+    /// and returns `None`.
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        self.tx_response(value)?;
+        Ok(None)
+    }
+
+    /// Called when serving a call fails after `rx_begin_call` without
+    /// a response having been transmitted, for example because the
+    /// method is unknown or a parameter could not be read. A transport
+    /// which can report the error to the client may do so and return
+    /// `Ok(())`, in which case the call counts as served. Generated
+    /// servers also call it with an error of kind
+    /// `RPCErrorKind::MessageTooLarge` from `rx_begin_call`, once the
+    /// transport has discarded an oversized call. The default
+    /// implementation returns the error.
+    fn tx_error(&mut self, error: RPCError) -> Result<()> {
+        Err(error)
+    }
+}
+
+/// Server transport from which calls may be read on one thread and
+/// answered on others, used by
+/// [ConcurrentServer](server/struct.ConcurrentServer.html). Responses
+/// may be sent in a different order than calls were received, so the
+/// transport should tag them with request ids.
+pub trait ConcurrentServerTransport {
+    /// Transport for a single call which has been read, used to read
+    /// its parameters and transmit its response.
+    type Call: ServerTransport + Send + 'static;
+
+    /// Read the next call.
+    fn rx_next_call(&mut self) -> Result<Self::Call>;
 }
 
 /// Trait implemented by all RPC clients generated by the `essrpc`
@@ -260,26 +461,94 @@ pub trait RPCServer {
     /// checked after serving a single call. It does not provide a
     /// mechanism to interrupt a server which is waiting for more data
     /// or for a connection to be established. If you need that
-    /// capability, you must build it into a
-    /// [Transport](trait.Transport.html)
+    /// capability, see
+    /// [ShutdownHandle](server/struct.ShutdownHandle.html)
     fn serve_until(&mut self, mut cond: impl FnMut() -> bool) -> Result<()> {
         loop {
-            self.serve_single_call()?;
+            serve_recovering(self)?;
             if !cond() {
                 return Ok(());
             }
         }
     }
 
-    /// Serve RPC calls indefinitely. The result will always be an
-    /// error, as it attempts to serve forever. It is recommended that
-    /// transport implementations return an error with
-    /// RPCErrorKind::TransportEOF when the client disconnects.
+    /// Serve RPC calls indefinitely. Unless the server has a
+    /// shutdown handle, the result will always be an error, as it
+    /// attempts to serve forever. It is recommended that transport
+    /// implementations return an error with RPCErrorKind::TransportEOF
+    /// when the client disconnects.
+    ///
+    /// Like `serve_until` and `serve_with_shutdown`, `serve` goes on
+    /// serving after an error of kind `RPCErrorKind::MessageTooLarge`,
+    /// as the transport has discarded the message. Unless the
+    /// transport responded to the discarded call with the error (see
+    /// `ServerTransport::tx_error`), the error is passed to
+    /// `report_error`.
     fn serve(&mut self) -> Result<()> {
+        #[cfg(feature = "std")]
+        {
+            if let Some(shutdown) = self.shutdown_handle().cloned() {
+                return self.serve_with_shutdown(&shutdown);
+            }
+        }
         loop {
-            self.serve_single_call()?;
+            serve_recovering(self)?;
         }
     }
+
+    /// Serve RPC calls until `shutdown` is triggered, then return
+    /// `Ok(())`. A call being served when the shutdown is triggered
+    /// is completed. An error from a call which fails after the
+    /// shutdown is triggered (for example because reading was
+    /// interrupted) is ignored.
+    #[cfg(feature = "std")]
+    fn serve_with_shutdown(&mut self, shutdown: &server::ShutdownHandle) -> Result<()> {
+        while !shutdown.is_triggered() {
+            if let Err(e) = serve_recovering(self) {
+                if shutdown.is_triggered() {
+                    break;
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The shutdown handle used by `serve`, if any. Generated servers
+    /// have one if given one with `with_shutdown`.
+    #[cfg(feature = "std")]
+    fn shutdown_handle(&self) -> Option<&server::ShutdownHandle> {
+        None
+    }
+
+    /// Called by a [Listener](server/struct.Listener.html) with the
+    /// connection the server is serving, which is made available to
+    /// implementations through a
+    /// [CallContext](struct.CallContext.html).
+    #[cfg(feature = "std")]
+    fn set_connection(&mut self, _connection: &server::Connection) {}
+
+    /// Called with an error which serving goes on after, which would
+    /// otherwise go unreported as the transport could not respond to
+    /// the call with it (see `ServerTransport::tx_error`), so the
+    /// client receives no response: an oversized call which the
+    /// transport discarded, or a call of a method returning a bare
+    /// value rather than a `Result` which panicked or was rejected by
+    /// an interceptor. Generated servers pass the error to the
+    /// callback given to their `on_error` method, if any.
+    fn report_error(&self, _error: &RPCError) {}
+}
+
+// Serve a single call, reporting and recovering from an oversized
+// call which the transport has discarded.
+pub(crate) fn serve_recovering<S: RPCServer + ?Sized>(server: &mut S) -> Result<()> {
+    match server.serve_single_call() {
+        Err(e) if e.kind == RPCErrorKind::MessageTooLarge => {
+            server.report_error(&e);
+            Ok(())
+        }
+        result => result,
+    }
 }
 
 /// Generic serializable error with a description and optional
@@ -290,7 +559,7 @@ pub struct GenericSerializableError {
     cause: Option<Box<GenericSerializableError>>,
 }
 impl GenericSerializableError {
-    pub fn new(e: impl std::error::Error) -> Self {
+    pub fn new(e: impl StdError) -> Self {
         let cause = e
             .source()
             .map(|ec| Box::new(GenericSerializableError::from_dyn(ec)));
@@ -300,11 +569,19 @@ impl GenericSerializableError {
         }
     }
 
+    /// Create a `GenericSerializableError` with the given description and no cause.
+    pub fn msg(description: impl Into<String>) -> Self {
+        GenericSerializableError {
+            description: description.into(),
+            cause: None,
+        }
+    }
+
     /// Create a `GenericSerializableError` from a trait object. This
     /// preserved the description and cause of the error (as another
     /// `GenericSerializableError`), but the specific type and
     /// backtrace of the error are lost.
-    pub fn from_dyn(e: &dyn std::error::Error) -> Self {
+    pub fn from_dyn(e: &dyn StdError) -> Self {
         let cause = e
             .source()
             .map(|ec| Box::new(GenericSerializableError::from_dyn(ec)));
@@ -314,8 +591,8 @@ impl GenericSerializableError {
         }
     }
 }
-impl std::error::Error for GenericSerializableError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+impl StdError for GenericSerializableError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
         #[allow(clippy::match_as_ref)] // clippy's suggestion doesn't compile
         match self.cause {
             Some(ref e) => Some(e),
@@ -332,6 +609,75 @@ impl fmt::Display for GenericSerializableError {
     }
 }
 
+/// Ready-made error type for RPC trait methods, for use when a
+/// bespoke error type is not needed. Holds either an `RPCError` or an
+/// error returned by the remote implementation. Like
+/// `GenericSerializableError`, the description and causes of the
+/// error are preserved, but not its type.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RemoteError {
+    kind: Option<RPCErrorKind>,
+    error: GenericSerializableError,
+}
+
+impl RemoteError {
+    /// New error from the given error and its causes.
+    pub fn new(e: impl StdError) -> Self {
+        RemoteError {
+            kind: None,
+            error: GenericSerializableError::new(e),
+        }
+    }
+
+    /// New error with only a message.
+    pub fn msg(msg: impl Into<String>) -> Self {
+        RemoteError {
+            kind: None,
+            error: GenericSerializableError::msg(msg),
+        }
+    }
+
+    /// The kind of the underlying `RPCError` if the error occurred in
+    /// the RPC machinery, `None` if the error was created by an RPC
+    /// trait implementation.
+    pub fn rpc_error_kind(&self) -> Option<&RPCErrorKind> {
+        self.kind.as_ref()
+    }
+}
+
+impl From<RPCError> for RemoteError {
+    fn from(e: RPCError) -> Self {
+        RemoteError {
+            kind: Some(e.kind),
+            error: GenericSerializableError {
+                description: e.msg,
+                cause: e.cause,
+            },
+        }
+    }
+}
+
+impl From<Box<dyn StdError + Send + Sync>> for RemoteError {
+    fn from(e: Box<dyn StdError + Send + Sync>) -> Self {
+        RemoteError {
+            kind: None,
+            error: GenericSerializableError::from_dyn(&*e),
+        }
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl StdError for RemoteError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
 /// RPC error. All functions in RPC traits must return an error type
 /// which implements `From<RPCError>`.
 #[derive(Debug, Deserialize, Serialize)]
@@ -352,11 +698,7 @@ impl RPCError {
     }
 
     /// New error with a cause.
-    pub fn with_cause(
-        kind: RPCErrorKind,
-        msg: impl Into<String>,
-        cause: impl std::error::Error,
-    ) -> Self {
+    pub fn with_cause(kind: RPCErrorKind, msg: impl Into<String>, cause: impl StdError) -> Self {
         RPCError {
             kind,
             msg: msg.into(),
@@ -379,10 +721,26 @@ impl fmt::Display for RPCError {
     }
 }
 
-impl std::error::Error for RPCError {}
+impl StdError for RPCError {}
 
+#[cfg(feature = "std")]
 impl From<std::io::Error> for RPCError {
     fn from(e: std::io::Error) -> RPCError {
+        // Codecs report errors as `io::Error`s, which may wrap an
+        // `RPCError`.
+        if e.get_ref().is_some_and(|inner| inner.is::<RPCError>()) {
+            return *e.into_inner().unwrap().downcast::<RPCError>().unwrap();
+        }
+        #[cfg(feature = "async_client")]
+        if e.get_ref()
+            .is_some_and(|inner| inner.is::<tokio_util::codec::LengthDelimitedCodecError>())
+        {
+            return RPCError::with_cause(
+                RPCErrorKind::MessageTooLarge,
+                "message exceeds maximum size",
+                e,
+            );
+        }
         match e.kind() {
             std::io::ErrorKind::UnexpectedEof => {
                 RPCError::with_cause(RPCErrorKind::TransportEOF, "IO error in transport", e)
@@ -392,14 +750,14 @@ impl From<std::io::Error> for RPCError {
     }
 }
 
-impl From<std::array::TryFromSliceError> for RPCError {
-    fn from(e: std::array::TryFromSliceError) -> RPCError {
+impl From<core::array::TryFromSliceError> for RPCError {
+    fn from(e: core::array::TryFromSliceError) -> RPCError {
         RPCError::with_cause(RPCErrorKind::TransportError, "IO error in transport", e)
     }
 }
 
 /// Types of [RPCError](trait.RPCError.html)
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum RPCErrorKind {
     /// Error caused by serialization or deserialization failure.
     SerializationError,
@@ -417,11 +775,22 @@ pub enum RPCErrorKind {
     IllegalState,
     /// Other error.
     Other,
+    /// The caller is not permitted to make the call, for example
+    /// because of its [PeerCredentials](server/struct.PeerCredentials.html).
+    PermissionDenied,
+    /// The server implementation panicked while handling the call.
+    ServerPanic,
+    /// A message was larger than the maximum size configured on the
+    /// transport (see
+    /// [DEFAULT_MAX_MESSAGE_SIZE](transports/constant.DEFAULT_MAX_MESSAGE_SIZE.html)).
+    /// The transport discards the message, so a server can go on
+    /// serving.
+    MessageTooLarge,
 }
 
 /// Type returned by async transport methods. A pinned dynamic-dispatch future.
 #[cfg(feature = "async_client")]
-pub type BoxFuture<T, E> = Pin<Box<dyn Future<Output = std::result::Result<T, E>>>>;
+pub type BoxFuture<T, E> = Pin<Box<dyn Future<Output = core::result::Result<T, E>>>>;
 
 pub mod internal {
     #[cfg(feature = "async_client")]
@@ -430,5 +799,31 @@ pub mod internal {
     #[cfg(feature = "async_client")]
     pub use futures::lock::Mutex as AsyncMutex;
 
+    #[cfg(not(feature = "std"))]
+    pub use self::nostd::SyncMutex;
+    #[cfg(feature = "std")]
     pub use parking_lot::Mutex as SyncMutex;
+
+    // Paths used by generated code, which cannot assume `std`.
+    pub use alloc::format;
+    pub use core::{convert, marker, result};
+
+    #[cfg(not(feature = "std"))]
+    mod nostd {
+        use core::cell::{RefCell, RefMut};
+
+        /// Stand-in for a mutex without `std`, where a client is used
+        /// from a single thread. Locking while already locked panics.
+        pub struct SyncMutex<T>(RefCell<T>);
+
+        impl<T> SyncMutex<T> {
+            pub fn new(value: T) -> Self {
+                SyncMutex(RefCell::new(value))
+            }
+
+            pub fn lock(&self) -> RefMut<'_, T> {
+                self.0.borrow_mut()
+            }
+        }
+    }
 }
@@ -0,0 +1,161 @@
+//! Support for mock implementations of RPC traits generated by
+//! `#[essrpc(mock)]`. Enable the "mock" feature to use this.
+//!
+//! For a trait `Foo` with a method `bar`, the macro generates
+//! `MockFoo` implementing `Foo` (and `FooAsync` if an async client is
+//! generated). Calls are answered by expectations set with
+//! `expect_bar`, for example
+//! ```ignore
+//! let mut mock = MockFoo::new();
+//! mock.expect_bar()
+//!     .with(("the answer".to_string(), 42))
+//!     .returning(|(a, b)| Ok(format!("{} is {}", a, b)));
+//! mock.expect_bar().rpc_error(RPCErrorKind::TransportEOF);
+//! ```
+//! Parameters are passed to expectations as a tuple, omitting any
+//! `#[essrpc(context)]` parameter. A call which
+//! matches no expectation panics. Local methods (and associated
+//! functions) run their default implementations.
+
+use crate::{RPCError, RPCErrorKind, Result};
+
+type Matcher<P> = Box<dyn Fn(&P) -> bool + Send>;
+
+enum Action<P, R> {
+    Return(Box<dyn FnMut(P) -> R + Send>),
+    Error(RPCErrorKind),
+}
+
+/// An expected call to a method of a mock, with `P` the tuple of
+/// parameter types and `R` the return type of the method.
+pub struct Expectation<P, R> {
+    matcher: Option<Matcher<P>>,
+    action: Option<Action<P, R>>,
+    times: Option<usize>,
+    calls: usize,
+}
+
+impl<P, R> Expectation<P, R> {
+    fn new() -> Self {
+        Expectation {
+            matcher: None,
+            action: None,
+            times: None,
+            calls: 0,
+        }
+    }
+
+    /// Only match calls with parameters equal to `params`.
+    pub fn with(&mut self, params: P) -> &mut Self
+    where
+        P: PartialEq + Send + 'static,
+    {
+        self.withf(move |p| *p == params)
+    }
+
+    /// Only match calls with parameters for which `f` returns `true`.
+    pub fn withf(&mut self, f: impl Fn(&P) -> bool + Send + 'static) -> &mut Self {
+        self.matcher = Some(Box::new(f));
+        self
+    }
+
+    /// Answer matching calls with the result of `f`.
+    pub fn returning(&mut self, f: impl FnMut(P) -> R + Send + 'static) -> &mut Self {
+        self.action = Some(Action::Return(Box::new(f)));
+        self
+    }
+
+    /// Answer matching calls as if the RPC call had failed with an
+    /// `RPCError` of the given kind. The error is converted to the
+    /// return type just as it would be by an RPC client.
+    pub fn rpc_error(&mut self, kind: RPCErrorKind) -> &mut Self {
+        self.action = Some(Action::Error(kind));
+        self
+    }
+
+    /// Match exactly `n` calls. Further calls fall through to later
+    /// expectations, and `checkpoint` on the mock panics if fewer
+    /// calls were made.
+    pub fn times(&mut self, n: usize) -> &mut Self {
+        self.times = Some(n);
+        self
+    }
+
+    /// Number of calls matched by this expectation.
+    pub fn call_count(&self) -> usize {
+        self.calls
+    }
+
+    fn matches(&self, params: &P) -> bool {
+        let exhausted = matches!(self.times, Some(n) if self.calls >= n);
+        let matched = match &self.matcher {
+            Some(m) => m(params),
+            None => true,
+        };
+        !exhausted && matched
+    }
+}
+
+/// All expectations for one method of a mock. Used by generated code.
+pub struct Expectations<P, R> {
+    method: &'static str,
+    expectations: Vec<Expectation<P, R>>,
+    calls: usize,
+}
+
+impl<P, R> Expectations<P, R> {
+    pub fn new(method: &'static str) -> Self {
+        Expectations {
+            method,
+            expectations: Vec::new(),
+            calls: 0,
+        }
+    }
+
+    /// Add a new expectation.
+    pub fn expect(&mut self) -> &mut Expectation<P, R> {
+        self.expectations.push(Expectation::new());
+        self.expectations.last_mut().unwrap()
+    }
+
+    /// Answer a call using the first matching expectation.
+    pub fn call(&mut self, params: P) -> Result<R> {
+        self.calls += 1;
+        let method = self.method;
+        let expectation = self
+            .expectations
+            .iter_mut()
+            .find(|e| e.matches(&params))
+            .unwrap_or_else(|| panic!("No matching expectation for call to {}", method));
+        expectation.calls += 1;
+        match expectation.action.as_mut() {
+            Some(Action::Return(f)) => Ok(f(params)),
+            Some(Action::Error(kind)) => Err(RPCError::new(
+                *kind,
+                format!("injected error in mock of {}", method),
+            )),
+            None => panic!("No return value set for expectation of {}", method),
+        }
+    }
+
+    /// Number of calls made to the method, whether or not they
+    /// matched an expectation.
+    pub fn call_count(&self) -> usize {
+        self.calls
+    }
+
+    /// Verify all expectations with a `times` count were satisfied,
+    /// then remove them.
+    pub fn checkpoint(&mut self) {
+        for e in self.expectations.drain(..) {
+            if let Some(n) = e.times {
+                if e.calls != n {
+                    panic!(
+                        "Expected {} calls to {}, but {} were made",
+                        n, self.method, e.calls
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1191 @@
+//! Helpers for running servers.
+//!
+//! A [Listener](struct.Listener.html) accepts connections on a TCP or
+//! Unix socket and serves each on its own thread, for example
+//! ```ignore
+//! let imp = Arc::new(FooImpl::new());
+//! Listener::new(TcpListener::bind("127.0.0.1:9000")?)
+//!     .max_connections(64)
+//!     .serve_bincode(move |tr, _conn| FooRPCServer::new(imp.clone(), tr))?;
+//! ```
+//! With `#[essrpc(arc)]`, the RPC trait is implemented for `Arc<T>`
+//! where `T` implements it, so one implementation may be shared by all
+//! connections.
+//! Alternatively a new implementation may be created for each
+//! connection.
+//!
+//! A [ShutdownHandle](struct.ShutdownHandle.html) stops a listener or
+//! a single server, letting calls in progress finish.
+//!
+//! A [ConcurrentServer](struct.ConcurrentServer.html) serves several
+//! calls from one connection at once.
+//!
+//! [Interceptors](trait.Interceptor.html) add behavior to every call
+//! of a generated server. [PeerAllowlist](struct.PeerAllowlist.html)
+//! is an interceptor authorizing Unix socket peers by their
+//! credentials.
+//!
+//! [request_metadata](fn.request_metadata.html) and
+//! [set_response_metadata](fn.set_response_metadata.html) give a
+//! server implementation access to the metadata of the call it is
+//! serving. A method may instead take a
+//! [CallContext](../struct.CallContext.html) parameter, which also
+//! describes the connection.
+//!
+//! A generated server catches a panic in the implementation and
+//! responds with an `RPCErrorKind::ServerPanic` error, continuing to
+//! serve. A method which does not return a `Result` has no way to
+//! report the error, so `serve_single_call` returns it instead. Use
+//! `catch_panics(false)` on the server to let panics unwind.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{
+    serve_recovering, ConcurrentServerTransport, Metadata, PartialMethodId, RPCError, RPCErrorKind,
+    RPCServer, Result, ServerTransport,
+};
+
+pub use erased_serde;
+// Formerly defined here.
+pub use crate::CallContext;
+
+/// Source of connections for a [Listener](struct.Listener.html).
+pub trait Accept {
+    type Stream: Read + Write + Send + 'static;
+
+    /// Wait for and accept a new connection.
+    fn accept_connection(&self) -> io::Result<(Self::Stream, PeerAddr)>;
+
+    /// Create an interrupt which unblocks a thread waiting in
+    /// `accept_connection`, used when a shutdown is triggered. If
+    /// `None`, a shutdown takes effect when the next connection is
+    /// accepted.
+    fn waker(&self) -> io::Result<Option<Interrupt>> {
+        Ok(None)
+    }
+
+    /// Create an interrupt which makes reads from `stream` return EOF
+    /// while still allowing writes, used when a shutdown is
+    /// triggered. If `None`, a shutdown takes effect for the
+    /// connection when its next call is received.
+    fn read_interrupt(_stream: &Self::Stream) -> io::Result<Option<Interrupt>> {
+        Ok(None)
+    }
+
+    /// Read the credentials of the peer of `stream`, if the stream
+    /// provides them.
+    fn peer_credentials(_stream: &Self::Stream) -> io::Result<Option<PeerCredentials>> {
+        Ok(None)
+    }
+}
+
+impl Accept for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept_connection(&self) -> io::Result<(TcpStream, PeerAddr)> {
+        let (stream, addr) = self.accept()?;
+        Ok((stream, PeerAddr::Tcp(addr)))
+    }
+
+    fn waker(&self) -> io::Result<Option<Interrupt>> {
+        let mut addr = self.local_addr()?;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        Ok(Some(Box::new(move || {
+            let _ = TcpStream::connect(addr);
+        })))
+    }
+
+    fn read_interrupt(stream: &TcpStream) -> io::Result<Option<Interrupt>> {
+        let stream = stream.try_clone()?;
+        Ok(Some(Box::new(move || {
+            let _ = stream.shutdown(Shutdown::Read);
+        })))
+    }
+}
+
+#[cfg(unix)]
+impl Accept for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept_connection(&self) -> io::Result<(UnixStream, PeerAddr)> {
+        let (stream, addr) = self.accept()?;
+        Ok((stream, PeerAddr::Unix(addr)))
+    }
+
+    fn waker(&self) -> io::Result<Option<Interrupt>> {
+        Ok(self.local_addr()?.as_pathname().map(|path| {
+            let path = path.to_path_buf();
+            Box::new(move || {
+                let _ = UnixStream::connect(path);
+            }) as Interrupt
+        }))
+    }
+
+    fn read_interrupt(stream: &UnixStream) -> io::Result<Option<Interrupt>> {
+        let stream = stream.try_clone()?;
+        Ok(Some(Box::new(move || {
+            let _ = stream.shutdown(Shutdown::Read);
+        })))
+    }
+
+    fn peer_credentials(stream: &UnixStream) -> io::Result<Option<PeerCredentials>> {
+        PeerCredentials::of(stream).map(Some)
+    }
+}
+
+/// Action run when a shutdown is triggered, to interrupt a blocked
+/// operation.
+pub type Interrupt = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct ShutdownState {
+    triggered: bool,
+    next_id: u64,
+    interrupts: Vec<(u64, Interrupt)>,
+}
+
+/// Handle used to stop servers. Clones refer to the same shutdown,
+/// which may be triggered from any thread.
+///
+/// Once triggered, servers (generated servers given the handle with
+/// `with_shutdown`, or `serve_with_shutdown`) finish the call in
+/// progress and return `Ok(())` instead of reading another call. A
+/// server blocked waiting for a call is only stopped if the read is
+/// interrupted, for example by shutting down the reading half of its
+/// socket:
+/// ```ignore
+/// let reader = stream.try_clone()?;
+/// let _registration = shutdown.on_shutdown(move || {
+///     let _ = reader.shutdown(std::net::Shutdown::Read);
+/// });
+/// FooRPCServer::new(FooImpl, BincodeTransport::new(stream))
+///     .with_shutdown(shutdown)
+///     .serve()
+/// ```
+/// A [Listener](struct.Listener.html) does this for each of its
+/// connections.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<(Mutex<ShutdownState>, Condvar)>,
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShutdownHandle")
+            .field("triggered", &self.is_triggered())
+            .finish()
+    }
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trigger the shutdown, running all registered interrupts.
+    pub fn trigger(&self) {
+        let interrupts = {
+            let mut state = self.inner.0.lock().unwrap();
+            if state.triggered {
+                return;
+            }
+            state.triggered = true;
+            std::mem::take(&mut state.interrupts)
+        };
+        self.inner.1.notify_all();
+        for (_, interrupt) in interrupts {
+            interrupt()
+        }
+    }
+
+    /// Whether the shutdown has been triggered.
+    pub fn is_triggered(&self) -> bool {
+        self.inner.0.lock().unwrap().triggered
+    }
+
+    /// Block until the shutdown is triggered.
+    pub fn wait(&self) {
+        let mut state = self.inner.0.lock().unwrap();
+        while !state.triggered {
+            state = self.inner.1.wait(state).unwrap();
+        }
+    }
+
+    /// Run `interrupt` when the shutdown is triggered, or immediately
+    /// if it already has been. The interrupt is removed when the
+    /// returned registration is dropped.
+    pub fn on_shutdown(&self, interrupt: impl FnOnce() + Send + 'static) -> ShutdownRegistration {
+        let mut state = self.inner.0.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        if state.triggered {
+            drop(state);
+            interrupt();
+        } else {
+            state.interrupts.push((id, Box::new(interrupt)));
+        }
+        ShutdownRegistration {
+            handle: self.clone(),
+            id,
+        }
+    }
+
+    /// Trigger the shutdown when the process receives SIGTERM. The
+    /// default action of SIGTERM (terminating the process) is no
+    /// longer taken. Enable the "signal_shutdown" feature to use this.
+    #[cfg(all(unix, feature = "signal_shutdown"))]
+    pub fn trigger_on_sigterm(&self) -> io::Result<()> {
+        let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGTERM])?;
+        let shutdown = self.clone();
+        thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                shutdown.trigger();
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Registration of an interrupt with a
+/// [ShutdownHandle](struct.ShutdownHandle.html). The interrupt is
+/// removed when this is dropped.
+pub struct ShutdownRegistration {
+    handle: ShutdownHandle,
+    id: u64,
+}
+
+impl Drop for ShutdownRegistration {
+    fn drop(&mut self) {
+        let mut state = self.handle.inner.0.lock().unwrap();
+        state.interrupts.retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Address of the peer of a connection.
+#[derive(Clone, Debug)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::SocketAddr),
+}
+
+/// Credentials of the process at the other end of a Unix socket, as
+/// reported by the operating system when the connection was made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    uid: u32,
+    gid: u32,
+    pid: Option<u32>,
+}
+
+impl PeerCredentials {
+    /// Read the credentials of the peer of `stream`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn of(stream: &UnixStream) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: cred and len are valid for writes of the given length.
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            uid: cred.uid,
+            gid: cred.gid,
+            pid: Some(cred.pid as u32),
+        })
+    }
+
+    /// Read the credentials of the peer of `stream`. The process id
+    /// is not available on this platform.
+    #[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+    pub fn of(stream: &UnixStream) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let mut uid = 0;
+        let mut gid = 0;
+        // SAFETY: uid and gid are valid for writes.
+        let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            uid,
+            gid,
+            pid: None,
+        })
+    }
+
+    /// User id of the peer process.
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Primary group id of the peer process.
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Process id of the peer, if available.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+}
+
+/// A connection accepted by a [Listener](struct.Listener.html).
+#[derive(Clone, Debug)]
+pub struct Connection {
+    pub(crate) id: u64,
+    pub(crate) peer: PeerAddr,
+    pub(crate) credentials: Option<PeerCredentials>,
+    // Shutdown handle of the listener, if any.
+    pub(crate) shutdown: Option<ShutdownHandle>,
+}
+
+impl Connection {
+    /// Identifier of the connection, unique within its listener.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Address of the peer.
+    pub fn peer(&self) -> &PeerAddr {
+        &self.peer
+    }
+
+    /// Credentials of the peer, read when the connection was
+    /// accepted. Available for Unix socket connections.
+    pub fn credentials(&self) -> Option<&PeerCredentials> {
+        self.credentials.as_ref()
+    }
+}
+
+type ConnectionCallback = Arc<dyn Fn(&Connection) + Send + Sync>;
+/// Callback for errors, given the connection on which the error
+/// occurred if there is one.
+pub type ErrorCallback = Arc<dyn Fn(Option<&Connection>, &RPCError) + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct Callbacks {
+    on_connect: Option<ConnectionCallback>,
+    on_disconnect: Option<ConnectionCallback>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl Callbacks {
+    fn error(&self, conn: Option<&Connection>, e: &RPCError) {
+        if let Some(f) = &self.on_error {
+            f(conn, e)
+        }
+    }
+}
+
+// Count of active connections, used to enforce max_connections.
+#[derive(Default)]
+struct ActiveCount {
+    count: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl ActiveCount {
+    // Wait until fewer than max connections are active. Returns false
+    // if the shutdown was triggered first.
+    fn wait_below(&self, max: Option<usize>, shutdown: &ShutdownHandle) -> bool {
+        let mut count = self.count.lock().unwrap();
+        if let Some(max) = max {
+            while *count >= max && !shutdown.is_triggered() {
+                count = self.changed.wait(count).unwrap();
+            }
+        }
+        !shutdown.is_triggered()
+    }
+
+    // Wait until no connections are active, or the timeout expires.
+    fn wait_none(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.count.lock().unwrap();
+        while *count > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+            count = self.changed.wait_timeout(count, deadline - now).unwrap().0;
+        }
+    }
+
+    fn wake(&self) {
+        let _count = self.count.lock().unwrap();
+        self.changed.notify_all();
+    }
+
+    fn acquire(self: &Arc<Self>) -> ActiveGuard {
+        *self.count.lock().unwrap() += 1;
+        ActiveGuard(self.clone())
+    }
+
+    fn release(&self) {
+        *self.count.lock().unwrap() -= 1;
+        self.changed.notify_all();
+    }
+}
+
+// Releases a connection slot when a connection thread exits, even by
+// panicking.
+struct ActiveGuard(Arc<ActiveCount>);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Accepts connections and serves each on its own thread with a
+/// server created for that connection. A connection ends when its
+/// server returns an error, which for a disconnected client is
+/// normally `RPCErrorKind::TransportEOF`. Other errors are reported to
+/// the `on_error` callback.
+///
+/// If given a [ShutdownHandle](struct.ShutdownHandle.html), the
+/// listener stops accepting connections when it is triggered. Each
+/// connection finishes the call in progress (if any) and ends. Once
+/// all connections have ended, or the drain timeout has expired,
+/// `serve` returns `Ok(())`. Connections still active after the drain
+/// timeout continue on their threads.
+pub struct Listener<A: Accept> {
+    acceptor: A,
+    max_connections: Option<usize>,
+    shutdown: Option<ShutdownHandle>,
+    drain_timeout: Duration,
+    callbacks: Callbacks,
+    active: Arc<ActiveCount>,
+    next_id: AtomicU64,
+}
+
+impl<A: Accept> Listener<A> {
+    /// Create a listener accepting connections from `acceptor`, such
+    /// as a `TcpListener` or `UnixListener`.
+    pub fn new(acceptor: A) -> Self {
+        Listener {
+            acceptor,
+            max_connections: None,
+            shutdown: None,
+            drain_timeout: Duration::from_secs(30),
+            callbacks: Callbacks::default(),
+            active: Arc::new(ActiveCount::default()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Limit the number of connections served at once. Further
+    /// connections are not accepted until an existing one ends.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Stop serving when `shutdown` is triggered.
+    pub fn shutdown(mut self, shutdown: ShutdownHandle) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Maximum time to wait for connections to end after a shutdown
+    /// is triggered. The default is 30 seconds.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Call `f` when a connection is accepted.
+    pub fn on_connect(mut self, f: impl Fn(&Connection) + Send + Sync + 'static) -> Self {
+        self.callbacks.on_connect = Some(Arc::new(f));
+        self
+    }
+
+    /// Call `f` when a connection ends.
+    pub fn on_disconnect(mut self, f: impl Fn(&Connection) + Send + Sync + 'static) -> Self {
+        self.callbacks.on_disconnect = Some(Arc::new(f));
+        self
+    }
+
+    /// Call `f` when a connection ends with an error other than
+    /// `TransportEOF`, or accepting a connection fails (in which case
+    /// there is no connection).
+    pub fn on_error(
+        mut self,
+        f: impl Fn(Option<&Connection>, &RPCError) + Send + Sync + 'static,
+    ) -> Self {
+        self.callbacks.on_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Get the underlying acceptor.
+    pub fn acceptor(&self) -> &A {
+        &self.acceptor
+    }
+
+    /// Number of connections currently being served.
+    pub fn active_connections(&self) -> usize {
+        *self.active.count.lock().unwrap()
+    }
+
+    /// Accept connections, serving each with the server returned by
+    /// `make_server` for its stream. Returns `Ok(())` after a
+    /// shutdown, or an error if accepting a connection fails with an
+    /// error which is not specific to that connection.
+    pub fn serve<S, F>(&self, mut make_server: F) -> Result<()>
+    where
+        F: FnMut(A::Stream, &Connection) -> S,
+        S: RPCServer + Send + 'static,
+    {
+        // Without a shutdown handle, use one which is never triggered
+        let shutdown = self.shutdown.clone().unwrap_or_default();
+        let _waker = self
+            .acceptor
+            .waker()?
+            .map(|waker| shutdown.on_shutdown(waker));
+        let active = self.active.clone();
+        let _wake_waiting = shutdown.on_shutdown(move || active.wake());
+
+        while self.active.wait_below(self.max_connections, &shutdown) {
+            let (stream, peer) = match self.acceptor.accept_connection() {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    let transient = is_transient(&e);
+                    let e = RPCError::with_cause(
+                        RPCErrorKind::TransportError,
+                        "failed to accept connection",
+                        e,
+                    );
+                    self.callbacks.error(None, &e);
+                    if transient {
+                        continue;
+                    }
+                    return Err(e);
+                }
+            };
+            if shutdown.is_triggered() {
+                break;
+            }
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let credentials = match A::peer_credentials(&stream) {
+                Ok(credentials) => credentials,
+                Err(e) => {
+                    self.callbacks.error(None, &e.into());
+                    continue;
+                }
+            };
+            let conn = Connection {
+                id,
+                peer,
+                credentials,
+                shutdown: self.shutdown.clone(),
+            };
+            let interrupt = match A::read_interrupt(&stream) {
+                Ok(interrupt) => interrupt,
+                Err(e) => {
+                    self.callbacks.error(Some(&conn), &e.into());
+                    continue;
+                }
+            };
+            let guard = self.active.acquire();
+            let mut server = make_server(stream, &conn);
+            server.set_connection(&conn);
+            let callbacks = self.callbacks.clone();
+            let shutdown = self.shutdown.clone();
+            thread::spawn(move || {
+                let _guard = guard;
+                let _registration = match (&shutdown, interrupt) {
+                    (Some(shutdown), Some(interrupt)) => Some(shutdown.on_shutdown(interrupt)),
+                    _ => None,
+                };
+                serve_connection(server, conn, &callbacks, shutdown.as_ref())
+            });
+        }
+        self.active.wait_none(self.drain_timeout);
+        Ok(())
+    }
+
+    /// Like `serve`, with each connection using a
+    /// [BincodeTransport](../transports/struct.BincodeTransport.html).
+    #[cfg(feature = "bincode_transport")]
+    pub fn serve_bincode<S, F>(&self, mut make_server: F) -> Result<()>
+    where
+        F: FnMut(crate::transports::BincodeTransport<A::Stream>, &Connection) -> S,
+        S: RPCServer + Send + 'static,
+    {
+        self.serve(|stream, conn| {
+            make_server(crate::transports::BincodeTransport::new(stream), conn)
+        })
+    }
+
+    /// Like `serve`, with each connection using a
+    /// [JSONTransport](../transports/struct.JSONTransport.html).
+    #[cfg(feature = "json_transport")]
+    pub fn serve_json<S, F>(&self, mut make_server: F) -> Result<()>
+    where
+        F: FnMut(crate::transports::JSONTransport<A::Stream>, &Connection) -> S,
+        S: RPCServer + Send + 'static,
+    {
+        self.serve(|stream, conn| make_server(crate::transports::JSONTransport::new(stream), conn))
+    }
+
+    /// Like `serve`, with each connection using an
+    /// [HTTPServerTransport](../transports/struct.HTTPServerTransport.html).
+    #[cfg(feature = "http_transport")]
+    pub fn serve_http<S, F>(&self, mut make_server: F) -> Result<()>
+    where
+        F: FnMut(crate::transports::HTTPServerTransport<A::Stream>, &Connection) -> S,
+        S: RPCServer + Send + 'static,
+    {
+        self.serve(|stream, conn| {
+            make_server(crate::transports::HTTPServerTransport::new(stream), conn)
+        })
+    }
+}
+
+fn serve_connection(
+    mut server: impl RPCServer,
+    conn: Connection,
+    callbacks: &Callbacks,
+    shutdown: Option<&ShutdownHandle>,
+) {
+    if let Some(f) = &callbacks.on_connect {
+        f(&conn)
+    }
+    let result = match shutdown {
+        Some(shutdown) => server.serve_with_shutdown(shutdown),
+        None => server.serve(),
+    };
+    if let Err(e) = result {
+        if e.kind != RPCErrorKind::TransportEOF {
+            callbacks.error(Some(&conn), &e);
+        }
+    }
+    if let Some(f) = &callbacks.on_disconnect {
+        f(&conn)
+    }
+}
+
+// Errors from accept which concern only the connection being
+// accepted.
+pub(crate) fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+    )
+}
+
+// Calls being executed by a ConcurrentServer, and the first error
+// from any of them.
+#[derive(Default)]
+struct InFlight {
+    state: Mutex<(usize, Option<RPCError>)>,
+    changed: Condvar,
+}
+
+impl InFlight {
+    fn acquire(&self, max: usize) {
+        let mut state = self.state.lock().unwrap();
+        while state.0 >= max {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.0 += 1;
+    }
+
+    fn release(&self, result: Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        state.0 -= 1;
+        if let Err(e) = result {
+            state.1.get_or_insert(e);
+        }
+        self.changed.notify_all();
+    }
+
+    fn wait_none(&self) {
+        let mut state = self.state.lock().unwrap();
+        while state.0 > 0 {
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn take_error(&self) -> Result<()> {
+        match self.state.lock().unwrap().1.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() -> Result<()> + Send>;
+
+/// Server which reads calls from a single transport continuously and
+/// executes them on a pool of worker threads, so that a slow call does
+/// not delay others. Responses are sent as calls complete, which may
+/// be in a different order than the calls were received, so the
+/// transport should tag responses with request ids (e.g.
+/// `BincodeTransport::with_request_ids`). A generated client makes one
+/// call at a time, so concurrency on one connection only helps clients
+/// which send calls before their earlier responses are read, by
+/// driving the transport directly; otherwise use a connection per
+/// concurrent caller, accepted with a [Listener](struct.Listener.html).
+///
+/// Each call is served by a server created by `make_server` for the
+/// call's transport. Servers usually share an implementation through
+/// an `Arc` (see `#[essrpc(arc)]`), which requires the implementation
+/// to be `Sync`:
+/// ```ignore
+/// let imp = Arc::new(FooImpl::new());
+/// let mut server = ConcurrentServer::new(
+///     BincodeTransport::new(stream).with_request_ids(),
+///     8,
+///     move |call| FooRPCServer::new(imp.clone(), call),
+/// );
+/// server.serve()
+/// ```
+/// `serve_single_call` returns once a call has been read and handed
+/// to a worker. An error from a call is returned by the following
+/// `serve_single_call`, including an error of kind
+/// `RPCErrorKind::ServerPanic` for a call which panicked in a server
+/// not catching panics; its worker goes on serving calls. Dropping the server waits for calls in
+/// progress to complete.
+pub struct ConcurrentServer<TR: ConcurrentServerTransport, F> {
+    transport: TR,
+    make_server: F,
+    max_concurrency: usize,
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    in_flight: Arc<InFlight>,
+    shutdown: Option<ShutdownHandle>,
+    connection: Option<Connection>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl<TR, F, S> ConcurrentServer<TR, F>
+where
+    TR: ConcurrentServerTransport,
+    F: FnMut(TR::Call) -> S,
+    S: RPCServer + Send + 'static,
+{
+    /// Create a server executing at most `max_concurrency` calls at
+    /// once, each on its own worker thread. Reading further calls
+    /// waits for a worker to become free.
+    pub fn new(transport: TR, max_concurrency: usize, make_server: F) -> Self {
+        assert!(max_concurrency > 0, "max_concurrency must be at least 1");
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let in_flight = Arc::new(InFlight::default());
+        let workers = (0..max_concurrency)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let in_flight = in_flight.clone();
+                thread::spawn(move || loop {
+                    let job = match receiver.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    // A call which panics (if its server does not catch
+                    // panics) fails, but the worker goes on
+                    in_flight.release(catch_panic(true, job).and_then(|result| result));
+                })
+            })
+            .collect();
+        ConcurrentServer {
+            transport,
+            make_server,
+            max_concurrency,
+            jobs: Some(jobs),
+            workers,
+            in_flight,
+            shutdown: None,
+            connection: None,
+            on_error: None,
+        }
+    }
+
+    /// Use `shutdown` to stop `serve`. See
+    /// [ShutdownHandle](struct.ShutdownHandle.html).
+    pub fn with_shutdown(mut self, shutdown: ShutdownHandle) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Call `f` with an oversized call which the transport discarded
+    /// and `serve` went on after. See `RPCServer::report_error`.
+    pub fn on_error(
+        mut self,
+        f: impl Fn(Option<&Connection>, &RPCError) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Get the transport calls are read from.
+    pub fn transport(&self) -> &TR {
+        &self.transport
+    }
+}
+
+impl<TR, F, S> RPCServer for ConcurrentServer<TR, F>
+where
+    TR: ConcurrentServerTransport,
+    F: FnMut(TR::Call) -> S,
+    S: RPCServer + Send + 'static,
+{
+    fn serve_single_call(&mut self) -> Result<()> {
+        self.in_flight.take_error()?;
+        let call = self.transport.rx_next_call()?;
+        let mut server = (self.make_server)(call);
+        if let Some(connection) = &self.connection {
+            server.set_connection(connection);
+        }
+        self.in_flight.acquire(self.max_concurrency);
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(Box::new(move || server.serve_single_call()))
+            .map_err(|_| RPCError::new(RPCErrorKind::IllegalState, "worker pool has stopped"))
+    }
+
+    /// Like the default `serve_with_shutdown`, but also waits for
+    /// calls in progress to complete before returning.
+    fn serve_with_shutdown(&mut self, shutdown: &ShutdownHandle) -> Result<()> {
+        let mut result = Ok(());
+        while !shutdown.is_triggered() {
+            if let Err(e) = serve_recovering(self) {
+                if !shutdown.is_triggered() {
+                    result = Err(e);
+                }
+                break;
+            }
+        }
+        self.in_flight.wait_none();
+        result
+    }
+
+    fn shutdown_handle(&self) -> Option<&ShutdownHandle> {
+        self.shutdown.as_ref()
+    }
+
+    fn set_connection(&mut self, connection: &Connection) {
+        self.connection = Some(connection.clone());
+    }
+
+    fn report_error(&self, error: &RPCError) {
+        if let Some(f) = &self.on_error {
+            f(self.connection.as_ref(), error)
+        }
+    }
+}
+
+impl<TR: ConcurrentServerTransport, F> Drop for ConcurrentServer<TR, F> {
+    fn drop(&mut self) {
+        // Workers exit once the queue is closed and empty
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A call being served, as seen by an
+/// [Interceptor](trait.Interceptor.html).
+#[derive(Debug)]
+pub struct CallInfo<'a> {
+    method: &'a PartialMethodId,
+    name: Option<&'static str>,
+    metadata: &'a Metadata,
+    connection: Option<&'a Connection>,
+}
+
+impl<'a> CallInfo<'a> {
+    /// Used by generated servers.
+    pub fn new(
+        method: &'a PartialMethodId,
+        name: Option<&'static str>,
+        metadata: &'a Metadata,
+        connection: Option<&'a Connection>,
+    ) -> Self {
+        CallInfo {
+            method,
+            name,
+            metadata,
+            connection,
+        }
+    }
+
+    /// The method identifier as received by the transport.
+    pub fn method_id(&self) -> &PartialMethodId {
+        self.method
+    }
+
+    /// The name of the method, or `None` if the server has no such
+    /// method.
+    pub fn method_name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Metadata sent by the client with the call.
+    pub fn metadata(&self) -> &Metadata {
+        self.metadata
+    }
+
+    /// The connection the call was received on, if the server is
+    /// serving a connection accepted by a [Listener](struct.Listener.html).
+    pub fn connection(&self) -> Option<&'a Connection> {
+        self.connection
+    }
+}
+
+/// Behavior added to every call of a generated server, such as
+/// logging, authorization or rate limiting. Interceptors are added to
+/// a server with `with_interceptor`, e.g.
+/// ```ignore
+/// FooRPCServer::new(FooImpl::new(), transport)
+///     .with_interceptor(Logger)
+///     .with_interceptor(RateLimit::new(100))
+/// ```
+/// `before_call` is invoked in the order the interceptors were added,
+/// and `after_call` in the reverse order.
+pub trait Interceptor<TR: ServerTransport> {
+    /// Called after a call has been received, before its parameters
+    /// are read. `state` is the transport's state for the call.
+    /// Returning an error rejects the call: the method is not
+    /// executed, later interceptors' `before_call` is not invoked,
+    /// and the error is sent to the client as the method's error.
+    /// (Methods which do not return a `Result` cannot return an error,
+    /// so `serve_single_call` returns the error instead.)
+    fn before_call(&mut self, _call: &CallInfo, _state: &mut TR::RXState) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with the response to a call, including a rejected call,
+    /// once it has been transmitted. The response may be serialized
+    /// with any serde serializer. `size` is the size in bytes of the
+    /// response as encoded by the transport, if the transport reports
+    /// it (see `ServerTransport::tx_response_sized`).
+    fn after_call(
+        &mut self,
+        _call: &CallInfo,
+        _response: &dyn erased_serde::Serialize,
+        _size: Option<usize>,
+    ) {
+    }
+}
+
+/// A stack of [Interceptors](trait.Interceptor.html). Used by
+/// generated servers.
+pub struct Interceptors<TR: ServerTransport> {
+    stack: Vec<Box<dyn Interceptor<TR> + Send>>,
+}
+
+impl<TR: ServerTransport> Interceptors<TR> {
+    pub fn new() -> Self {
+        Interceptors { stack: Vec::new() }
+    }
+
+    /// Add an interceptor to the top of the stack.
+    pub fn push(&mut self, interceptor: impl Interceptor<TR> + Send + 'static) {
+        self.stack.push(Box::new(interceptor));
+    }
+
+    pub fn before_call(&mut self, call: &CallInfo, state: &mut TR::RXState) -> Result<()> {
+        self.stack
+            .iter_mut()
+            .try_for_each(|i| i.before_call(call, state))
+    }
+
+    pub fn after_call(
+        &mut self,
+        call: &CallInfo,
+        response: &dyn erased_serde::Serialize,
+        size: Option<usize>,
+    ) {
+        for i in self.stack.iter_mut().rev() {
+            i.after_call(call, response, size);
+        }
+    }
+}
+
+impl<TR: ServerTransport> Default for Interceptors<TR> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Interceptor permitting calls only from peers whose
+/// [PeerCredentials](struct.PeerCredentials.html) have an allowed
+/// user id or primary group id, e.g.
+/// ```ignore
+/// FooRPCServer::new(FooImpl::new(), transport)
+///     .with_interceptor(PeerAllowlist::new().allow_uid(0).allow_gid(wheel))
+/// ```
+/// Other calls, including calls on connections without credentials,
+/// are rejected with `RPCErrorKind::PermissionDenied` before the
+/// method is dispatched. Credentials are available for Unix socket
+/// connections accepted by a [Listener](struct.Listener.html).
+#[derive(Clone, Debug, Default)]
+pub struct PeerAllowlist {
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+impl PeerAllowlist {
+    /// Create an allowlist permitting no peers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Permit peers running as `uid`.
+    pub fn allow_uid(mut self, uid: u32) -> Self {
+        self.uids.push(uid);
+        self
+    }
+
+    /// Permit peers whose primary group is `gid`.
+    pub fn allow_gid(mut self, gid: u32) -> Self {
+        self.gids.push(gid);
+        self
+    }
+
+    /// Whether a peer with `credentials` is permitted.
+    pub fn allows(&self, credentials: Option<&PeerCredentials>) -> bool {
+        match credentials {
+            Some(c) => self.uids.contains(&c.uid) || self.gids.contains(&c.gid),
+            None => false,
+        }
+    }
+}
+
+impl<TR: ServerTransport> Interceptor<TR> for PeerAllowlist {
+    fn before_call(&mut self, call: &CallInfo, _state: &mut TR::RXState) -> Result<()> {
+        if self.allows(call.connection().and_then(Connection::credentials)) {
+            Ok(())
+        } else {
+            Err(RPCError::new(
+                RPCErrorKind::PermissionDenied,
+                "peer is not permitted to call this server",
+            ))
+        }
+    }
+}
+
+// Metadata of the calls being served on this thread, innermost last.
+struct CallMetadata {
+    request: Metadata,
+    response: Metadata,
+}
+
+thread_local! {
+    static CALL_METADATA: RefCell<Vec<CallMetadata>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Metadata sent by the client with the call being served on the
+/// current thread. Empty if no call is being served. May be used by a
+/// server implementation or an interceptor.
+pub fn request_metadata() -> Metadata {
+    CALL_METADATA.with(|m| {
+        m.borrow()
+            .last()
+            .map(|m| m.request.clone())
+            .unwrap_or_default()
+    })
+}
+
+/// Attach metadata to the response to the call being served on the
+/// current thread. Has no effect if no call is being served. May be
+/// used by a server implementation or an interceptor.
+pub fn set_response_metadata(key: impl Into<String>, value: impl Into<String>) {
+    CALL_METADATA.with(|m| {
+        if let Some(m) = m.borrow_mut().last_mut() {
+            m.response.insert(key, value);
+        }
+    })
+}
+
+/// Makes the metadata of a call available to
+/// [request_metadata](fn.request_metadata.html) while it is served.
+/// Used by generated servers.
+pub struct MetadataScope {
+    _private: (),
+}
+
+impl MetadataScope {
+    pub fn enter(request: &Metadata) -> Self {
+        CALL_METADATA.with(|m| {
+            m.borrow_mut().push(CallMetadata {
+                request: request.clone(),
+                response: Metadata::new(),
+            })
+        });
+        MetadataScope { _private: () }
+    }
+
+    /// Metadata set with
+    /// [set_response_metadata](fn.set_response_metadata.html) so far.
+    pub fn response_metadata(&self) -> Metadata {
+        CALL_METADATA.with(|m| {
+            m.borrow()
+                .last()
+                .map(|m| m.response.clone())
+                .unwrap_or_default()
+        })
+    }
+}
+
+impl Drop for MetadataScope {
+    fn drop(&mut self) {
+        CALL_METADATA.with(|m| m.borrow_mut().pop());
+    }
+}
+
+/// Call `f`, converting a panic into an error of kind
+/// `RPCErrorKind::ServerPanic` carrying the panic message if `catch`
+/// is set. Used by generated servers.
+pub fn catch_panic<R>(catch: bool, f: impl FnOnce() -> R) -> Result<R> {
+    if !catch {
+        return Ok(f());
+    }
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).map_err(|payload| {
+        let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.as_str()
+        } else {
+            "unknown panic"
+        };
+        RPCError::new(
+            RPCErrorKind::ServerPanic,
+            format!("server panicked: {}", msg),
+        )
+    })
+}
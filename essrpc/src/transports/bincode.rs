@@ -1,15 +1,170 @@
+use super::WireFormat;
 use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::io;
 use std::io::{Read, Write};
-use tokio_util::codec::LengthDelimitedCodec;
+use std::sync::Arc;
 
+use super::framing::{read_msg_endian, write_msg_endian, Endian};
+use super::{DuplicateChannel, DEFAULT_MAX_MESSAGE_SIZE};
+use crate::internal::SyncMutex;
 use crate::{
-    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
+    metadata_unsupported, ClientTransport, ConcurrentServerTransport, Metadata, MethodId,
+    PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
 };
 
-fn serialize(w: impl Write, value: impl Serialize) -> Result<()> {
-    bincode::serialize_into(w, &value).map_err(|e| {
+// Version of the frame header written by `with_frame_header`.
+const FRAME_VERSION: u8 = 1;
+
+// Flags of the frame header.
+const FLAG_VARINT: u8 = 0x01;
+const FLAG_BIG_ENDIAN: u8 = 0x02;
+const FLAG_REQUEST_IDS: u8 = 0x04;
+const FLAG_METADATA: u8 = 0x08;
+
+/// Encoding options shared by the bincode transports.
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    varint: bool,
+    endian: Endian,
+    reject_trailing_bytes: bool,
+    frame_header: bool,
+    request_ids: bool,
+    metadata: bool,
+    max_message_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            varint: false,
+            endian: Endian::Little,
+            reject_trailing_bytes: false,
+            frame_header: false,
+            request_ids: false,
+            metadata: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+impl Config {
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.varint {
+            flags |= FLAG_VARINT;
+        }
+        if self.endian == Endian::Big {
+            flags |= FLAG_BIG_ENDIAN;
+        }
+        if self.request_ids {
+            flags |= FLAG_REQUEST_IDS;
+        }
+        if self.metadata {
+            flags |= FLAG_METADATA;
+        }
+        flags
+    }
+
+    // Bytes preceding the request id of each message.
+    fn header(&self) -> Vec<u8> {
+        if self.frame_header {
+            vec![FRAME_VERSION, self.flags()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Check and remove the header of a received message.
+    fn split_header(&self, mut msg: Vec<u8>) -> Result<Vec<u8>> {
+        if !self.frame_header {
+            return Ok(msg);
+        }
+        if msg.len() < 2 {
+            return Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                "bincode message too short for frame header",
+            ));
+        }
+        if msg[0] != FRAME_VERSION {
+            return Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!("unsupported bincode frame version {}", msg[0]),
+            ));
+        }
+        if msg[1] != self.flags() {
+            return Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!(
+                    "bincode frame flags {:#04x} do not match configuration {:#04x}",
+                    msg[1],
+                    self.flags()
+                ),
+            ));
+        }
+        msg.drain(..2);
+        Ok(msg)
+    }
+
+    fn write_msg(&self, w: impl Write, id: Option<u32>, msg: Vec<u8>) -> Result<()> {
+        let mut framed = self.header();
+        if let Some(id) = id {
+            framed.extend_from_slice(&self.endian.u32_to_bytes(id));
+        }
+        if framed.is_empty() {
+            return write_msg_endian(w, &msg, self.endian, self.max_message_size);
+        }
+        framed.extend(msg);
+        write_msg_endian(w, &framed, self.endian, self.max_message_size)
+    }
+
+    fn read_msg(&self, r: impl Read) -> Result<Vec<u8>> {
+        self.split_header(read_msg_endian(r, self.endian, self.max_message_size)?)
+    }
+
+    // Check that nothing follows the last value of a message.
+    fn check_trailing_bytes(&self, remaining: usize) -> Result<()> {
+        if self.reject_trailing_bytes && remaining > 0 {
+            return Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!("bincode message has {} trailing bytes", remaining),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Evaluate `$body` with `$options` bound to the bincode options
+// selected by `$config`.
+macro_rules! with_options {
+    ($config:expr, |$options:ident| $body:expr) => {{
+        use bincode::Options;
+        let options = bincode::DefaultOptions::new();
+        match ($config.varint, $config.endian) {
+            (false, Endian::Little) => {
+                let $options = options.with_fixint_encoding().with_little_endian();
+                $body
+            }
+            (false, Endian::Big) => {
+                let $options = options.with_fixint_encoding().with_big_endian();
+                $body
+            }
+            (true, Endian::Little) => {
+                let $options = options.with_varint_encoding().with_little_endian();
+                $body
+            }
+            (true, Endian::Big) => {
+                let $options = options.with_varint_encoding().with_big_endian();
+                $body
+            }
+        }
+    }};
+}
+
+fn serialize(config: &Config, w: impl Write, value: impl Serialize) -> Result<()> {
+    with_options!(config, |options| options.serialize_into(w, &value)).map_err(|e| {
         RPCError::with_cause(
             RPCErrorKind::SerializationError,
             "bincode serialization failure",
@@ -18,18 +173,32 @@ fn serialize(w: impl Write, value: impl Serialize) -> Result<()> {
     })
 }
 
-fn deserialize<T>(r: impl Read) -> Result<T>
+fn deserialize<T>(config: &Config, r: impl Read) -> Result<T>
 where
     for<'de> T: Deserialize<'de>,
 {
-    bincode::deserialize_from(r).map_err(|e| {
-        if let bincode::ErrorKind::Io(e) = e.as_ref() {
-            if e.kind() == io::ErrorKind::UnexpectedEof {
+    // The limit stops a length within the message, such as that of a
+    // string or of a nested collection, from claiming more memory
+    // than a message may hold.
+    let limit = config.max_message_size as u64;
+    with_options!(config, |options| options
+        .with_limit(limit)
+        .deserialize_from(r))
+    .map_err(|e| {
+        match e.as_ref() {
+            bincode::ErrorKind::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
                 return RPCError::new(
                     RPCErrorKind::TransportEOF,
                     "EOF during bincode deserialization",
                 );
             }
+            bincode::ErrorKind::SizeLimit => {
+                return RPCError::new(
+                    RPCErrorKind::MessageTooLarge,
+                    "bincode value exceeds maximum message size",
+                );
+            }
+            _ => {}
         }
         RPCError::with_cause(
             RPCErrorKind::SerializationError,
@@ -39,29 +208,194 @@ where
     })
 }
 
-fn read_msg_len(mut r: impl Read) -> Result<usize> {
-    let mut msg_len_bytes = [0u8; 4];
-    r.read_exact(&mut msg_len_bytes)?;
-    Ok(u32::from_le_bytes(msg_len_bytes) as usize)
+// Split the request id from the start of a message.
+fn split_request_id(config: &Config, mut msg: Vec<u8>) -> Result<(u32, Vec<u8>)> {
+    if msg.len() < 4 {
+        return Err(RPCError::new(
+            RPCErrorKind::SerializationError,
+            "bincode message too short for request id",
+        ));
+    }
+    let id = config
+        .endian
+        .u32_from_bytes([msg[0], msg[1], msg[2], msg[3]]);
+    msg.drain(..4);
+    Ok((id, msg))
 }
 
-fn write_msg_len(mut w: impl Write, len: usize) -> Result<()> {
-    w.write_all(&(len as u32).to_le_bytes())?;
+// Begin a call, with an empty metadata header if metadata is enabled.
+fn begin_call(config: &Config, method: MethodId) -> Result<Vec<u8>> {
+    let mut state = Vec::new();
+    if config.metadata {
+        serialize(config, &mut state, Metadata::new())?;
+    }
+    serialize(config, &mut state, method.num)?;
+    Ok(state)
+}
+
+// Replace the empty metadata header written by `begin_call`.
+fn set_metadata(config: &Config, metadata: &Metadata, state: &mut Vec<u8>) -> Result<()> {
+    if !config.metadata {
+        return metadata_unsupported(metadata);
+    }
+    let mut empty = Vec::new();
+    serialize(config, &mut empty, Metadata::new())?;
+    let mut header = Vec::new();
+    serialize(config, &mut header, metadata)?;
+    state.splice(..empty.len(), header);
     Ok(())
 }
 
+// Read a response, preceded by its metadata if enabled.
+fn read_response<T>(config: &Config, metadata: &mut Metadata, mut msg: &[u8]) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    if config.metadata {
+        *metadata = deserialize(config, &mut msg)?;
+    }
+    let value = deserialize(config, &mut msg)?;
+    config.check_trailing_bytes(msg.len())?;
+    Ok(value)
+}
+
+// Encode a response, preceded by its metadata if enabled.
+fn encode_response(
+    config: &Config,
+    metadata: Option<Metadata>,
+    value: impl Serialize,
+) -> Result<Vec<u8>> {
+    let mut msg: Vec<u8> = Vec::new();
+    if let Some(metadata) = metadata {
+        serialize(config, &mut msg, metadata)?;
+    }
+    serialize(config, &mut msg, value)?;
+    Ok(msg)
+}
+
 /// Transport implementation using Bincode serialization. Can be used
 /// over any `Read+Write` channel (local socket, internet socket,
 /// pipe, etc). The present implementation is naive with regards to
 /// this channel -- no buffering is performed.
 /// Enable the "bincode_transport" feature to use this.
+///
+/// By default values are encoded as by `bincode::serialize`: integers
+/// have a fixed size and are little-endian, and `usize` is encoded as
+/// a `u64`, so that peers on different architectures interoperate.
+/// The `with_*` methods change the encoding; the client and server
+/// must be configured alike.
+///
+/// # Frame format
+///
+/// Each message is framed as follows, where integers outside the
+/// bincode-encoded values are 4 bytes long and use the byte order
+/// selected with `with_endian` (little-endian by default).
+///
+/// 1. The length of the rest of the message.
+/// 2. If enabled with `with_frame_header`, a 2-byte header: the frame
+///    format version, currently 1, then a byte of flags describing
+///    the sender's configuration: 0x01 for varint encoding, 0x02 for
+///    big-endian, 0x04 for request ids and 0x08 for metadata. A
+///    receiver with a frame header enabled rejects messages with
+///    another version or flags which differ from its own. Later
+///    versions of the format will only be used when a header is
+///    enabled.
+/// 3. If enabled with `with_request_ids`, a request id, which the
+///    server repeats in the response. Request ids allow a client to
+///    have several calls in progress (see
+///    `ClientTransport::tx_finalize`), answered in any order by a
+///    [ConcurrentServer](../server/struct.ConcurrentServer.html).
+/// 4. If enabled with `with_metadata`, the call's or response's
+///    [Metadata](../struct.Metadata.html) as a bincode-encoded map.
+/// 5. For a call, the method number as a bincode-encoded `u32`
+///    followed by each parameter. For a response, the return value.
+///
+/// By default, messages are the same as those of earlier releases.
+/// A message longer than the maximum set with `with_max_message_size`
+/// is discarded.
 pub struct BincodeTransport<C: Read + Write> {
     channel: C,
+    config: Config,
+    // Metadata of the call being served or the response most
+    // recently received, and of the next response to transmit.
+    rx_metadata: Metadata,
+    tx_metadata: Metadata,
+    // Client: id of the next request, ids of requests whose response
+    // has not been read, and responses received for requests other
+    // than the one being waited for.
+    next_id: u32,
+    outstanding: HashSet<u32>,
+    received: HashMap<u32, Vec<u8>>,
+    // Server: id of the call being served, and the channel used to
+    // respond to concurrent calls.
+    current_id: u32,
+    writer: Option<Arc<SyncMutex<C>>>,
 }
 
 impl<C: Read + Write> BincodeTransport<C> {
     pub fn new(channel: C) -> Self {
-        BincodeTransport { channel }
+        BincodeTransport {
+            channel,
+            config: Config::default(),
+            rx_metadata: Metadata::new(),
+            tx_metadata: Metadata::new(),
+            next_id: 0,
+            outstanding: HashSet::new(),
+            received: HashMap::new(),
+            current_id: 0,
+            writer: None,
+        }
+    }
+
+    /// Tag each message with a request id. A client discards
+    /// responses to requests it is not waiting for.
+    pub fn with_request_ids(mut self) -> Self {
+        self.config.request_ids = true;
+        self
+    }
+
+    /// Carry metadata with each call and response.
+    pub fn with_metadata(mut self) -> Self {
+        self.config.metadata = true;
+        self
+    }
+
+    /// Encode integers with bincode's variable-length encoding rather
+    /// than at a fixed size. Small values, including lengths and enum
+    /// tags, take less space.
+    pub fn with_varint_encoding(mut self) -> Self {
+        self.config.varint = true;
+        self
+    }
+
+    /// Use the given byte order for encoded values and for the
+    /// integers framing each message.
+    pub fn with_endian(mut self, endian: Endian) -> Self {
+        self.config.endian = endian;
+        self
+    }
+
+    /// Reject a call or response holding bytes beyond its last value,
+    /// as sent by a peer whose trait has more parameters or a
+    /// different return type. A rejected call is not executed.
+    pub fn with_trailing_bytes_rejected(mut self) -> Self {
+        self.config.reject_trailing_bytes = true;
+        self
+    }
+
+    /// Begin each message with a header holding the frame format
+    /// version and the configuration of the sender, which the
+    /// receiver checks against its own.
+    pub fn with_frame_header(mut self) -> Self {
+        self.config.frame_header = true;
+        self
+    }
+
+    /// Set the maximum size of a message, in bytes. See
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.config.max_message_size = max;
+        self
     }
 
     /// Get the underlying read/write channel
@@ -69,25 +403,45 @@ impl<C: Read + Write> BincodeTransport<C> {
         &self.channel
     }
 
-    fn flush(&mut self) -> Result<()> {
-        self.channel.flush().map_err(|e| {
-            RPCError::with_cause(
-                RPCErrorKind::SerializationError,
-                "cannot flush underlying channel",
-                e,
-            )
-        })
+    // Read a call, returning its request id (if enabled), metadata
+    // and message.
+    fn read_call(&mut self) -> Result<(Option<u32>, Metadata, u32, VecReader)> {
+        let msg = self.config.read_msg(&mut self.channel)?;
+        let (id, msg) = if self.config.request_ids {
+            let (id, msg) = split_request_id(&self.config, msg)?;
+            (Some(id), msg)
+        } else {
+            (None, msg)
+        };
+        let mut reader = VecReader::new(msg);
+        let metadata = if self.config.metadata {
+            deserialize(&self.config, &mut reader)?
+        } else {
+            Metadata::new()
+        };
+        let method_id: u32 = deserialize(&self.config, &mut reader)?;
+        Ok((id, metadata, method_id, reader))
+    }
+
+    fn response_metadata(&mut self) -> Option<Metadata> {
+        if self.config.metadata {
+            Some(std::mem::take(&mut self.tx_metadata))
+        } else {
+            None
+        }
     }
 }
 
 impl<C: Read + Write> ClientTransport for BincodeTransport<C> {
     type TXState = Vec<u8>;
-    type FinalState = ();
+    type FinalState = u32;
 
     fn tx_begin_call(&mut self, method: MethodId) -> Result<Vec<u8>> {
-        let mut state = Vec::new();
-        serialize(&mut state, method.num)?;
-        Ok(state)
+        begin_call(&self.config, method)
+    }
+
+    fn tx_set_metadata(&mut self, metadata: &Metadata, state: &mut Vec<u8>) -> Result<()> {
+        set_metadata(&self.config, metadata, state)
     }
 
     fn tx_add_param(
@@ -96,25 +450,56 @@ impl<C: Read + Write> ClientTransport for BincodeTransport<C> {
         value: impl Serialize,
         state: &mut Vec<u8>,
     ) -> Result<()> {
-        serialize(state, value)
+        serialize(&self.config, state, value)
     }
 
-    fn tx_finalize(&mut self, state: Vec<u8>) -> Result<()> {
-        write_msg_len(&mut self.channel, state.len())?;
-        self.channel.write_all(&state)?;
-        self.flush()?;
-        Ok(())
+    fn tx_finalize(&mut self, state: Vec<u8>) -> Result<u32> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let tag = if self.config.request_ids {
+            Some(id)
+        } else {
+            None
+        };
+        self.config.write_msg(&mut self.channel, tag, state)?;
+        if self.config.request_ids {
+            self.outstanding.insert(id);
+        }
+        Ok(id)
     }
 
-    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    fn rx_response<T>(&mut self, id: u32) -> Result<T>
     where
         for<'de> T: Deserialize<'de>,
     {
-        let msg_len = read_msg_len(&mut self.channel)?;
-        let mut buffer = Vec::new();
-        buffer.resize(msg_len, 0);
-        self.channel.read_exact(buffer.as_mut_slice())?;
-        deserialize(buffer.as_slice())
+        let msg = if !self.config.request_ids {
+            self.config.read_msg(&mut self.channel)?
+        } else {
+            // Once this call returns, the response is no longer
+            // awaited, even if reading it fails.
+            self.outstanding.remove(&id);
+            match self.received.remove(&id) {
+                Some(msg) => msg,
+                None => loop {
+                    let msg = self.config.read_msg(&mut self.channel)?;
+                    let (rx_id, msg) = split_request_id(&self.config, msg)?;
+                    if rx_id == id {
+                        break msg;
+                    }
+                    // Responses to requests which are not outstanding
+                    // (unknown, abandoned or already answered) are
+                    // dropped.
+                    if self.outstanding.contains(&rx_id) {
+                        self.received.entry(rx_id).or_insert(msg);
+                    }
+                },
+            }
+        };
+        read_response(&self.config, &mut self.rx_metadata, &msg)
+    }
+
+    fn rx_response_metadata(&mut self) -> Metadata {
+        std::mem::take(&mut self.rx_metadata)
     }
 }
 
@@ -126,6 +511,10 @@ impl VecReader {
     fn new(v: Vec<u8>) -> Self {
         VecReader { v, pos: 0 }
     }
+
+    fn remaining(&self) -> usize {
+        self.v.len() - self.pos
+    }
 }
 impl std::io::Read for VecReader {
     fn read(&mut self, mut buf: &mut [u8]) -> std::io::Result<usize> {
@@ -147,30 +536,155 @@ impl<C: Read + Write> ServerTransport for BincodeTransport<C> {
     type RXState = VecReader;
 
     fn rx_begin_call(&mut self) -> Result<(PartialMethodId, Self::RXState)> {
-        let msg_len = read_msg_len(&mut self.channel)?;
-        let mut buffer = Vec::new();
-        buffer.resize(msg_len, 0);
-        self.channel.read_exact(buffer.as_mut_slice())?;
-        let mut reader = VecReader::new(buffer);
-        let method_id: u32 = deserialize(&mut reader)?;
+        let (id, metadata, method_id, reader) = self.read_call()?;
+        self.current_id = id.unwrap_or(0);
+        self.rx_metadata = metadata;
         Ok((PartialMethodId::Num(method_id), reader))
     }
 
+    fn rx_metadata(&mut self, _state: &mut VecReader) -> Result<Metadata> {
+        Ok(std::mem::take(&mut self.rx_metadata))
+    }
+
     fn rx_read_param<T>(&mut self, _name: &'static str, state: &mut Self::RXState) -> Result<T>
     where
         for<'de> T: serde::Deserialize<'de>,
     {
-        deserialize(state)
+        deserialize(&self.config, state)
+    }
+
+    fn rx_end_call(&mut self, state: &mut VecReader) -> Result<()> {
+        self.config.check_trailing_bytes(state.remaining())
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.tx_metadata = metadata.clone();
+        Ok(())
     }
 
     fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
-        let mut msg: Vec<u8> = Vec::new();
-        serialize(&mut msg, value)?;
-        write_msg_len(&mut self.channel, msg.len())?;
-        self.channel.write_all(&msg)?;
-        self.flush()?;
+        self.tx_response_sized(value)?;
         Ok(())
     }
+
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        let metadata = self.response_metadata();
+        let msg = encode_response(&self.config, metadata, value)?;
+        let size = msg.len();
+        let tag = if self.config.request_ids {
+            Some(self.current_id)
+        } else {
+            None
+        };
+        self.config.write_msg(&mut self.channel, tag, msg)?;
+        Ok(Some(size))
+    }
+}
+
+/// A single call read by a BincodeTransport used with a
+/// [ConcurrentServer](../server/struct.ConcurrentServer.html).
+pub struct BincodeCall<C: Read + Write> {
+    id: Option<u32>,
+    call: Option<(u32, VecReader)>,
+    // Request metadata, and response metadata if enabled.
+    rx_metadata: Metadata,
+    tx_metadata: Option<Metadata>,
+    config: Config,
+    writer: Arc<SyncMutex<C>>,
+}
+
+impl<C: Read + Write> ServerTransport for BincodeCall<C> {
+    type RXState = VecReader;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, VecReader)> {
+        let (method_id, reader) = self.call.take().ok_or_else(|| {
+            RPCError::new(RPCErrorKind::IllegalState, "bincode call has already begun")
+        })?;
+        Ok((PartialMethodId::Num(method_id), reader))
+    }
+
+    fn rx_metadata(&mut self, _state: &mut VecReader) -> Result<Metadata> {
+        Ok(std::mem::take(&mut self.rx_metadata))
+    }
+
+    fn rx_read_param<T>(&mut self, _name: &'static str, state: &mut VecReader) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        deserialize(&self.config, state)
+    }
+
+    fn rx_end_call(&mut self, state: &mut VecReader) -> Result<()> {
+        self.config.check_trailing_bytes(state.remaining())
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        if let Some(tx_metadata) = &mut self.tx_metadata {
+            *tx_metadata = metadata.clone();
+        }
+        Ok(())
+    }
+
+    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+        self.tx_response_sized(value)?;
+        Ok(())
+    }
+
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        let msg = encode_response(&self.config, self.tx_metadata.take(), value)?;
+        let size = msg.len();
+        self.config
+            .write_msg(&mut *self.writer.lock(), self.id, msg)?;
+        Ok(Some(size))
+    }
+}
+
+impl<C> ConcurrentServerTransport for BincodeTransport<C>
+where
+    C: Read + Write + DuplicateChannel + Send + 'static,
+{
+    type Call = BincodeCall<C>;
+
+    fn rx_next_call(&mut self) -> Result<BincodeCall<C>> {
+        let writer = match &self.writer {
+            Some(writer) => writer.clone(),
+            None => {
+                let writer = Arc::new(SyncMutex::new(self.channel.duplicate()?));
+                self.writer = Some(writer.clone());
+                writer
+            }
+        };
+        let (id, metadata, method_id, reader) = self.read_call()?;
+        Ok(BincodeCall {
+            id,
+            call: Some((method_id, reader)),
+            rx_metadata: metadata,
+            tx_metadata: if self.config.metadata {
+                Some(Metadata::new())
+            } else {
+                None
+            },
+            config: self.config,
+            writer,
+        })
+    }
+}
+
+/// [WireFormat](trait.WireFormat.html) using bincode, for use with
+/// [SerdeTransport](struct.SerdeTransport.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeFormat;
+
+impl WireFormat for BincodeFormat {
+    fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        serialize(&Config::default(), &mut buf, value)?;
+        Ok(buf)
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        deserialize(&Config::default(), bytes)
+    }
 }
 
 #[cfg(feature = "async_client")]
@@ -179,28 +693,84 @@ mod async_client {
     use crate::AsyncClientTransport;
     use futures::{SinkExt, StreamExt};
     use tokio::io::{AsyncRead, AsyncWrite};
-    use tokio_util::codec::Framed;
+    use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+    use crate::transports::{check_message_size, framing};
 
     /// Like BincodeTransport except for use as
     /// AsyncClientTransport.Can be used over any `AsyncRead+AsyncWrite+Send` channel
     /// (local socket, internet socket, pipe, etc).
     pub struct BincodeAsyncClientTransport<C: AsyncRead + AsyncWrite + Send> {
         channel: Framed<C, LengthDelimitedCodec>,
+        config: Config,
+        rx_metadata: Metadata,
+        next_id: u32,
     }
 
     impl<C: AsyncRead + AsyncWrite + Send> BincodeAsyncClientTransport<C> {
         /// Create an AsyncBincodeTransport.
         pub fn new(channel: C) -> Self {
             BincodeAsyncClientTransport {
-                channel: Framed::new(
-                    channel,
-                    LengthDelimitedCodec::builder()
-                        .little_endian()
-                        .max_frame_length(usize::MAX)
-                        .new_codec(),
-                ),
+                channel: Framed::new(channel, framing::codec(DEFAULT_MAX_MESSAGE_SIZE)),
+                config: Config::default(),
+                rx_metadata: Metadata::new(),
+                next_id: 0,
             }
         }
+
+        /// Tag each message with a request id, for a server which
+        /// expects them. See `BincodeTransport::with_request_ids`.
+        /// Calls are made one at a time, so a response to any other
+        /// request (such as a call whose future was dropped) is
+        /// discarded.
+        pub fn with_request_ids(mut self) -> Self {
+            self.config.request_ids = true;
+            self
+        }
+
+        /// Carry metadata with each call and response. See
+        /// `BincodeTransport::with_metadata`.
+        pub fn with_metadata(mut self) -> Self {
+            self.config.metadata = true;
+            self
+        }
+
+        /// See `BincodeTransport::with_varint_encoding`.
+        pub fn with_varint_encoding(mut self) -> Self {
+            self.config.varint = true;
+            self
+        }
+
+        /// See `BincodeTransport::with_endian`.
+        pub fn with_endian(mut self, endian: Endian) -> Self {
+            self.config.endian = endian;
+            self.update_codec();
+            self
+        }
+
+        /// See `BincodeTransport::with_trailing_bytes_rejected`.
+        pub fn with_trailing_bytes_rejected(mut self) -> Self {
+            self.config.reject_trailing_bytes = true;
+            self
+        }
+
+        /// See `BincodeTransport::with_frame_header`.
+        pub fn with_frame_header(mut self) -> Self {
+            self.config.frame_header = true;
+            self
+        }
+
+        /// See `BincodeTransport::with_max_message_size`.
+        pub fn with_max_message_size(mut self, max: usize) -> Self {
+            self.config.max_message_size = max;
+            self.update_codec();
+            self
+        }
+
+        fn update_codec(&mut self) {
+            *self.channel.codec_mut() =
+                framing::codec_endian(self.config.endian, self.config.max_message_size);
+        }
     }
 
     #[async_trait]
@@ -208,12 +778,18 @@ mod async_client {
         for BincodeAsyncClientTransport<C>
     {
         type TXState = Vec<u8>;
-        type FinalState = ();
+        type FinalState = u32;
 
         async fn tx_begin_call(&mut self, method: MethodId) -> Result<Self::TXState> {
-            let mut state = Vec::new();
-            serialize(&mut state, method.num)?;
-            Ok(state)
+            begin_call(&self.config, method)
+        }
+
+        async fn tx_set_metadata(
+            &mut self,
+            metadata: &Metadata,
+            state: &mut Self::TXState,
+        ) -> Result<()> {
+            set_metadata(&self.config, metadata, state)
         }
 
         async fn tx_add_param(
@@ -222,25 +798,47 @@ mod async_client {
             value: impl Serialize + Send + 'async_trait,
             state: &mut Self::TXState,
         ) -> Result<()> {
-            serialize(state, value)
+            serialize(&self.config, state, value)
         }
 
-        async fn tx_finalize(&mut self, state: Self::TXState) -> Result<()> {
-            self.channel.send(state.into()).await?;
-            Ok(())
+        async fn tx_finalize(&mut self, state: Self::TXState) -> Result<u32> {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1);
+            let mut msg = self.config.header();
+            if self.config.request_ids {
+                msg.extend_from_slice(&self.config.endian.u32_to_bytes(id));
+            }
+            msg.extend(state);
+            check_message_size(msg.len(), self.config.max_message_size)?;
+            self.channel.send(msg.into()).await?;
+            Ok(id)
         }
 
-        async fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+        async fn rx_response<T>(&mut self, id: u32) -> Result<T>
         where
             for<'de> T: Deserialize<'de>,
         {
-            let msg = self.channel.next().await.unwrap_or_else(|| {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::UnexpectedEof,
-                    "Could not rx response, unexpcted EOF",
-                ))
-            })?;
-            deserialize(&*msg)
+            let msg = loop {
+                let msg = self.channel.next().await.unwrap_or_else(|| {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Could not rx response, unexpcted EOF",
+                    ))
+                })?;
+                let msg = self.config.split_header(msg.to_vec())?;
+                if !self.config.request_ids {
+                    break msg;
+                }
+                let (rx_id, msg) = split_request_id(&self.config, msg)?;
+                if rx_id == id {
+                    break msg;
+                }
+            };
+            read_response(&self.config, &mut self.rx_metadata, &msg)
+        }
+
+        async fn rx_response_metadata(&mut self) -> Metadata {
+            std::mem::take(&mut self.rx_metadata)
         }
     }
 }
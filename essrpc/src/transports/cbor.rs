@@ -0,0 +1,484 @@
+use super::WireFormat;
+use ciborium::value::Value;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::io::{Read, Write};
+
+use super::{check_message_size, Recorder, DEFAULT_MAX_MESSAGE_SIZE};
+use crate::{
+    ClientTransport, Metadata, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result,
+    ServerTransport,
+};
+
+/// A call being sent by a [CBORTransport](struct.CBORTransport.html).
+pub struct CBORTXState {
+    method: &'static str,
+    params: Vec<(Value, Value)>,
+    metadata: Metadata,
+}
+
+/// A call being served by a [CBORTransport](struct.CBORTransport.html).
+pub struct CBORRXState {
+    params: Vec<(Value, Value)>,
+    metadata: Option<Value>,
+}
+
+/// Transport implementation using [CBOR](https://cbor.io)
+/// serialization. Can be used over any `Read+Write` channel (local
+/// socket, internet socket, pipe, etc). Enable the "cbor_transport"
+/// feature to use this.
+///
+/// The channel carries a CBOR sequence (RFC 8742): each message is a
+/// single CBOR data item, which delimits itself. A call is a map with
+/// the members `method` (the method name), `params` (a map from
+/// parameter name to value) and, if the call has any, `metadata`. The
+/// response is the bare return value, so response metadata is
+/// discarded.
+///
+/// A message longer than the maximum set with `with_max_message_size`
+/// is read to its end and discarded.
+pub struct CBORTransport<C: Read + Write> {
+    channel: C,
+    max_message_size: usize,
+}
+
+impl<C: Read + Write> CBORTransport<C> {
+    pub fn new(channel: C) -> Self {
+        CBORTransport {
+            channel,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Set the maximum size of a message, in bytes. See
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = max;
+        self
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        &self.channel
+    }
+}
+
+fn convert_error(e: impl std::error::Error) -> RPCError {
+    RPCError::with_cause(
+        RPCErrorKind::SerializationError,
+        "cbor serialization or deserialization failed",
+        e,
+    )
+}
+
+fn to_value(value: impl Serialize) -> Result<Value> {
+    Value::serialized(&value).map_err(convert_error)
+}
+
+fn from_value<T>(value: &Value) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    value.deserialized().map_err(convert_error)
+}
+
+fn begin_call(method: MethodId) -> CBORTXState {
+    CBORTXState {
+        method: method.name,
+        params: Vec::new(),
+        metadata: Metadata::new(),
+    }
+}
+
+fn add_param(name: &'static str, value: impl Serialize, state: &mut CBORTXState) -> Result<()> {
+    state.params.push((Value::from(name), to_value(value)?));
+    Ok(())
+}
+
+fn value_for_state(state: CBORTXState) -> Result<Value> {
+    let mut call = vec![
+        (Value::from("method"), Value::from(state.method)),
+        (Value::from("params"), Value::Map(state.params)),
+    ];
+    if !state.metadata.is_empty() {
+        call.push((Value::from("metadata"), to_value(&state.metadata)?));
+    }
+    Ok(Value::Map(call))
+}
+
+fn encode(value: &impl Serialize) -> Result<Vec<u8>> {
+    let mut msg = Vec::new();
+    ciborium::ser::into_writer(value, &mut msg).map_err(convert_error)?;
+    Ok(msg)
+}
+
+// Write a value as a message, returning its encoded size.
+fn write_value(mut w: impl Write, value: &impl Serialize, max: usize) -> Result<usize> {
+    // Encode first to write the item at once rather than piecemeal
+    let msg = encode(value)?;
+    check_message_size(msg.len(), max)?;
+    w.write_all(&msg)?;
+    w.flush().map_err(|e| {
+        RPCError::with_cause(
+            RPCErrorKind::SerializationError,
+            "cannot flush underlying channel",
+            e,
+        )
+    })?;
+    Ok(msg.len())
+}
+
+// Deepest nesting of arrays, maps, tags and indefinite-length strings
+// accepted when scanning a data item.
+const MAX_DEPTH: usize = 256;
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed cbor data item")
+}
+
+// Read the head of a data item: its major type and argument, which is
+// `None` for an indefinite length or a break.
+fn read_head(mut r: impl Read) -> io::Result<(u8, Option<u64>)> {
+    let mut initial = [0u8; 1];
+    r.read_exact(&mut initial)?;
+    let (major, info) = (initial[0] >> 5, initial[0] & 0x1f);
+    let len = match info {
+        0..=23 => return Ok((major, Some(u64::from(info)))),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        31 => return Ok((major, None)),
+        _ => return Err(malformed()),
+    };
+    let mut arg = [0u8; 8];
+    r.read_exact(&mut arg[8 - len..])?;
+    Ok((major, Some(u64::from_be_bytes(arg))))
+}
+
+// Read a single data item without decoding it.
+fn skip_item(mut r: impl Read) -> io::Result<()> {
+    // Items remaining at each level of nesting, or `None` for an
+    // indefinite length ended by a break.
+    let mut pending = vec![Some(1u64)];
+    while let Some(remaining) = pending.last_mut() {
+        match remaining {
+            Some(0) => {
+                pending.pop();
+                continue;
+            }
+            Some(n) => *n -= 1,
+            None => {}
+        }
+        let nested = match read_head(&mut r)? {
+            (0 | 1 | 7, Some(_)) => None,
+            (2 | 3, Some(len)) => {
+                if io::copy(&mut (&mut r).take(len), &mut io::sink())? < len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                None
+            }
+            (4, Some(len)) => Some(Some(len)),
+            (5, Some(len)) => Some(Some(len.checked_mul(2).ok_or_else(malformed)?)),
+            (6, Some(_)) => Some(Some(1)),
+            (2..=5, None) => Some(None),
+            // A break, which must end an indefinite length
+            (7, None) => {
+                if pending.pop() != Some(None) {
+                    return Err(malformed());
+                }
+                None
+            }
+            _ => return Err(malformed()),
+        };
+        if let Some(len) = nested {
+            if pending.len() > MAX_DEPTH {
+                return Err(malformed());
+            }
+            pending.push(len);
+        }
+    }
+    Ok(())
+}
+
+// Read the bytes of a single data item of the sequence. The item is
+// scanned without being decoded, so one longer than `max` bytes is
+// read to its end and discarded.
+fn read_item(reader: impl Read, max: usize) -> Result<Vec<u8>> {
+    let mut recorder = Recorder::new(reader, max);
+    skip_item(&mut recorder).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => RPCError::new(
+            RPCErrorKind::TransportEOF,
+            "EOF during cbor deserialization",
+        ),
+        io::ErrorKind::InvalidData => convert_error(e),
+        _ => e.into(),
+    })?;
+    recorder.into_message()
+}
+
+// Decode a single data item.
+fn read_value<T, R>(reader: R) -> Result<T>
+where
+    for<'de> T: serde::Deserialize<'de>,
+    R: Read,
+{
+    ciborium::de::from_reader(reader).map_err(|e| match e {
+        ciborium::de::Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => RPCError::new(
+            RPCErrorKind::TransportEOF,
+            "EOF during cbor deserialization",
+        ),
+        e => convert_error(e),
+    })
+}
+
+fn take_member(call: &mut Vec<(Value, Value)>, name: &str) -> Option<Value> {
+    let i = call.iter().position(|(k, _)| k.as_text() == Some(name))?;
+    Some(call.swap_remove(i).1)
+}
+
+fn read_call(value: Value) -> Result<(PartialMethodId, CBORRXState)> {
+    let mut call = value
+        .into_map()
+        .map_err(|_| RPCError::new(RPCErrorKind::SerializationError, "cbor call is not a map"))?;
+    let method = take_member(&mut call, "method")
+        .and_then(|method| method.into_text().ok())
+        .ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::SerializationError,
+                "cbor call has no method name",
+            )
+        })?;
+    let params = match take_member(&mut call, "params") {
+        Some(params) => params.into_map().map_err(|_| {
+            RPCError::new(
+                RPCErrorKind::SerializationError,
+                "cbor call params are not a map",
+            )
+        })?,
+        None => Vec::new(),
+    };
+    let metadata = take_member(&mut call, "metadata");
+    Ok((
+        PartialMethodId::Name(method),
+        CBORRXState { params, metadata },
+    ))
+}
+
+impl<C: Read + Write> ClientTransport for CBORTransport<C> {
+    type TXState = CBORTXState;
+    type FinalState = ();
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<CBORTXState> {
+        Ok(begin_call(method))
+    }
+
+    fn tx_set_metadata(&mut self, metadata: &Metadata, state: &mut CBORTXState) -> Result<()> {
+        state.metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut CBORTXState,
+    ) -> Result<()> {
+        add_param(name, value, state)
+    }
+
+    fn tx_finalize(&mut self, state: CBORTXState) -> Result<()> {
+        let call = value_for_state(state)?;
+        write_value(&mut self.channel, &call, self.max_message_size)?;
+        Ok(())
+    }
+
+    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        read_value(&*read_item(&mut self.channel, self.max_message_size)?)
+    }
+}
+
+impl<C: Read + Write> ServerTransport for CBORTransport<C> {
+    type RXState = CBORRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, CBORRXState)> {
+        let msg = read_item(&mut self.channel, self.max_message_size)?;
+        read_call(read_value(&*msg)?)
+    }
+
+    fn rx_metadata(&mut self, state: &mut CBORRXState) -> Result<Metadata> {
+        match &state.metadata {
+            Some(metadata) => from_value(metadata),
+            None => Ok(Metadata::new()),
+        }
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut CBORRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        let value = take_member(&mut state.params, name).ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!("parameters do not contain {}", name),
+            )
+        })?;
+        from_value(&value)
+    }
+
+    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+        self.tx_response_sized(value)?;
+        Ok(())
+    }
+
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        write_value(&mut self.channel, &value, self.max_message_size).map(Some)
+    }
+}
+
+/// [WireFormat](trait.WireFormat.html) using CBOR, for use with
+/// [SerdeTransport](struct.SerdeTransport.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CBORFormat;
+
+impl WireFormat for CBORFormat {
+    fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        encode(&value)
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        read_value(bytes)
+    }
+}
+
+#[cfg(feature = "async_client")]
+mod async_client {
+    use super::*;
+    use crate::transports::invalid_data;
+    use crate::AsyncClientTransport;
+    use async_trait::async_trait;
+    use bytes::{BufMut, Bytes, BytesMut};
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio_util::codec::Framed;
+
+    /// Like CBORTransport except for use as AsyncClientTransport. Can
+    /// be used over any `AsyncRead+AsyncWrite+Send` channel.
+    pub struct CBORAsyncClientTransport<C: AsyncRead + AsyncWrite + Send> {
+        channel: Framed<C, CBORCodec>,
+    }
+
+    impl<C: AsyncRead + AsyncWrite + Send> CBORAsyncClientTransport<C> {
+        pub fn new(channel: C) -> Self {
+            CBORAsyncClientTransport {
+                channel: Framed::new(
+                    channel,
+                    CBORCodec {
+                        max: DEFAULT_MAX_MESSAGE_SIZE,
+                    },
+                ),
+            }
+        }
+
+        /// Set the maximum size of a message, in bytes. See
+        /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+        pub fn with_max_message_size(mut self, max: usize) -> Self {
+            self.channel.codec_mut().max = max;
+            self
+        }
+    }
+
+    #[async_trait]
+    impl<C: AsyncRead + AsyncWrite + Send + Unpin> AsyncClientTransport
+        for CBORAsyncClientTransport<C>
+    {
+        type TXState = CBORTXState;
+        type FinalState = ();
+
+        async fn tx_begin_call(&mut self, method: MethodId) -> Result<CBORTXState> {
+            Ok(begin_call(method))
+        }
+
+        async fn tx_set_metadata(
+            &mut self,
+            metadata: &Metadata,
+            state: &mut CBORTXState,
+        ) -> Result<()> {
+            state.metadata = metadata.clone();
+            Ok(())
+        }
+
+        async fn tx_add_param(
+            &mut self,
+            name: &'static str,
+            value: impl Serialize + Send + 'async_trait,
+            state: &mut CBORTXState,
+        ) -> Result<()> {
+            add_param(name, value, state)
+        }
+
+        async fn tx_finalize(&mut self, state: CBORTXState) -> Result<()> {
+            let msg = encode(&value_for_state(state)?)?;
+            self.channel.send(msg.into()).await?;
+            Ok(())
+        }
+
+        async fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+        where
+            for<'de> T: Deserialize<'de>,
+        {
+            let msg = self.channel.next().await.unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Could not rx response, unexpected EOF",
+                ))
+            })?;
+            read_value(&*msg)
+        }
+    }
+
+    // Codec splitting a CBOR sequence into its data items.
+    struct CBORCodec {
+        max: usize,
+    }
+
+    impl tokio_util::codec::Encoder<Bytes> for CBORCodec {
+        type Error = io::Error;
+        fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+            check_message_size(item.len(), self.max).map_err(invalid_data)?;
+            dst.put(item);
+            Ok(())
+        }
+    }
+
+    impl tokio_util::codec::Decoder for CBORCodec {
+        type Item = BytesMut;
+        type Error = io::Error;
+        fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+            if src.is_empty() {
+                return Ok(None);
+            }
+            let mut rest: &[u8] = src;
+            match skip_item(&mut rest) {
+                Ok(()) => {
+                    let len = src.len() - rest.len();
+                    check_message_size(len, self.max).map_err(invalid_data)?;
+                    Ok(Some(src.split_to(len)))
+                }
+                // The item is incomplete
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    check_message_size(src.len(), self.max).map_err(invalid_data)?;
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async_client")]
+pub use self::async_client::CBORAsyncClientTransport;
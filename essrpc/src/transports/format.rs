@@ -0,0 +1,452 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io::{Read, Write};
+
+use super::framing::{read_msg, write_msg};
+use super::DEFAULT_MAX_MESSAGE_SIZE;
+use crate::{
+    ClientTransport, Metadata, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result,
+    ServerTransport,
+};
+
+/// A serialization format for use with
+/// [SerdeTransport](struct.SerdeTransport.html). Each value is
+/// serialized to, and deserialized from, a buffer of its own, so a
+/// format need not be able to find the end of a value.
+///
+/// Errors should generally be of kind
+/// `RPCErrorKind::SerializationError`.
+pub trait WireFormat {
+    /// Serialize a value.
+    fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>>;
+    /// Deserialize a value occupying all of `bytes`.
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T>;
+}
+
+fn push_segment(msg: &mut Vec<u8>, segment: &[u8]) {
+    msg.extend_from_slice(&(segment.len() as u32).to_le_bytes());
+    msg.extend_from_slice(segment);
+}
+
+fn truncated() -> RPCError {
+    RPCError::new(
+        RPCErrorKind::SerializationError,
+        "serde transport message is truncated",
+    )
+}
+
+// Segments of a received message.
+struct Segments {
+    msg: Vec<u8>,
+    pos: usize,
+}
+
+impl Segments {
+    fn new(msg: Vec<u8>) -> Self {
+        Segments { msg, pos: 0 }
+    }
+
+    fn next_u32(&mut self) -> Result<u32> {
+        let bytes = self.msg.get(self.pos..self.pos + 4).ok_or_else(truncated)?;
+        self.pos += 4;
+        Ok(u32::from_le_bytes(bytes.try_into()?))
+    }
+
+    fn next_segment(&mut self) -> Result<&[u8]> {
+        let len = self.next_u32()? as usize;
+        let start = self.pos;
+        let segment = self.msg.get(start..start + len).ok_or_else(truncated)?;
+        self.pos += len;
+        Ok(segment)
+    }
+}
+
+/// A call being sent by a [SerdeTransport](struct.SerdeTransport.html).
+pub struct SerdeTXState {
+    method: u32,
+    metadata: Metadata,
+    params: Vec<u8>,
+}
+
+/// A call being served by a [SerdeTransport](struct.SerdeTransport.html).
+pub struct SerdeRXState {
+    segments: Segments,
+}
+
+/// How calls and responses are laid out within the messages of a
+/// [SerdeTransport](struct.SerdeTransport.html), which frames,
+/// transmits and receives them. Every
+/// [WireFormat](trait.WireFormat.html) has the layout described for
+/// `SerdeTransport`. A transport with a layout of its own, such as
+/// [MsgPackTransport](struct.MsgPackTransport.html), implements this
+/// trait and wraps a transport created with
+/// `SerdeTransport::with_layout`.
+pub trait MessageLayout {
+    /// A call being sent.
+    type TXState;
+    /// A call being served.
+    type RXState;
+
+    /// Begin a call of `method`.
+    fn begin_call(&self, method: MethodId) -> Result<Self::TXState>;
+
+    /// Attach metadata to a call.
+    fn set_metadata(&self, metadata: &Metadata, state: &mut Self::TXState) -> Result<()>;
+
+    /// Add a parameter to a call.
+    fn add_param(
+        &self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut Self::TXState,
+    ) -> Result<()>;
+
+    /// Encode a call as a message.
+    fn encode_call(&self, state: Self::TXState) -> Result<Vec<u8>>;
+
+    /// Decode a response message into its metadata and return value.
+    fn decode_response<T>(&self, msg: Vec<u8>) -> Result<(Metadata, T)>
+    where
+        for<'de> T: Deserialize<'de>;
+
+    /// Decode a call message into its method, its metadata, and the
+    /// state from which its parameters are read.
+    fn decode_call(&mut self, msg: Vec<u8>) -> Result<(PartialMethodId, Metadata, Self::RXState)>;
+
+    /// Read the next parameter of a call.
+    fn read_param<T>(&self, name: &'static str, state: &mut Self::RXState) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>;
+
+    /// Encode the response to the call most recently decoded as a
+    /// message.
+    fn encode_response(&self, metadata: &Metadata, value: impl Serialize) -> Result<Vec<u8>>;
+}
+
+impl<F: WireFormat> MessageLayout for F {
+    type TXState = SerdeTXState;
+    type RXState = SerdeRXState;
+
+    fn begin_call(&self, method: MethodId) -> Result<SerdeTXState> {
+        Ok(SerdeTXState {
+            method: method.num,
+            metadata: Metadata::new(),
+            params: Vec::new(),
+        })
+    }
+
+    fn set_metadata(&self, metadata: &Metadata, state: &mut SerdeTXState) -> Result<()> {
+        state.metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn add_param(
+        &self,
+        _name: &'static str,
+        value: impl Serialize,
+        state: &mut SerdeTXState,
+    ) -> Result<()> {
+        push_segment(&mut state.params, &self.serialize(&value)?);
+        Ok(())
+    }
+
+    fn encode_call(&self, state: SerdeTXState) -> Result<Vec<u8>> {
+        let mut msg = state.method.to_le_bytes().to_vec();
+        push_segment(&mut msg, &self.serialize(&state.metadata)?);
+        msg.extend(state.params);
+        Ok(msg)
+    }
+
+    fn decode_response<T>(&self, msg: Vec<u8>) -> Result<(Metadata, T)>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let mut segments = Segments::new(msg);
+        let metadata = self.deserialize(segments.next_segment()?)?;
+        Ok((metadata, self.deserialize(segments.next_segment()?)?))
+    }
+
+    fn decode_call(&mut self, msg: Vec<u8>) -> Result<(PartialMethodId, Metadata, SerdeRXState)> {
+        let mut segments = Segments::new(msg);
+        let method = segments.next_u32()?;
+        let metadata = self.deserialize(segments.next_segment()?)?;
+        Ok((
+            PartialMethodId::Num(method),
+            metadata,
+            SerdeRXState { segments },
+        ))
+    }
+
+    fn read_param<T>(&self, _name: &'static str, state: &mut SerdeRXState) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        self.deserialize(state.segments.next_segment()?)
+    }
+
+    fn encode_response(&self, metadata: &Metadata, value: impl Serialize) -> Result<Vec<u8>> {
+        let mut msg = Vec::new();
+        push_segment(&mut msg, &self.serialize(metadata)?);
+        push_segment(&mut msg, &self.serialize(&value)?);
+        Ok(msg)
+    }
+}
+
+/// Transport implementation over any serde format implementing
+/// [WireFormat](trait.WireFormat.html), such as
+/// [BincodeFormat](struct.BincodeFormat.html) or
+/// [JSONFormat](struct.JSONFormat.html). Can be used over any
+/// `Read+Write` channel.
+///
+/// Messages are framed as for
+/// [BincodeTransport](struct.BincodeTransport.html): each is preceded
+/// by its length as a 4-byte little-endian integer. Within a message,
+/// each serialized value is a segment preceded by its own 4-byte
+/// little-endian length. A call holds the method number as a 4-byte
+/// little-endian integer, then a segment holding its
+/// [Metadata](../struct.Metadata.html), then a segment for each
+/// parameter. A response holds a metadata segment followed by a
+/// segment holding the return value.
+///
+/// Messages may be laid out differently by a
+/// [MessageLayout](trait.MessageLayout.html) given to `with_layout`.
+pub struct SerdeTransport<C: Read + Write, F: MessageLayout> {
+    channel: C,
+    format: F,
+    max_message_size: usize,
+    // Metadata of the call being served or the response most
+    // recently received, and of the next response to transmit.
+    rx_metadata: Metadata,
+    tx_metadata: Metadata,
+}
+
+impl<C: Read + Write, F: WireFormat> SerdeTransport<C, F> {
+    pub fn new(channel: C, format: F) -> Self {
+        Self::with_layout(channel, format)
+    }
+}
+
+impl<C: Read + Write, F: MessageLayout> SerdeTransport<C, F> {
+    /// Create a transport laying out messages with `layout`.
+    pub fn with_layout(channel: C, layout: F) -> Self {
+        SerdeTransport {
+            channel,
+            format: layout,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            rx_metadata: Metadata::new(),
+            tx_metadata: Metadata::new(),
+        }
+    }
+
+    /// Set the maximum size of a message, in bytes. See
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = max;
+        self
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        &self.channel
+    }
+
+    /// Get the format
+    pub fn format(&self) -> &F {
+        &self.format
+    }
+
+    /// Get the format mutably
+    pub fn format_mut(&mut self) -> &mut F {
+        &mut self.format
+    }
+}
+
+impl<C: Read + Write, F: MessageLayout> ClientTransport for SerdeTransport<C, F> {
+    type TXState = F::TXState;
+    type FinalState = ();
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<F::TXState> {
+        self.format.begin_call(method)
+    }
+
+    fn tx_set_metadata(&mut self, metadata: &Metadata, state: &mut F::TXState) -> Result<()> {
+        self.format.set_metadata(metadata, state)
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut F::TXState,
+    ) -> Result<()> {
+        self.format.add_param(name, value, state)
+    }
+
+    fn tx_finalize(&mut self, state: F::TXState) -> Result<()> {
+        let msg = self.format.encode_call(state)?;
+        write_msg(&mut self.channel, &msg, self.max_message_size)
+    }
+
+    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let msg = read_msg(&mut self.channel, self.max_message_size)?;
+        let (metadata, value) = self.format.decode_response(msg)?;
+        self.rx_metadata = metadata;
+        Ok(value)
+    }
+
+    fn rx_response_metadata(&mut self) -> Metadata {
+        std::mem::take(&mut self.rx_metadata)
+    }
+}
+
+impl<C: Read + Write, F: MessageLayout> ServerTransport for SerdeTransport<C, F> {
+    type RXState = F::RXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, F::RXState)> {
+        let msg = read_msg(&mut self.channel, self.max_message_size)?;
+        let (method, metadata, state) = self.format.decode_call(msg)?;
+        self.rx_metadata = metadata;
+        self.tx_metadata = Metadata::new();
+        Ok((method, state))
+    }
+
+    fn rx_metadata(&mut self, _state: &mut F::RXState) -> Result<Metadata> {
+        Ok(std::mem::take(&mut self.rx_metadata))
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut F::RXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        self.format.read_param(name, state)
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.tx_metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+        self.tx_response_sized(value)?;
+        Ok(())
+    }
+
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        let metadata = std::mem::take(&mut self.tx_metadata);
+        let msg = self.format.encode_response(&metadata, value)?;
+        write_msg(&mut self.channel, &msg, self.max_message_size)?;
+        Ok(Some(msg.len()))
+    }
+}
+
+#[cfg(feature = "async_client")]
+mod async_client {
+    use super::*;
+    use crate::transports::framing;
+    use crate::AsyncClientTransport;
+    use async_trait::async_trait;
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+    /// Like SerdeTransport except for use as AsyncClientTransport.
+    /// Can be used over any `AsyncRead+AsyncWrite+Send` channel.
+    pub struct SerdeAsyncClientTransport<C: AsyncRead + AsyncWrite + Send, F: MessageLayout> {
+        channel: Framed<C, LengthDelimitedCodec>,
+        format: F,
+        rx_metadata: Metadata,
+    }
+
+    impl<C: AsyncRead + AsyncWrite + Send, F: WireFormat> SerdeAsyncClientTransport<C, F> {
+        pub fn new(channel: C, format: F) -> Self {
+            Self::with_layout(channel, format)
+        }
+    }
+
+    impl<C: AsyncRead + AsyncWrite + Send, F: MessageLayout> SerdeAsyncClientTransport<C, F> {
+        /// Create a transport laying out messages with `layout`.
+        pub fn with_layout(channel: C, layout: F) -> Self {
+            SerdeAsyncClientTransport {
+                channel: Framed::new(channel, framing::codec(DEFAULT_MAX_MESSAGE_SIZE)),
+                format: layout,
+                rx_metadata: Metadata::new(),
+            }
+        }
+
+        /// Set the maximum size of a message, in bytes. See
+        /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+        pub fn with_max_message_size(mut self, max: usize) -> Self {
+            self.channel.codec_mut().set_max_frame_length(max);
+            self
+        }
+
+        /// Get the format mutably
+        pub fn format_mut(&mut self) -> &mut F {
+            &mut self.format
+        }
+    }
+
+    #[async_trait]
+    impl<C, F> AsyncClientTransport for SerdeAsyncClientTransport<C, F>
+    where
+        C: AsyncRead + AsyncWrite + Send + Unpin,
+        F: MessageLayout + Send,
+        F::TXState: Send,
+    {
+        type TXState = F::TXState;
+        type FinalState = ();
+
+        async fn tx_begin_call(&mut self, method: MethodId) -> Result<F::TXState> {
+            self.format.begin_call(method)
+        }
+
+        async fn tx_set_metadata(
+            &mut self,
+            metadata: &Metadata,
+            state: &mut F::TXState,
+        ) -> Result<()> {
+            self.format.set_metadata(metadata, state)
+        }
+
+        async fn tx_add_param(
+            &mut self,
+            name: &'static str,
+            value: impl Serialize + Send + 'async_trait,
+            state: &mut F::TXState,
+        ) -> Result<()> {
+            self.format.add_param(name, value, state)
+        }
+
+        async fn tx_finalize(&mut self, state: F::TXState) -> Result<()> {
+            let msg = self.format.encode_call(state)?;
+            self.channel.send(msg.into()).await?;
+            Ok(())
+        }
+
+        async fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+        where
+            for<'de> T: Deserialize<'de>,
+        {
+            let msg = self.channel.next().await.unwrap_or_else(|| {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Could not rx response, unexpected EOF",
+                ))
+            })?;
+            let (metadata, value) = self.format.decode_response(msg.to_vec())?;
+            self.rx_metadata = metadata;
+            Ok(value)
+        }
+
+        async fn rx_response_metadata(&mut self) -> Metadata {
+            std::mem::take(&mut self.rx_metadata)
+        }
+    }
+}
+
+#[cfg(feature = "async_client")]
+pub use self::async_client::SerdeAsyncClientTransport;
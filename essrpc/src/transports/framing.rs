@@ -0,0 +1,107 @@
+//! Length-prefixed framing shared by the bincode, MessagePack and
+//! serde transports. Each message is preceded by its length as a 4-byte
+//! integer, little-endian unless the transport is configured otherwise.
+use std::io;
+use std::io::{Read, Write};
+
+use super::{check_message_size, message_too_large};
+use crate::{RPCError, RPCErrorKind, Result};
+
+/// Byte order of the integers framing a message, and of those encoded
+/// by a [BincodeTransport](struct.BincodeTransport.html).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub(crate) fn u32_to_bytes(self, n: u32) -> [u8; 4] {
+        match self {
+            Endian::Little => n.to_le_bytes(),
+            Endian::Big => n.to_be_bytes(),
+        }
+    }
+
+    pub(crate) fn u32_from_bytes(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+fn read_msg_len(mut r: impl Read, endian: Endian) -> Result<usize> {
+    let mut msg_len_bytes = [0u8; 4];
+    r.read_exact(&mut msg_len_bytes)?;
+    Ok(endian.u32_from_bytes(msg_len_bytes) as usize)
+}
+
+fn write_msg_len(mut w: impl Write, len: usize, endian: Endian) -> Result<()> {
+    w.write_all(&endian.u32_to_bytes(len as u32))?;
+    Ok(())
+}
+
+pub(crate) fn read_msg(r: impl Read, max: usize) -> Result<Vec<u8>> {
+    read_msg_endian(r, Endian::Little, max)
+}
+
+/// Read a message of at most `max` bytes. A longer message is read
+/// and discarded, leaving the channel at the start of the next one.
+pub(crate) fn read_msg_endian(mut r: impl Read, endian: Endian, max: usize) -> Result<Vec<u8>> {
+    let msg_len = read_msg_len(&mut r, endian)?;
+    if msg_len > max {
+        let discarded = io::copy(&mut r.take(msg_len as u64), &mut io::sink())?;
+        if discarded < msg_len as u64 {
+            return Err(RPCError::new(
+                RPCErrorKind::TransportEOF,
+                "EOF while discarding oversized message",
+            ));
+        }
+        return Err(message_too_large(msg_len, max));
+    }
+    let mut buffer = vec![0; msg_len];
+    r.read_exact(buffer.as_mut_slice())?;
+    Ok(buffer)
+}
+
+pub(crate) fn write_msg(w: impl Write, msg: &[u8], max: usize) -> Result<()> {
+    write_msg_endian(w, msg, Endian::Little, max)
+}
+
+/// Write a message of at most `max` bytes. A longer message is not
+/// written.
+pub(crate) fn write_msg_endian(
+    mut w: impl Write,
+    msg: &[u8],
+    endian: Endian,
+    max: usize,
+) -> Result<()> {
+    check_message_size(msg.len(), max)?;
+    write_msg_len(&mut w, msg.len(), endian)?;
+    w.write_all(msg)?;
+    w.flush().map_err(|e| {
+        RPCError::with_cause(
+            RPCErrorKind::SerializationError,
+            "cannot flush underlying channel",
+            e,
+        )
+    })
+}
+
+/// Codec for the same framing on an async channel.
+#[cfg(feature = "async_client")]
+pub(crate) fn codec(max: usize) -> tokio_util::codec::LengthDelimitedCodec {
+    codec_endian(Endian::Little, max)
+}
+
+#[cfg(feature = "async_client")]
+pub(crate) fn codec_endian(endian: Endian, max: usize) -> tokio_util::codec::LengthDelimitedCodec {
+    let mut builder = tokio_util::codec::LengthDelimitedCodec::builder();
+    match endian {
+        Endian::Little => builder.little_endian(),
+        Endian::Big => builder.big_endian(),
+    };
+    builder.max_frame_length(max).new_codec()
+}
@@ -0,0 +1,1497 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::value::Value;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use uuid::Uuid;
+
+use super::json::{
+    add_param, begin_call, convert_error, read_metadata, read_param, value_for_state, JRXState,
+    JTXState,
+};
+use super::{check_message_size, DEFAULT_MAX_MESSAGE_SIZE};
+use crate::{
+    ClientTransport, Metadata, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result,
+    ServerTransport,
+};
+
+// Longest request or status line and headers accepted.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+// JSON-RPC 2.0 error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+fn closed() -> RPCError {
+    RPCError::new(RPCErrorKind::TransportEOF, "http connection closed")
+}
+
+fn malformed() -> RPCError {
+    RPCError::new(RPCErrorKind::SerializationError, "malformed http message")
+}
+
+// The request or status line and headers of an HTTP message.
+struct Head {
+    // The method, target and version of a request, or the version,
+    // status and reason of a response.
+    start: [String; 3],
+    // Header names are lowercase.
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    fn parse(bytes: &[u8]) -> Result<Head> {
+        let text = std::str::from_utf8(bytes).map_err(|_| malformed())?;
+        let mut lines = text.split("\r\n");
+        let mut start = lines.next().unwrap_or("").splitn(3, ' ');
+        let mut part = || start.next().unwrap_or("").to_string();
+        let start = [part(), part(), part()];
+        if start[0].is_empty() || start[1].is_empty() {
+            return Err(malformed());
+        }
+        let headers = lines
+            .map(|line| {
+                let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+                Ok((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Head { start, headers })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn version(&self) -> &str {
+        if self.start[0].starts_with("HTTP/") {
+            &self.start[0]
+        } else {
+            &self.start[2]
+        }
+    }
+
+    // The Content-Length, which may be repeated (in several headers or
+    // a list) only with the same value.
+    fn content_length(&self) -> Result<Option<usize>> {
+        let mut len = None;
+        let values = self
+            .headers
+            .iter()
+            .filter(|(n, _)| n == "content-length")
+            .flat_map(|(_, value)| value.split(','));
+        for value in values {
+            let value: usize = value.trim().parse().map_err(|_| malformed())?;
+            if len.is_some_and(|len| len != value) {
+                return Err(malformed());
+            }
+            len = Some(value);
+        }
+        Ok(len)
+    }
+
+    // Whether the connection stays open after this message.
+    fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.header("connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|t| t.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        if self.version() == "HTTP/1.0" {
+            has_token("keep-alive")
+        } else {
+            !has_token("close")
+        }
+    }
+
+    fn expects_continue(&self) -> bool {
+        self.header("expect")
+            .is_some_and(|value| value.eq_ignore_ascii_case("100-continue"))
+    }
+
+    fn status(&self) -> Result<u16> {
+        self.start[1].parse().map_err(|_| malformed())
+    }
+}
+
+// Bytes received on a connection which have not yet been parsed.
+#[derive(Default)]
+struct RxBuffer {
+    buf: Vec<u8>,
+    // Number of bytes received in all.
+    received: usize,
+}
+
+impl RxBuffer {
+    fn extend(&mut self, bytes: &[u8]) -> Result<()> {
+        if bytes.is_empty() {
+            return Err(closed());
+        }
+        self.buf.extend_from_slice(bytes);
+        self.received += bytes.len();
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn take_head(&mut self) -> Result<Option<Head>> {
+        // Empty lines before a request line are ignored
+        while self.buf.starts_with(b"\r\n") {
+            self.buf.drain(..2);
+        }
+        let end = match self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => end,
+            None if self.buf.len() > MAX_HEAD_SIZE => {
+                return Err(RPCError::new(
+                    RPCErrorKind::MessageTooLarge,
+                    "http head is too long",
+                ))
+            }
+            None => return Ok(None),
+        };
+        let head: Vec<u8> = self.buf.drain(..end + 4).collect();
+        Head::parse(&head[..end]).map(Some)
+    }
+
+    fn take_body(&mut self, len: usize) -> Option<Vec<u8>> {
+        if self.buf.len() < len {
+            self.buf.reserve(len - self.buf.len());
+            return None;
+        }
+        Some(self.buf.drain(..len).collect())
+    }
+
+    // Discard up to `len` bytes, returning how many remain to be
+    // discarded.
+    fn discard(&mut self, len: usize) -> usize {
+        let n = len.min(self.buf.len());
+        self.buf.drain(..n);
+        len - n
+    }
+}
+
+fn fill(channel: &mut impl Read, rx: &mut RxBuffer) -> Result<()> {
+    let mut buf = [0u8; 8192];
+    loop {
+        match channel.read(&mut buf) {
+            Ok(n) => return rx.extend(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn read_head(channel: &mut impl Read, rx: &mut RxBuffer) -> Result<Head> {
+    loop {
+        if let Some(head) = rx.take_head()? {
+            return Ok(head);
+        }
+        fill(channel, rx)?;
+    }
+}
+
+fn read_body(channel: &mut impl Read, rx: &mut RxBuffer, len: usize) -> Result<Vec<u8>> {
+    loop {
+        if let Some(body) = rx.take_body(len) {
+            return Ok(body);
+        }
+        fill(channel, rx)?;
+    }
+}
+
+fn discard_body(channel: &mut impl Read, rx: &mut RxBuffer, mut len: usize) -> Result<()> {
+    loop {
+        len = rx.discard(len);
+        if len == 0 {
+            return Ok(());
+        }
+        fill(channel, rx)?;
+    }
+}
+
+fn write_all(channel: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    channel.write_all(bytes)?;
+    channel.flush()?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        505 => "HTTP Version Not Supported",
+        _ => "Internal Server Error",
+    }
+}
+
+// The bytes of a response. Without a json body, the reason is sent as
+// plain text.
+fn response_bytes(status: u16, body: Option<&[u8]>, close: bool) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    if status == 405 {
+        head.push_str("Allow: POST\r\n");
+    }
+    let (content_type, body) = match body {
+        _ if status == 204 => ("", &[][..]),
+        Some(body) => ("application/json", body),
+        None => ("text/plain", reason(status).as_bytes()),
+    };
+    if status != 204 {
+        head.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n",
+            content_type,
+            body.len()
+        ));
+    }
+    if close {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+// A request which is not served, the status to respond with and the
+// length of its body to discard, or `None` if the end of the body
+// cannot be found and the connection must be closed.
+struct Rejection {
+    status: u16,
+    discard: Option<usize>,
+}
+
+fn is_json(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    [
+        "application/json",
+        "application/json-rpc",
+        "application/jsonrequest",
+    ]
+    .iter()
+    .any(|json| media_type.eq_ignore_ascii_case(json))
+}
+
+// Check a request head, returning the length of its body.
+fn check_request(head: &Head, max: usize) -> std::result::Result<usize, Rejection> {
+    let reject = |status, discard| Err(Rejection { status, discard });
+    if !head.start[2].starts_with("HTTP/1.") {
+        return reject(505, None);
+    }
+    if head.header("transfer-encoding").is_some() {
+        // A message with both is a request smuggling attempt, or at
+        // best ambiguous
+        if head.header("content-length").is_some() {
+            return reject(400, None);
+        }
+        return reject(501, None);
+    }
+    let len = match head.content_length() {
+        Ok(Some(len)) => len,
+        Ok(None) if head.start[0] == "POST" => return reject(411, Some(0)),
+        Ok(None) => 0,
+        Err(_) => return reject(400, None),
+    };
+    if head.start[0] != "POST" {
+        return reject(405, Some(len));
+    }
+    if !head.header("content-type").map_or(true, is_json) {
+        return reject(415, Some(len));
+    }
+    if len > max {
+        return reject(413, Some(len));
+    }
+    Ok(len)
+}
+
+fn error_object(code: i64, error: &RPCError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": {
+            "code": code,
+            "message": error.to_string(),
+            "data": error
+        }
+    })
+}
+
+// The JSON-RPC calls carried by one HTTP request, and the responses to
+// them.
+struct Exchange {
+    // Calls not yet begun, with their ids (`None` for a notification).
+    calls: VecDeque<(Option<Value>, JRXState)>,
+    batch: bool,
+    responses: Vec<Value>,
+    // The id of the call being served, and whether reading its
+    // parameters failed.
+    current: Option<Option<Value>>,
+    invalid_params: bool,
+    tx_metadata: Metadata,
+}
+
+impl Exchange {
+    fn new(body: &[u8]) -> Exchange {
+        let mut exchange = Exchange {
+            calls: VecDeque::new(),
+            batch: false,
+            responses: Vec::new(),
+            current: None,
+            invalid_params: false,
+            tx_metadata: Metadata::new(),
+        };
+        match serde_json::from_slice(body) {
+            Ok(Value::Array(requests)) if !requests.is_empty() => {
+                exchange.batch = true;
+                requests.into_iter().for_each(|r| exchange.push(r));
+            }
+            Ok(Value::Array(_)) => exchange.push(Value::Null),
+            Ok(request) => exchange.push(request),
+            Err(e) => exchange.responses.push(with_id(
+                error_object(PARSE_ERROR, &convert_error(e)),
+                Value::Null,
+            )),
+        }
+        exchange
+    }
+
+    fn push(&mut self, request: Value) {
+        let id = request.get("id").cloned();
+        if request.get("method").is_some_and(Value::is_string) {
+            self.calls.push_back((id, JRXState { json: request }));
+        } else {
+            let error = RPCError::new(RPCErrorKind::SerializationError, "invalid json-rpc request");
+            self.responses.push(with_id(
+                error_object(INVALID_REQUEST, &error),
+                id.unwrap_or(Value::Null),
+            ));
+        }
+    }
+
+    fn next_call(&mut self) -> Option<(PartialMethodId, JRXState)> {
+        let (id, state) = self.calls.pop_front()?;
+        self.current = Some(id);
+        self.invalid_params = false;
+        let method = state.json["method"].as_str().unwrap_or("").to_string();
+        Some((PartialMethodId::Name(method), state))
+    }
+
+    fn respond(&mut self, response: Value) -> Result<()> {
+        match self.current.take() {
+            Some(Some(id)) => self.responses.push(with_id(response, id)),
+            Some(None) => {}
+            None => {
+                return Err(RPCError::new(
+                    RPCErrorKind::IllegalState,
+                    "no json-rpc call to respond to",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn respond_result(&mut self, value: impl Serialize) -> Result<()> {
+        let mut response = json!({
+            "jsonrpc": "2.0",
+            "result": serde_json::to_value(value).map_err(convert_error)?,
+        });
+        let metadata = std::mem::take(&mut self.tx_metadata);
+        if !metadata.is_empty() {
+            response["metadata"] = json!(metadata);
+        }
+        self.respond(response)
+    }
+
+    fn respond_error(&mut self, error: RPCError) -> Result<()> {
+        if self.current.is_none() {
+            return Err(error);
+        }
+        let code = if self.invalid_params {
+            INVALID_PARAMS
+        } else if error.kind == RPCErrorKind::UnknownMethod {
+            METHOD_NOT_FOUND
+        } else {
+            INTERNAL_ERROR
+        };
+        self.tx_metadata = Metadata::new();
+        self.respond(error_object(code, &error))
+    }
+
+    fn is_complete(&self) -> bool {
+        self.current.is_none() && self.calls.is_empty()
+    }
+
+    // The status and json body of the HTTP response.
+    fn http_response(self) -> (u16, Option<Vec<u8>>) {
+        let (status, body) = match self.responses.as_slice() {
+            [] => return (204, None),
+            [response] if !self.batch => (call_status(response), serde_json::to_vec(response)),
+            responses => (200, serde_json::to_vec(responses)),
+        };
+        match body {
+            Ok(body) => (status, Some(body)),
+            Err(_) => (500, None),
+        }
+    }
+}
+
+// The status of the HTTP response to a single call.
+fn call_status(response: &Value) -> u16 {
+    match response["error"]["code"].as_i64() {
+        None => 200,
+        Some(PARSE_ERROR | INVALID_REQUEST | INVALID_PARAMS) => 400,
+        Some(METHOD_NOT_FOUND) => 404,
+        Some(_) => 500,
+    }
+}
+
+fn with_id(mut response: Value, id: Value) -> Value {
+    response["id"] = id;
+    response
+}
+
+// The parts of an `http://` URL used to send requests to it.
+struct Url {
+    authority: String,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url> {
+        let invalid = || {
+            RPCError::new(
+                RPCErrorKind::TransportError,
+                format!("invalid http URL {}", url),
+            )
+        };
+        let rest = match url.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("http://") => &url[7..],
+            _ => return Err(invalid()),
+        };
+        let rest = rest.split('#').next().unwrap_or("");
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        if authority.is_empty() || authority.contains('@') {
+            return Err(invalid());
+        }
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{}", path)
+        };
+        Ok(Url {
+            authority: authority.to_string(),
+            path,
+        })
+    }
+
+    // The address to connect to, with the default port if the URL
+    // has none.
+    fn addr(&self) -> String {
+        match self.authority.rsplit_once(':') {
+            Some((_, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+                self.authority.clone()
+            }
+            _ => format!("{}:80", self.authority),
+        }
+    }
+
+    fn request(&self, body: &[u8]) -> Vec<u8> {
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Accept: application/json\r\nContent-Length: {}\r\n\r\n",
+            self.path,
+            self.authority,
+            body.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(body);
+        request
+    }
+}
+
+// The length of the body of a response, which the client requires to
+// be given.
+fn response_length(head: &Head, max: usize) -> Result<usize> {
+    if head.header("transfer-encoding").is_some() {
+        return Err(RPCError::new(
+            RPCErrorKind::TransportError,
+            "http response uses an unsupported transfer encoding",
+        ));
+    }
+    let len = match head.content_length()? {
+        Some(len) => len,
+        None if head.status()? == 204 => 0,
+        None => {
+            return Err(RPCError::new(
+                RPCErrorKind::TransportError,
+                "http response has no Content-Length",
+            ))
+        }
+    };
+    check_message_size(len, max)?;
+    Ok(len)
+}
+
+// The result of a JSON-RPC response carried by an HTTP response to the
+// call with `id`, and its metadata.
+fn take_result(head: &Head, body: &[u8], id: &str) -> Result<(Value, Metadata)> {
+    let status = head.status()?;
+    let mut response = match serde_json::from_slice(body) {
+        Ok(Value::Object(response)) => response,
+        _ if (200..300).contains(&status) => {
+            return Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                "http response does not hold a json-rpc response",
+            ))
+        }
+        _ => {
+            return Err(RPCError::new(
+                RPCErrorKind::TransportError,
+                format!("http status {} {}", status, head.start[2]),
+            ))
+        }
+    };
+    if let Some(error) = response.remove("error") {
+        return Err(remote_error(error));
+    }
+    if response.get("id").and_then(Value::as_str) != Some(id) {
+        return Err(RPCError::new(
+            RPCErrorKind::SerializationError,
+            "json-rpc response has the wrong id",
+        ));
+    }
+    let metadata = match response.remove("metadata") {
+        Some(metadata) => serde_json::from_value(metadata).map_err(convert_error)?,
+        None => Metadata::new(),
+    };
+    Ok((response.remove("result").unwrap_or(Value::Null), metadata))
+}
+
+// The error described by a JSON-RPC error object. An essrpc server
+// sends the RPCError itself as the error's data.
+fn remote_error(error: Value) -> RPCError {
+    if let Some(Ok(e)) = error.get("data").cloned().map(serde_json::from_value) {
+        return e;
+    }
+    let message = error["message"].as_str().unwrap_or("json-rpc error");
+    RPCError::new(
+        RPCErrorKind::Other,
+        format!("{} (code {})", message, error["code"]),
+    )
+}
+
+// Serialize a call, returning its id and the request body.
+fn request_body(state: &JTXState, max: usize) -> Result<(String, Vec<u8>)> {
+    let id = format!("{}", Uuid::new_v4());
+    let body = serde_json::to_vec(&value_for_state(state, &id)).map_err(convert_error)?;
+    check_message_size(body.len(), max)?;
+    Ok((id, body))
+}
+
+// Whether a request which failed on a connection kept alive from an
+// earlier request may be sent again on a new connection, as the server
+// may have closed the connection while it was idle. If writing the
+// request failed, the server cannot have served it. Once it has been
+// written, the server may have served it before closing the
+// connection, so it is only sent again if `unanswered` allows it and
+// nothing has been received.
+fn may_retry(e: &RPCError, reused: bool, sent: bool, received: bool, unanswered: bool) -> bool {
+    reused
+        && (!sent || (unanswered && !received))
+        && matches!(
+            e.kind,
+            RPCErrorKind::TransportEOF | RPCErrorKind::TransportError
+        )
+}
+
+// Whether a connection kept alive may be used for another request:
+// the server may have closed it, or sent something unsolicited before
+// closing it, while it was idle.
+fn is_idle(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 1];
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let idle = matches!(stream.peek(&mut buf), Err(e) if e.kind() == io::ErrorKind::WouldBlock);
+    stream.set_nonblocking(false).is_ok() && idle
+}
+
+/// Client transport for JSON-RPC over HTTP/1.1. Each call is POSTed
+/// to a URL as a JSON-RPC request, and its result read from the
+/// JSON-RPC response in the body of the HTTP response. Enable the
+/// "http_transport" feature to use this.
+///
+/// Only `http://` URLs are supported. The connection is kept alive
+/// between calls, and reopened if the server closes it. A call is
+/// sent again on a new connection if writing it to a connection kept
+/// alive fails. If the server closes the connection after the call
+/// was written but without responding, the call fails unless
+/// `retry_unanswered` is set, as the server may have served it. An error
+/// response from the server is returned as the `RPCError` it carries,
+/// so a call to a method the server does not have fails with
+/// `RPCErrorKind::UnknownMethod`.
+pub struct HTTPClientTransport {
+    url: Url,
+    stream: Option<TcpStream>,
+    rx: RxBuffer,
+    max_message_size: usize,
+    rx_metadata: Metadata,
+    retry_unanswered: bool,
+    // Whether the request being posted has been written.
+    sent: bool,
+}
+
+impl HTTPClientTransport {
+    /// Create a transport sending calls to `url`, such as
+    /// `http://127.0.0.1:8080/rpc`. Connecting is left until the
+    /// first call.
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(HTTPClientTransport {
+            url: Url::parse(url)?,
+            stream: None,
+            rx: RxBuffer::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            rx_metadata: Metadata::new(),
+            retry_unanswered: false,
+            sent: false,
+        })
+    }
+
+    /// Set the maximum size of a request or response body, in bytes.
+    /// See
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = max;
+        self
+    }
+
+    /// Whether to send a call again on a new connection if the server
+    /// closes the connection it was sent on, kept alive from an
+    /// earlier call, without responding (by default, `false`). The
+    /// server may have served the call before closing the connection,
+    /// so this is only safe if every method called is idempotent.
+    pub fn retry_unanswered(mut self, retry: bool) -> Self {
+        self.retry_unanswered = retry;
+        self
+    }
+
+    fn post(&mut self, body: &[u8]) -> Result<(Head, Vec<u8>)> {
+        let request = self.url.request(body);
+        if self.stream.as_ref().is_some_and(|s| !is_idle(s)) {
+            self.stream = None;
+        }
+        let reused = self.stream.is_some();
+        let received = self.rx.received;
+        match self.try_post(&request) {
+            Err(e)
+                if may_retry(
+                    &e,
+                    reused,
+                    self.sent,
+                    self.rx.received != received,
+                    self.retry_unanswered,
+                ) =>
+            {
+                self.try_post(&request)
+            }
+            result => result,
+        }
+    }
+
+    fn try_post(&mut self, request: &[u8]) -> Result<(Head, Vec<u8>)> {
+        if self.stream.is_none() {
+            self.stream = Some(TcpStream::connect(self.url.addr())?);
+            self.rx = RxBuffer::default();
+        }
+        let result = self.round_trip(request);
+        match &result {
+            Ok((head, _)) if head.keep_alive() => {}
+            _ => self.stream = None,
+        }
+        result
+    }
+
+    fn round_trip(&mut self, request: &[u8]) -> Result<(Head, Vec<u8>)> {
+        self.sent = false;
+        let stream = self.stream.as_mut().ok_or_else(closed)?;
+        write_all(stream, request)?;
+        self.sent = true;
+        let head = loop {
+            let head = read_head(stream, &mut self.rx)?;
+            // Interim responses are skipped
+            if !head.start[1].starts_with('1') {
+                break head;
+            }
+        };
+        let len = response_length(&head, self.max_message_size)?;
+        let body = read_body(stream, &mut self.rx, len)?;
+        Ok((head, body))
+    }
+}
+
+impl ClientTransport for HTTPClientTransport {
+    type TXState = JTXState;
+    type FinalState = Value;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<JTXState> {
+        Ok(begin_call(method))
+    }
+
+    fn tx_set_metadata(&mut self, metadata: &Metadata, state: &mut JTXState) -> Result<()> {
+        state.metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut JTXState,
+    ) -> Result<()> {
+        add_param(name, value, state)
+    }
+
+    fn tx_finalize(&mut self, state: JTXState) -> Result<Value> {
+        let (id, body) = request_body(&state, self.max_message_size)?;
+        let (head, body) = self.post(&body)?;
+        let (result, metadata) = take_result(&head, &body, &id)?;
+        self.rx_metadata = metadata;
+        Ok(result)
+    }
+
+    fn rx_response<T>(&mut self, result: Value) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        serde_json::from_value(result).map_err(convert_error)
+    }
+
+    fn rx_response_metadata(&mut self) -> Metadata {
+        std::mem::take(&mut self.rx_metadata)
+    }
+}
+
+/// Server transport for JSON-RPC over HTTP/1.1, serving a single
+/// connection. Each POST request holds a JSON-RPC request object, or
+/// a batch of them in an array, whose calls are served in order; the
+/// response holds the JSON-RPC response objects. Notifications
+/// (requests without an id) are served without a response. Enable
+/// the "http_transport" feature to use this, and serve connections
+/// with
+/// [Listener::serve_http](../server/struct.Listener.html#method.serve_http).
+///
+/// A call which fails, for example because its method is unknown or
+/// its parameters are invalid, is answered with a JSON-RPC error
+/// object carrying the `RPCError` as its data, and serving continues.
+/// The status of the HTTP response is
+/// * 200 for a response with a result, or for a batch,
+/// * 204 if there is nothing to respond, as all calls were
+///   notifications,
+/// * 400 if the body is not json (parse error), not a JSON-RPC
+///   request (invalid request) or has invalid parameters, 404 for an
+///   unknown method, and 500 for any other error,
+/// * 405 for a method other than POST, 411 without a
+///   `Content-Length`, 413 if the body is longer than the maximum set
+///   with `with_max_message_size`, and 415 for a `Content-Type` other
+///   than json. These requests are discarded without being served.
+/// * 400 for a malformed request head, including one with
+///   conflicting `Content-Length` values or with both
+///   `Content-Length` and `Transfer-Encoding`, and 501 for any other
+///   `Transfer-Encoding`. The connection is then closed.
+pub struct HTTPServerTransport<C: Read + Write> {
+    channel: C,
+    rx: RxBuffer,
+    max_message_size: usize,
+    // The request being served, if any.
+    exchange: Option<Exchange>,
+    // Whether the connection is closed after the request.
+    close: bool,
+}
+
+impl<C: Read + Write> HTTPServerTransport<C> {
+    pub fn new(channel: C) -> Self {
+        HTTPServerTransport {
+            channel,
+            rx: RxBuffer::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            exchange: None,
+            close: false,
+        }
+    }
+
+    /// Set the maximum size of a request body, in bytes. See
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = max;
+        self
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        &self.channel
+    }
+
+    // Read requests until one is to be served, answering those which
+    // are rejected.
+    fn read_request(&mut self) -> Result<Exchange> {
+        loop {
+            let head = match read_head(&mut self.channel, &mut self.rx) {
+                Ok(head) => head,
+                Err(e) if e.kind == RPCErrorKind::TransportEOF => return Err(e),
+                Err(e) => {
+                    self.close = true;
+                    let status = match e.kind {
+                        RPCErrorKind::MessageTooLarge => 431,
+                        _ => 400,
+                    };
+                    write_all(&mut self.channel, &response_bytes(status, None, true))?;
+                    return Err(e);
+                }
+            };
+            self.close = !head.keep_alive();
+            match check_request(&head, self.max_message_size) {
+                Ok(len) => {
+                    if head.expects_continue() && self.rx.len() < len {
+                        write_all(&mut self.channel, CONTINUE)?;
+                    }
+                    let body = read_body(&mut self.channel, &mut self.rx, len)?;
+                    return Ok(Exchange::new(&body));
+                }
+                Err(rejection) => {
+                    // A client expecting 100 Continue may not send the
+                    // body
+                    let discard = rejection.discard.filter(|_| !head.expects_continue());
+                    self.close |= discard.is_none();
+                    let response = response_bytes(rejection.status, None, self.close);
+                    write_all(&mut self.channel, &response)?;
+                    if self.close {
+                        return Err(closed());
+                    }
+                    discard_body(&mut self.channel, &mut self.rx, discard.unwrap_or(0))?;
+                }
+            }
+        }
+    }
+
+    fn exchange(&mut self) -> Result<&mut Exchange> {
+        self.exchange.as_mut().ok_or_else(|| {
+            RPCError::new(RPCErrorKind::IllegalState, "no http request being served")
+        })
+    }
+
+    // Respond to the request once all its calls have been served.
+    fn finish(&mut self) -> Result<()> {
+        if !self.exchange.as_ref().is_some_and(Exchange::is_complete) {
+            return Ok(());
+        }
+        if let Some(exchange) = self.exchange.take() {
+            let (status, body) = exchange.http_response();
+            let response = response_bytes(status, body.as_deref(), self.close);
+            write_all(&mut self.channel, &response)?;
+        }
+        Ok(())
+    }
+}
+
+impl<C: Read + Write> ServerTransport for HTTPServerTransport<C> {
+    type RXState = JRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+        loop {
+            if let Some(call) = self.exchange.as_mut().and_then(Exchange::next_call) {
+                return Ok(call);
+            }
+            if self.close {
+                return Err(closed());
+            }
+            self.exchange = Some(self.read_request()?);
+            self.finish()?;
+        }
+    }
+
+    fn rx_metadata(&mut self, state: &mut JRXState) -> Result<Metadata> {
+        read_metadata(state).map_err(|e| {
+            if let Some(exchange) = &mut self.exchange {
+                exchange.invalid_params = true;
+            }
+            e
+        })
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        read_param(name, state).map_err(|e| {
+            if let Some(exchange) = &mut self.exchange {
+                exchange.invalid_params = true;
+            }
+            e
+        })
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.exchange()?.tx_metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+        self.exchange()?.respond_result(value)?;
+        self.finish()
+    }
+
+    fn tx_error(&mut self, error: RPCError) -> Result<()> {
+        match &mut self.exchange {
+            Some(exchange) => exchange.respond_error(error)?,
+            None => return Err(error),
+        }
+        self.finish()
+    }
+}
+
+#[cfg(feature = "async_client")]
+mod asynchronous {
+    use super::*;
+    use crate::server::{is_transient, Connection, ErrorCallback, PeerAddr, ShutdownHandle};
+    use crate::{AsyncClientTransport, RPCServer};
+    use async_trait::async_trait;
+    use futures::channel::oneshot;
+    use std::sync::Arc;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::{mpsc, watch};
+
+    async fn fill(channel: &mut (impl AsyncRead + Unpin), rx: &mut RxBuffer) -> Result<()> {
+        let mut buf = [0u8; 8192];
+        let n = channel.read(&mut buf).await?;
+        rx.extend(&buf[..n])
+    }
+
+    async fn read_head(channel: &mut (impl AsyncRead + Unpin), rx: &mut RxBuffer) -> Result<Head> {
+        loop {
+            if let Some(head) = rx.take_head()? {
+                return Ok(head);
+            }
+            fill(channel, rx).await?;
+        }
+    }
+
+    async fn read_body(
+        channel: &mut (impl AsyncRead + Unpin),
+        rx: &mut RxBuffer,
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        loop {
+            if let Some(body) = rx.take_body(len) {
+                return Ok(body);
+            }
+            fill(channel, rx).await?;
+        }
+    }
+
+    async fn discard_body(
+        channel: &mut (impl AsyncRead + Unpin),
+        rx: &mut RxBuffer,
+        mut len: usize,
+    ) -> Result<()> {
+        loop {
+            len = rx.discard(len);
+            if len == 0 {
+                return Ok(());
+            }
+            fill(channel, rx).await?;
+        }
+    }
+
+    // Whether a connection kept alive may be used for another request.
+    // The server closing it is only seen once the runtime has noticed.
+    fn is_idle(stream: &TcpStream) -> bool {
+        let mut buf = [0u8; 1];
+        matches!(stream.try_read(&mut buf), Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+    }
+
+    async fn write_all(channel: &mut (impl AsyncWrite + Unpin), bytes: &[u8]) -> Result<()> {
+        channel.write_all(bytes).await?;
+        channel.flush().await?;
+        Ok(())
+    }
+
+    /// Like HTTPClientTransport except for use as
+    /// AsyncClientTransport.
+    pub struct HTTPAsyncClientTransport {
+        url: Url,
+        stream: Option<TcpStream>,
+        rx: RxBuffer,
+        max_message_size: usize,
+        rx_metadata: Metadata,
+        retry_unanswered: bool,
+        // Whether the request being posted has been written.
+        sent: bool,
+    }
+
+    impl HTTPAsyncClientTransport {
+        /// Create a transport sending calls to `url`, such as
+        /// `http://127.0.0.1:8080/rpc`. Connecting is left until the
+        /// first call.
+        pub fn new(url: &str) -> Result<Self> {
+            Ok(HTTPAsyncClientTransport {
+                url: Url::parse(url)?,
+                stream: None,
+                rx: RxBuffer::default(),
+                max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+                rx_metadata: Metadata::new(),
+                retry_unanswered: false,
+                sent: false,
+            })
+        }
+
+        /// Set the maximum size of a request or response body, in
+        /// bytes. See
+        /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+        pub fn with_max_message_size(mut self, max: usize) -> Self {
+            self.max_message_size = max;
+            self
+        }
+
+        /// Whether to send a call again on a new connection if the
+        /// server closes the connection without responding. See
+        /// `HTTPClientTransport::retry_unanswered`.
+        pub fn retry_unanswered(mut self, retry: bool) -> Self {
+            self.retry_unanswered = retry;
+            self
+        }
+
+        async fn post(&mut self, body: &[u8]) -> Result<(Head, Vec<u8>)> {
+            let request = self.url.request(body);
+            if self.stream.as_ref().is_some_and(|s| !is_idle(s)) {
+                self.stream = None;
+            }
+            let reused = self.stream.is_some();
+            let received = self.rx.received;
+            match self.try_post(&request).await {
+                Err(e)
+                    if may_retry(
+                        &e,
+                        reused,
+                        self.sent,
+                        self.rx.received != received,
+                        self.retry_unanswered,
+                    ) =>
+                {
+                    self.try_post(&request).await
+                }
+                result => result,
+            }
+        }
+
+        async fn try_post(&mut self, request: &[u8]) -> Result<(Head, Vec<u8>)> {
+            if self.stream.is_none() {
+                self.stream = Some(TcpStream::connect(self.url.addr()).await?);
+                self.rx = RxBuffer::default();
+            }
+            let result = self.round_trip(request).await;
+            match &result {
+                Ok((head, _)) if head.keep_alive() => {}
+                _ => self.stream = None,
+            }
+            result
+        }
+
+        async fn round_trip(&mut self, request: &[u8]) -> Result<(Head, Vec<u8>)> {
+            self.sent = false;
+            let stream = self.stream.as_mut().ok_or_else(closed)?;
+            write_all(stream, request).await?;
+            self.sent = true;
+            let head = loop {
+                let head = read_head(stream, &mut self.rx).await?;
+                if !head.start[1].starts_with('1') {
+                    break head;
+                }
+            };
+            let len = response_length(&head, self.max_message_size)?;
+            let body = read_body(stream, &mut self.rx, len).await?;
+            Ok((head, body))
+        }
+    }
+
+    #[async_trait]
+    impl AsyncClientTransport for HTTPAsyncClientTransport {
+        type TXState = JTXState;
+        type FinalState = Value;
+
+        async fn tx_begin_call(&mut self, method: MethodId) -> Result<JTXState> {
+            Ok(begin_call(method))
+        }
+
+        async fn tx_set_metadata(
+            &mut self,
+            metadata: &Metadata,
+            state: &mut JTXState,
+        ) -> Result<()> {
+            state.metadata = metadata.clone();
+            Ok(())
+        }
+
+        async fn tx_add_param(
+            &mut self,
+            name: &'static str,
+            value: impl Serialize + Send + 'async_trait,
+            state: &mut JTXState,
+        ) -> Result<()> {
+            add_param(name, value, state)
+        }
+
+        async fn tx_finalize(&mut self, state: JTXState) -> Result<Value> {
+            let (id, body) = request_body(&state, self.max_message_size)?;
+            let (head, body) = self.post(&body).await?;
+            let (result, metadata) = take_result(&head, &body, &id)?;
+            self.rx_metadata = metadata;
+            Ok(result)
+        }
+
+        async fn rx_response<T>(&mut self, result: Value) -> Result<T>
+        where
+            for<'de> T: Deserialize<'de>,
+        {
+            serde_json::from_value(result).map_err(convert_error)
+        }
+
+        async fn rx_response_metadata(&mut self) -> Metadata {
+            std::mem::take(&mut self.rx_metadata)
+        }
+    }
+
+    /// A single HTTP request read by an
+    /// [HTTPAsyncServer](struct.HTTPAsyncServer.html), used as the
+    /// transport of the server which serves its calls.
+    pub struct HTTPRequest {
+        // Taken once all calls have been served.
+        exchange: Option<Exchange>,
+        done: Option<oneshot::Sender<Exchange>>,
+    }
+
+    impl HTTPRequest {
+        fn exchange(&mut self) -> Result<&mut Exchange> {
+            self.exchange.as_mut().ok_or_else(|| {
+                RPCError::new(RPCErrorKind::IllegalState, "http request has been answered")
+            })
+        }
+
+        // Hand the responses to the server once all calls have been
+        // served.
+        fn finish(&mut self) {
+            if !self.exchange.as_ref().is_some_and(Exchange::is_complete) {
+                return;
+            }
+            if let (Some(exchange), Some(done)) = (self.exchange.take(), self.done.take()) {
+                let _ = done.send(exchange);
+            }
+        }
+    }
+
+    impl ServerTransport for HTTPRequest {
+        type RXState = JRXState;
+
+        fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+            self.exchange()?.next_call().ok_or_else(|| {
+                RPCError::new(RPCErrorKind::IllegalState, "http request has no more calls")
+            })
+        }
+
+        fn rx_metadata(&mut self, state: &mut JRXState) -> Result<Metadata> {
+            read_metadata(state).map_err(|e| {
+                if let Some(exchange) = &mut self.exchange {
+                    exchange.invalid_params = true;
+                }
+                e
+            })
+        }
+
+        fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
+        where
+            for<'de> T: serde::Deserialize<'de>,
+        {
+            read_param(name, state).map_err(|e| {
+                if let Some(exchange) = &mut self.exchange {
+                    exchange.invalid_params = true;
+                }
+                e
+            })
+        }
+
+        fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+            self.exchange()?.tx_metadata = metadata.clone();
+            Ok(())
+        }
+
+        fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+            self.exchange()?.respond_result(value)?;
+            self.finish();
+            Ok(())
+        }
+
+        fn tx_error(&mut self, error: RPCError) -> Result<()> {
+            match &mut self.exchange {
+                Some(exchange) => exchange.respond_error(error)?,
+                None => return Err(error),
+            }
+            self.finish();
+            Ok(())
+        }
+    }
+
+    /// Async server for JSON-RPC over HTTP/1.1, accepting connections
+    /// from a tokio `TcpListener` and serving each on a task of its
+    /// own. Requests are read and answered as by
+    /// [HTTPServerTransport](struct.HTTPServerTransport.html). The
+    /// calls of each request are served on a blocking thread by the
+    /// server `make_server` returns for it, for example
+    /// ```ignore
+    /// let imp = Arc::new(FooImpl::new());
+    /// HTTPAsyncServer::new(listener, move |tr| FooRPCServer::new(imp.clone(), tr))
+    ///     .serve()
+    ///     .await?;
+    /// ```
+    /// If serving fails without answering every call, for example
+    /// because the implementation panicked with panics not being
+    /// caught, the request is answered with status 500.
+    ///
+    /// If given a [ShutdownHandle](../server/struct.ShutdownHandle.html),
+    /// the server stops accepting connections when it is triggered.
+    /// Each connection finishes the request in progress (if any) and
+    /// is closed, after which `serve` returns `Ok(())`.
+    pub struct HTTPAsyncServer<F> {
+        listener: TcpListener,
+        make_server: Arc<F>,
+        max_message_size: usize,
+        shutdown: Option<ShutdownHandle>,
+        on_error: Option<ErrorCallback>,
+    }
+
+    impl<F, S> HTTPAsyncServer<F>
+    where
+        F: Fn(HTTPRequest) -> S + Send + Sync + 'static,
+        S: RPCServer,
+    {
+        pub fn new(listener: TcpListener, make_server: F) -> Self {
+            HTTPAsyncServer {
+                listener,
+                make_server: Arc::new(make_server),
+                max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+                shutdown: None,
+                on_error: None,
+            }
+        }
+
+        /// Set the maximum size of a request body, in bytes. See
+        /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+        pub fn with_max_message_size(mut self, max: usize) -> Self {
+            self.max_message_size = max;
+            self
+        }
+
+        /// Stop serving when `shutdown` is triggered.
+        pub fn shutdown(mut self, shutdown: ShutdownHandle) -> Self {
+            self.shutdown = Some(shutdown);
+            self
+        }
+
+        /// Call `f` when a connection ends with an error other than
+        /// `TransportEOF`, or accepting a connection fails (in which
+        /// case there is no connection).
+        pub fn on_error(
+            mut self,
+            f: impl Fn(Option<&Connection>, &RPCError) + Send + Sync + 'static,
+        ) -> Self {
+            self.on_error = Some(Arc::new(f));
+            self
+        }
+
+        /// Get the underlying listener.
+        pub fn listener(&self) -> &TcpListener {
+            &self.listener
+        }
+
+        /// Accept and serve connections. Returns `Ok(())` after a
+        /// shutdown, or an error if accepting a connection fails with
+        /// an error which is not specific to that connection.
+        pub async fn serve(self) -> Result<()> {
+            let (triggered, mut stopping) = watch::channel(false);
+            let _registration = self.shutdown.as_ref().map(|shutdown| {
+                shutdown.on_shutdown(move || {
+                    triggered.send_replace(true);
+                })
+            });
+            // Each connection holds a sender, so that once all have
+            // ended `recv` returns None.
+            let (active, mut ended) = mpsc::channel::<()>(1);
+            let mut next_id = 0;
+            loop {
+                let accepted = tokio::select! {
+                    accepted = self.listener.accept() => accepted,
+                    _ = stopped(&mut stopping) => break,
+                };
+                let (stream, addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        let transient = is_transient(&e);
+                        let e = RPCError::with_cause(
+                            RPCErrorKind::TransportError,
+                            "failed to accept connection",
+                            e,
+                        );
+                        if let Some(on_error) = &self.on_error {
+                            on_error(None, &e);
+                        }
+                        if transient {
+                            continue;
+                        }
+                        return Err(e);
+                    }
+                };
+                let conn = Connection {
+                    id: next_id,
+                    peer: PeerAddr::Tcp(addr),
+                    credentials: None,
+                    shutdown: self.shutdown.clone(),
+                };
+                next_id += 1;
+                let make_server = self.make_server.clone();
+                let max = self.max_message_size;
+                let on_error = self.on_error.clone();
+                let stopping = stopping.clone();
+                let active = active.clone();
+                tokio::spawn(async move {
+                    let result = serve_connection(stream, make_server, max, stopping).await;
+                    if let (Err(e), Some(on_error)) = (result, on_error) {
+                        if e.kind != RPCErrorKind::TransportEOF {
+                            on_error(Some(&conn), &e);
+                        }
+                    }
+                    drop(active);
+                });
+            }
+            drop(active);
+            ended.recv().await;
+            Ok(())
+        }
+    }
+
+    // Wait until the shutdown is triggered. Never completes if it
+    // cannot be.
+    async fn stopped(stopping: &mut watch::Receiver<bool>) {
+        while !*stopping.borrow_and_update() {
+            if stopping.changed().await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+
+    // Serve the calls of a request on a blocking thread.
+    async fn dispatch<F, S>(exchange: Exchange, make_server: Arc<F>) -> (u16, Option<Vec<u8>>)
+    where
+        F: Fn(HTTPRequest) -> S + Send + Sync + 'static,
+        S: RPCServer,
+    {
+        let (done, served) = oneshot::channel();
+        tokio::task::spawn_blocking(move || {
+            let mut server = make_server(HTTPRequest {
+                exchange: Some(exchange),
+                done: Some(done),
+            });
+            // Ends once the request has no more calls. A panic or
+            // error before then drops `done`.
+            while server.serve_single_call().is_ok() {}
+        });
+        match served.await {
+            Ok(exchange) => exchange.http_response(),
+            Err(_) => (500, None),
+        }
+    }
+
+    async fn serve_connection<F, S>(
+        mut stream: TcpStream,
+        make_server: Arc<F>,
+        max: usize,
+        mut stopping: watch::Receiver<bool>,
+    ) -> Result<()>
+    where
+        F: Fn(HTTPRequest) -> S + Send + Sync + 'static,
+        S: RPCServer,
+    {
+        let mut rx = RxBuffer::default();
+        loop {
+            let head = tokio::select! {
+                head = read_head(&mut stream, &mut rx) => head,
+                // Only waiting requests are abandoned
+                _ = stopped(&mut stopping) => return Ok(()),
+            };
+            let head = match head {
+                Ok(head) => head,
+                Err(e) if e.kind == RPCErrorKind::TransportEOF => return Ok(()),
+                Err(e) => {
+                    let status = match e.kind {
+                        RPCErrorKind::MessageTooLarge => 431,
+                        _ => 400,
+                    };
+                    write_all(&mut stream, &response_bytes(status, None, true)).await?;
+                    return Err(e);
+                }
+            };
+            let mut close = !head.keep_alive();
+            let len = match check_request(&head, max) {
+                Ok(len) => len,
+                Err(rejection) => {
+                    let discard = rejection.discard.filter(|_| !head.expects_continue());
+                    close |= discard.is_none();
+                    let response = response_bytes(rejection.status, None, close);
+                    write_all(&mut stream, &response).await?;
+                    if close {
+                        return Ok(());
+                    }
+                    discard_body(&mut stream, &mut rx, discard.unwrap_or(0)).await?;
+                    continue;
+                }
+            };
+            if head.expects_continue() && rx.len() < len {
+                write_all(&mut stream, CONTINUE).await?;
+            }
+            let body = read_body(&mut stream, &mut rx, len).await?;
+            let exchange = Exchange::new(&body);
+            let (status, body) = if exchange.is_complete() {
+                exchange.http_response()
+            } else {
+                dispatch(exchange, make_server.clone()).await
+            };
+            close |= *stopping.borrow();
+            write_all(&mut stream, &response_bytes(status, body.as_deref(), close)).await?;
+            if close {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async_client")]
+pub use self::asynchronous::{HTTPAsyncClientTransport, HTTPAsyncServer, HTTPRequest};
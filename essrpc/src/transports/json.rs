@@ -1,33 +1,194 @@
-use async_trait::async_trait;
+use super::WireFormat;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_json::value::Value;
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::io::{Read, Write};
+use std::sync::Arc;
 use uuid::Uuid;
 
+use super::{
+    check_message_size, message_too_large, DuplicateChannel, Recorder, DEFAULT_MAX_MESSAGE_SIZE,
+};
+use crate::internal::SyncMutex;
 use crate::{
-    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
+    ClientTransport, ConcurrentServerTransport, Metadata, MethodId, PartialMethodId, RPCError,
+    RPCErrorKind, Result, ServerTransport,
 };
 
 pub struct JTXState {
     method: &'static str,
     params: Value,
+    pub(super) metadata: Metadata,
 }
 
 pub struct JRXState {
-    json: Value,
+    pub(super) json: Value,
+}
+
+impl JRXState {
+    /// The JSON-RPC request object of the call.
+    pub fn json(&self) -> &Value {
+        &self.json
+    }
+}
+
+/// How the JSON transports delimit messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JSONFraming {
+    /// Values follow one another without delimiters, and are read by
+    /// scanning them. This is the default.
+    #[default]
+    Unframed,
+    /// Each message is a single line ended by a newline, as in JSON
+    /// Lines. Blank lines are skipped.
+    NewlineDelimited,
+    /// Each message is preceded by a `Content-Length: <bytes>` header
+    /// and a blank line, as in the Language Server Protocol. Header
+    /// lines end with CRLF, and other headers are ignored.
+    ContentLength,
+}
+
+impl JSONFraming {
+    // Frame a serialized message.
+    fn frame(self, msg: Vec<u8>) -> Vec<u8> {
+        match self {
+            JSONFraming::Unframed => msg,
+            JSONFraming::NewlineDelimited => {
+                // Compact json holds no raw newlines
+                let mut framed = msg;
+                framed.push(b'\n');
+                framed
+            }
+            JSONFraming::ContentLength => {
+                let mut framed = format!("Content-Length: {}\r\n\r\n", msg.len()).into_bytes();
+                framed.extend_from_slice(&msg);
+                framed
+            }
+        }
+    }
+}
+
+// Longest header section accepted with `JSONFraming::ContentLength`.
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+// How messages are framed, and their maximum size.
+#[derive(Clone, Copy)]
+struct Wire {
+    framing: JSONFraming,
+    max: usize,
+}
+
+impl Wire {
+    fn new() -> Self {
+        Wire {
+            framing: JSONFraming::default(),
+            max: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    // Read the bytes of a message.
+    fn read(self, reader: impl Read) -> Result<Vec<u8>> {
+        match self.framing {
+            JSONFraming::Unframed => read_message(reader, self.max),
+            JSONFraming::NewlineDelimited => read_line(reader, self.max),
+            JSONFraming::ContentLength => read_content(reader, self.max),
+        }
+    }
+
+    // Write a value as a message, returning its encoded size.
+    fn write(self, mut w: impl Write, value: &impl Serialize) -> Result<usize> {
+        let msg = serde_json::to_vec(value).map_err(convert_error)?;
+        let size = msg.len();
+        check_message_size(size, self.max)?;
+        w.write_all(&self.framing.frame(msg))?;
+        w.flush().map_err(|e| {
+            RPCError::with_cause(
+                RPCErrorKind::SerializationError,
+                "cannot flush underlying channel",
+                e,
+            )
+        })?;
+        Ok(size)
+    }
 }
 
 /// Transport implementation over JSON-RPC. Can be used over any
 /// `Read+Write` channel (local socket, internet socket, pipe,
 /// etc). Enable the "json_transport" feature to use this.
+///
+/// By default messages are not delimited, so a method returning a
+/// bare number (rather than a `Result`) cannot be read until further
+/// data arrives. `with_framing` selects newline-delimited messages or
+/// `Content-Length` headers instead, which the client and server must
+/// agree on.
+///
+/// By default the server responds with the bare return value. If
+/// request ids are enabled with `with_request_ids` (on both the
+/// client and the server), the response is a JSON-RPC response object
+/// with the id of the request. This allows a client to have several
+/// calls in progress, answered in any order by a
+/// [ConcurrentServer](../server/struct.ConcurrentServer.html).
+///
+/// [Metadata](../struct.Metadata.html) is carried in the `metadata`
+/// member of the request object, and of the response object if
+/// request ids are enabled. Without request ids, response metadata is
+/// discarded.
+///
+/// A message longer than the maximum set with `with_max_message_size`
+/// is read to its end and discarded.
 pub struct JSONTransport<C: Read + Write> {
     channel: C,
+    request_ids: bool,
+    wire: Wire,
+    // Client: ids of requests whose response has not been read,
+    // responses received for requests other than the one being
+    // waited for, and metadata of the last response.
+    outstanding: HashSet<String>,
+    received: HashMap<String, Value>,
+    rx_metadata: Metadata,
+    // Server: id of the call being served, metadata of the response,
+    // and the channel used to respond to concurrent calls.
+    current_id: Option<Value>,
+    tx_metadata: Metadata,
+    writer: Option<Arc<SyncMutex<C>>>,
 }
 
 impl<C: Read + Write> JSONTransport<C> {
     pub fn new(channel: C) -> Self {
-        JSONTransport { channel }
+        JSONTransport {
+            channel,
+            request_ids: false,
+            wire: Wire::new(),
+            outstanding: HashSet::new(),
+            received: HashMap::new(),
+            rx_metadata: Metadata::new(),
+            current_id: None,
+            tx_metadata: Metadata::new(),
+            writer: None,
+        }
+    }
+
+    /// Respond with JSON-RPC response objects carrying the request id.
+    /// A client discards responses to requests it is not waiting for.
+    pub fn with_request_ids(mut self) -> Self {
+        self.request_ids = true;
+        self
+    }
+
+    /// Set the maximum size of a message, in bytes. See
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.wire.max = max;
+        self
+    }
+
+    /// Set how messages are delimited.
+    pub fn with_framing(mut self, framing: JSONFraming) -> Self {
+        self.wire.framing = framing;
+        self
     }
 
     /// Get the underlying read/write channel
@@ -40,27 +201,50 @@ impl<C: Read + Write> JSONTransport<C> {
     where
         for<'de> T: serde::Deserialize<'de>,
     {
-        read_value_from_json(Read::by_ref(&mut self.channel))
+        let msg = self.wire.read(Read::by_ref(&mut self.channel))?;
+        decode(&msg)
     }
 
-    fn flush(&mut self) -> Result<()> {
-        self.channel.flush().map_err(|e| {
-            RPCError::with_cause(
-                RPCErrorKind::SerializationError,
-                "cannot flush underlying channel",
-                e,
-            )
-        })
+    // Read a call, returning its id if request ids are enabled.
+    fn read_call(&mut self) -> Result<(PartialMethodId, JRXState, Option<Value>)> {
+        let value: Value = self.read_from_channel()?;
+        let method = value
+            .get("method")
+            .ok_or_else(|| {
+                RPCError::new(
+                    RPCErrorKind::SerializationError,
+                    "json is not expected object",
+                )
+            })?
+            .as_str()
+            .ok_or_else(|| {
+                RPCError::new(
+                    RPCErrorKind::SerializationError,
+                    "json method was not string",
+                )
+            })?
+            .to_string();
+        let id = if self.request_ids {
+            Some(value.get("id").cloned().unwrap_or(Value::Null))
+        } else {
+            None
+        };
+        Ok((PartialMethodId::Name(method), JRXState { json: value }, id))
     }
 }
 impl<C: Read + Write> ClientTransport for JSONTransport<C> {
     type TXState = JTXState;
-    type FinalState = ();
+    type FinalState = String;
 
     fn tx_begin_call(&mut self, method: MethodId) -> Result<JTXState> {
         Ok(begin_call(method))
     }
 
+    fn tx_set_metadata(&mut self, metadata: &Metadata, state: &mut JTXState) -> Result<()> {
+        state.metadata = metadata.clone();
+        Ok(())
+    }
+
     fn tx_add_param(
         &mut self,
         name: &'static str,
@@ -70,21 +254,71 @@ impl<C: Read + Write> ClientTransport for JSONTransport<C> {
         add_param(name, value, state)
     }
 
-    fn tx_finalize(&mut self, state: JTXState) -> Result<()> {
-        serde_json::to_writer(Write::by_ref(&mut self.channel), &value_for_state(&state))
-            .map_err(convert_error)?;
-        self.flush()
+    fn tx_finalize(&mut self, state: JTXState) -> Result<String> {
+        let id = format!("{}", Uuid::new_v4());
+        self.wire
+            .write(&mut self.channel, &value_for_state(&state, &id))?;
+        if self.request_ids {
+            self.outstanding.insert(id.clone());
+        }
+        Ok(id)
     }
 
-    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    fn rx_response<T>(&mut self, id: String) -> Result<T>
     where
         for<'de> T: Deserialize<'de>,
     {
-        self.read_from_channel()
+        if !self.request_ids {
+            return self.read_from_channel();
+        }
+        // Once this call returns, the response is no longer awaited,
+        // even if reading it fails.
+        self.outstanding.remove(&id);
+        let response = match self.received.remove(&id) {
+            Some(response) => response,
+            None => loop {
+                let mut response: Value = self.read_from_channel()?;
+                let rx_id = match response.get_mut("id").map(Value::take) {
+                    Some(Value::String(rx_id)) => rx_id,
+                    _ => {
+                        return Err(RPCError::new(
+                            RPCErrorKind::SerializationError,
+                            "json response has no id",
+                        ))
+                    }
+                };
+                if rx_id == id {
+                    break response;
+                }
+                // Responses to requests which are not outstanding
+                // (unknown, abandoned or already answered) are dropped.
+                if self.outstanding.contains(&rx_id) {
+                    self.received.entry(rx_id).or_insert(response);
+                }
+            },
+        };
+        match response {
+            Value::Object(mut response) => {
+                self.rx_metadata = match response.remove("metadata") {
+                    Some(metadata) => serde_json::from_value(metadata).map_err(convert_error)?,
+                    None => Metadata::new(),
+                };
+                serde_json::from_value(response.remove("result").unwrap_or(Value::Null))
+                    .map_err(convert_error)
+            }
+            _ => Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                "json response is not an object",
+            )),
+        }
+    }
+
+    fn rx_response_metadata(&mut self) -> Metadata {
+        std::mem::take(&mut self.rx_metadata)
     }
 }
 
-fn convert_error(e: impl std::error::Error) -> RPCError {
+pub(super) fn convert_error(e: impl std::error::Error) -> RPCError {
     RPCError::with_cause(
         RPCErrorKind::SerializationError,
         "json serialization or deserialization failed",
@@ -92,23 +326,32 @@ fn convert_error(e: impl std::error::Error) -> RPCError {
     )
 }
 
-fn begin_call(method: MethodId) -> JTXState {
+pub(super) fn begin_call(method: MethodId) -> JTXState {
     JTXState {
         method: method.name,
         params: json!({}),
+        metadata: Metadata::new(),
     }
 }
 
-fn value_for_state(state: &JTXState) -> serde_json::Value {
-    json!({
+pub(super) fn value_for_state(state: &JTXState, id: &str) -> serde_json::Value {
+    let mut value = json!({
         "jsonrpc": "2.0",
         "method": state.method,
         "params": state.params,
-        "id": format!("{}", Uuid::new_v4())
-    })
+        "id": id
+    });
+    if !state.metadata.is_empty() {
+        value["metadata"] = json!(state.metadata);
+    }
+    value
 }
 
-fn add_param(name: &'static str, value: impl Serialize, state: &mut JTXState) -> Result<()> {
+pub(super) fn add_param(
+    name: &'static str,
+    value: impl Serialize,
+    state: &mut JTXState,
+) -> Result<()> {
     state.params.as_object_mut().unwrap().insert(
         name.to_string(),
         serde_json::to_value(value).map_err(convert_error)?,
@@ -116,14 +359,44 @@ fn add_param(name: &'static str, value: impl Serialize, state: &mut JTXState) ->
     Ok(())
 }
 
-fn read_value_from_json<T, R>(reader: R) -> Result<T>
-where
-    for<'de> T: serde::Deserialize<'de>,
-    R: Read,
-{
-    let read = serde_json::de::IoRead::new(reader);
+fn write_response(
+    w: impl Write,
+    id: Option<&Value>,
+    metadata: Metadata,
+    value: impl Serialize,
+    wire: Wire,
+) -> Result<usize> {
+    match id {
+        Some(id) => {
+            let mut response = json!({
+                "jsonrpc": "2.0",
+                "result": serde_json::to_value(value).map_err(convert_error)?,
+                "id": id
+            });
+            if !metadata.is_empty() {
+                response["metadata"] = json!(metadata);
+            }
+            wire.write(w, &response)
+        }
+        None => wire.write(w, &value),
+    }
+}
+
+pub(super) fn read_metadata(state: &JRXState) -> Result<Metadata> {
+    match state.json.get("metadata") {
+        Some(metadata) => serde_json::from_value(metadata.clone()).map_err(convert_error),
+        None => Ok(Metadata::new()),
+    }
+}
+
+// Read the bytes of a single JSON value. The value is scanned
+// without being decoded, so one longer than `max` bytes is read to its
+// end and discarded.
+fn read_message(reader: impl Read, max: usize) -> Result<Vec<u8>> {
+    let mut recorder = Recorder::new(reader, max);
+    let read = serde_json::de::IoRead::new(&mut recorder);
     let mut de = serde_json::de::Deserializer::new(read);
-    serde::de::Deserialize::deserialize(&mut de).map_err(|e| {
+    serde::de::IgnoredAny::deserialize(&mut de).map_err(|e| {
         if e.classify() == serde_json::error::Category::Eof {
             RPCError::new(
                 RPCErrorKind::TransportEOF,
@@ -132,69 +405,290 @@ where
         } else {
             convert_error(e)
         }
-    })
+    })?;
+    recorder.into_message()
 }
 
-impl<C: Read + Write> ServerTransport for JSONTransport<C> {
-    type RXState = JRXState;
+fn eof() -> RPCError {
+    RPCError::new(
+        RPCErrorKind::TransportEOF,
+        "EOF during json deserialization",
+    )
+}
 
-    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
-        let value: Value = self.read_from_channel()?;
-        let method = value
-            .get("method")
-            .ok_or_else(|| {
-                RPCError::new(
-                    RPCErrorKind::SerializationError,
-                    "json is not expected object",
-                )
-            })?
-            .as_str()
-            .ok_or_else(|| {
+fn read_byte(mut reader: impl Read) -> Result<Option<u8>> {
+    let mut b = [0u8];
+    loop {
+        match reader.read(&mut b) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(b[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// Read a line, without its newline, skipping blank lines. A line
+// longer than `max` bytes is read to its end and discarded.
+fn read_line(mut reader: impl Read, max: usize) -> Result<Vec<u8>> {
+    let mut line = Vec::new();
+    let mut len = 0usize;
+    loop {
+        match read_byte(&mut reader)? {
+            None => return Err(eof()),
+            Some(b'\n') => {
+                check_message_size(len, max)?;
+                if !line.iter().all(u8::is_ascii_whitespace) {
+                    return Ok(line);
+                }
+                line.clear();
+                len = 0;
+            }
+            Some(b) => {
+                len += 1;
+                if len <= max {
+                    line.push(b);
+                }
+            }
+        }
+    }
+}
+
+// Whether `headers` ends with the blank line which ends a header
+// section.
+fn ends_headers(headers: &[u8]) -> bool {
+    headers.ends_with(b"\n\n") || headers.ends_with(b"\n\r\n")
+}
+
+// The value of the Content-Length header in a header section.
+fn content_length(headers: &[u8]) -> Result<usize> {
+    let headers = std::str::from_utf8(headers).map_err(convert_error)?;
+    for line in headers.lines() {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        if let (true, Some(value)) = (
+            name.trim().eq_ignore_ascii_case("content-length"),
+            parts.next(),
+        ) {
+            return value.trim().parse().map_err(|_| {
                 RPCError::new(
                     RPCErrorKind::SerializationError,
-                    "json method was not string",
+                    format!("invalid Content-Length {}", value.trim()),
                 )
-            })?
-            .to_string();
-        Ok((PartialMethodId::Name(method), JRXState { json: value }))
+            });
+        }
+    }
+    Err(RPCError::new(
+        RPCErrorKind::SerializationError,
+        "json message has no Content-Length header",
+    ))
+}
+
+// Read a message preceded by headers. A message longer than `max`
+// bytes is read and discarded.
+fn read_content(mut reader: impl Read, max: usize) -> Result<Vec<u8>> {
+    let mut headers = Vec::new();
+    while !ends_headers(&headers) {
+        if headers.len() == MAX_HEADER_SIZE {
+            return Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                "json message headers are too long",
+            ));
+        }
+        headers.push(read_byte(&mut reader)?.ok_or_else(eof)?);
+    }
+    let len = content_length(&headers)?;
+    if len > max {
+        let discarded = io::copy(&mut reader.take(len as u64), &mut io::sink())?;
+        if discarded < len as u64 {
+            return Err(RPCError::new(
+                RPCErrorKind::TransportEOF,
+                "EOF while discarding oversized message",
+            ));
+        }
+        return Err(message_too_large(len, max));
+    }
+    let mut msg = vec![0; len];
+    reader.read_exact(&mut msg)?;
+    Ok(msg)
+}
+
+// Decode a value from the start of a message.
+fn decode<T>(msg: &[u8]) -> Result<T>
+where
+    for<'de> T: serde::Deserialize<'de>,
+{
+    let mut de = serde_json::de::Deserializer::from_slice(msg);
+    serde::de::Deserialize::deserialize(&mut de).map_err(convert_error)
+}
+
+pub(super) fn read_param<T>(name: &'static str, state: &JRXState) -> Result<T>
+where
+    for<'de> T: serde::Deserialize<'de>,
+{
+    let param_val = state
+        .json
+        .get("params")
+        .ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::SerializationError,
+                "json is not expected object",
+            )
+        })?
+        .get(name)
+        .ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!("parameters do not contain {}", name),
+            )
+        })?;
+    serde_json::from_value(param_val.clone()).map_err(convert_error)
+}
+
+impl<C: Read + Write> ServerTransport for JSONTransport<C> {
+    type RXState = JRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+        let (method, state, id) = self.read_call()?;
+        self.current_id = id;
+        Ok((method, state))
+    }
+
+    fn rx_metadata(&mut self, state: &mut JRXState) -> Result<Metadata> {
+        read_metadata(state)
     }
 
     fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
     where
         for<'de> T: serde::Deserialize<'de>,
     {
-        let param_val = state
-            .json
-            .get("params")
-            .ok_or_else(|| {
-                RPCError::new(
-                    RPCErrorKind::SerializationError,
-                    "json is not expected object",
-                )
-            })?
-            .get(name)
-            .ok_or_else(|| {
-                RPCError::new(
-                    RPCErrorKind::SerializationError,
-                    format!("parameters do not contain {}", name),
-                )
-            })?;
-        serde_json::from_value(param_val.clone()).map_err(convert_error)
+        read_param(name, state)
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.tx_metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+        self.tx_response_sized(value)?;
+        Ok(())
+    }
+
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        let metadata = std::mem::take(&mut self.tx_metadata);
+        write_response(
+            &mut self.channel,
+            self.current_id.as_ref(),
+            metadata,
+            value,
+            self.wire,
+        )
+        .map(Some)
+    }
+}
+
+/// A single call read by a JSONTransport used with a
+/// [ConcurrentServer](../server/struct.ConcurrentServer.html).
+pub struct JSONCall<C: Read + Write> {
+    id: Option<Value>,
+    call: Option<(PartialMethodId, JRXState)>,
+    tx_metadata: Metadata,
+    wire: Wire,
+    writer: Arc<SyncMutex<C>>,
+}
+
+impl<C: Read + Write> ServerTransport for JSONCall<C> {
+    type RXState = JRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+        self.call
+            .take()
+            .ok_or_else(|| RPCError::new(RPCErrorKind::IllegalState, "json call has already begun"))
+    }
+
+    fn rx_metadata(&mut self, state: &mut JRXState) -> Result<Metadata> {
+        read_metadata(state)
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        read_param(name, state)
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.tx_metadata = metadata.clone();
+        Ok(())
     }
 
     fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
-        let res = serde_json::to_writer(Write::by_ref(&mut self.channel), &value)
-            .map_err(convert_error)?;
-        self.flush()?;
-        Ok(res)
+        self.tx_response_sized(value)?;
+        Ok(())
+    }
+
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        let metadata = std::mem::take(&mut self.tx_metadata);
+        write_response(
+            &mut *self.writer.lock(),
+            self.id.as_ref(),
+            metadata,
+            value,
+            self.wire,
+        )
+        .map(Some)
+    }
+}
+
+impl<C> ConcurrentServerTransport for JSONTransport<C>
+where
+    C: Read + Write + DuplicateChannel + Send + 'static,
+{
+    type Call = JSONCall<C>;
+
+    fn rx_next_call(&mut self) -> Result<JSONCall<C>> {
+        let writer = match &self.writer {
+            Some(writer) => writer.clone(),
+            None => {
+                let writer = Arc::new(SyncMutex::new(self.channel.duplicate()?));
+                self.writer = Some(writer.clone());
+                writer
+            }
+        };
+        let (method, state, id) = self.read_call()?;
+        Ok(JSONCall {
+            id,
+            call: Some((method, state)),
+            tx_metadata: Metadata::new(),
+            wire: self.wire,
+            writer,
+        })
+    }
+}
+
+/// [WireFormat](trait.WireFormat.html) using JSON, for use with
+/// [SerdeTransport](struct.SerdeTransport.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JSONFormat;
+
+impl WireFormat for JSONFormat {
+    fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(convert_error)
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        serde_json::from_slice(bytes).map_err(convert_error)
     }
 }
 
 #[cfg(feature = "async_client")]
 mod async_client {
     use super::*;
+    use crate::transports::invalid_data;
     use crate::AsyncClientTransport;
-    use bytes::{BufMut, Bytes, BytesMut};
+    use async_trait::async_trait;
+    use bytes::{Buf, BufMut, Bytes, BytesMut};
     use futures::{Sink, SinkExt, Stream, StreamExt};
     use std::io::Result as IoResult;
     use tokio::io::{AsyncRead, AsyncWrite};
@@ -220,12 +714,27 @@ mod async_client {
     where
         A: AsyncRead + AsyncWrite,
     {
+        /// Create a transport over a byte channel, delimiting messages
+        /// as set with `with_framing`.
         pub fn new_unframed(channel: A) -> Self
         where
             A: AsyncRead + AsyncWrite,
         {
             Self::new(Framed::new(channel, JSONCodec::new()))
         }
+
+        /// Set how messages are delimited.
+        pub fn with_framing(mut self, framing: JSONFraming) -> Self {
+            self.channel.codec_mut().framing = framing;
+            self
+        }
+
+        /// Set the maximum size of a message, in bytes. See
+        /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+        pub fn with_max_message_size(mut self, max: usize) -> Self {
+            self.channel.codec_mut().max = max;
+            self
+        }
     }
 
     #[async_trait]
@@ -242,6 +751,15 @@ mod async_client {
             Ok(begin_call(method))
         }
 
+        async fn tx_set_metadata(
+            &mut self,
+            metadata: &Metadata,
+            state: &mut JTXState,
+        ) -> Result<()> {
+            state.metadata = metadata.clone();
+            Ok(())
+        }
+
         async fn tx_add_param(
             &mut self,
             name: &'static str,
@@ -252,7 +770,8 @@ mod async_client {
         }
 
         async fn tx_finalize(&mut self, state: JTXState) -> Result<()> {
-            let j = serde_json::to_vec(&value_for_state(&state)).map_err(convert_error)?;
+            let id = format!("{}", Uuid::new_v4());
+            let j = serde_json::to_vec(&value_for_state(&state, &id)).map_err(convert_error)?;
             self.channel.send(j.into()).await?;
             self.channel.flush().await?;
             Ok(())
@@ -268,24 +787,163 @@ mod async_client {
                     "Could not rx response, unexpcted EOF",
                 ))
             })?;
-            read_value_from_json(&*msg)
+            decode(&msg)
         }
     }
 
-    /// Codec which maps bytes to bytes but only decodes valid
-    /// json. Cannot handle a true stream of json objects one after
-    /// another with no delimeters, but essrpc always has an messages
-    /// from each side.
-    pub struct JSONCodec {}
+    /// Codec splitting a stream of JSON values into the bytes of each
+    /// value, delimited as set by its [JSONFraming](enum.JSONFraming.html).
+    ///
+    /// Unframed values may follow one another without delimiters. The
+    /// codec scans each byte once, tracking the nesting of objects and
+    /// arrays and whether it is within a string, but leaves
+    /// validating the value to its deserialization. A bare number or
+    /// literal at the top level ends at the byte which follows it, or
+    /// at the end of the stream.
+    pub struct JSONCodec {
+        framing: JSONFraming,
+        max: usize,
+        // Bytes of the value being framed which have been scanned, and
+        // the state at the end of them.
+        scanned: usize,
+        depth: usize,
+        in_string: bool,
+        escaped: bool,
+        // Length of the message following the headers read.
+        content_length: Option<usize>,
+    }
     impl JSONCodec {
         fn new() -> Self {
-            JSONCodec {}
+            JSONCodec {
+                framing: JSONFraming::default(),
+                max: DEFAULT_MAX_MESSAGE_SIZE,
+                scanned: 0,
+                depth: 0,
+                in_string: false,
+                escaped: false,
+                content_length: None,
+            }
+        }
+
+        // Split the value of `len` bytes from the start of `src`.
+        fn split_value(&mut self, src: &mut BytesMut, len: usize) -> IoResult<BytesMut> {
+            check_message_size(len, self.max).map_err(invalid_data)?;
+            self.scanned = 0;
+            Ok(src.split_to(len))
+        }
+
+        // Whether a bare number or literal at the top level has begun.
+        fn in_scalar(&self) -> bool {
+            self.scanned > 0 && self.depth == 0 && !self.in_string
+        }
+
+        fn decode_line(&mut self, src: &mut BytesMut) -> IoResult<Option<BytesMut>> {
+            while let Some(i) = src[self.scanned..].iter().position(|&b| b == b'\n') {
+                let len = self.scanned + i;
+                check_message_size(len, self.max).map_err(invalid_data)?;
+                self.scanned = 0;
+                let mut line = src.split_to(len + 1);
+                line.truncate(len);
+                if !line.iter().all(u8::is_ascii_whitespace) {
+                    return Ok(Some(line));
+                }
+            }
+            self.scanned = src.len();
+            check_message_size(src.len(), self.max).map_err(invalid_data)?;
+            Ok(None)
+        }
+
+        fn decode_content(&mut self, src: &mut BytesMut) -> IoResult<Option<BytesMut>> {
+            while self.content_length.is_none() {
+                if self.scanned == src.len() {
+                    if src.len() > MAX_HEADER_SIZE {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "json message headers are too long",
+                        ));
+                    }
+                    return Ok(None);
+                }
+                self.scanned += 1;
+                if ends_headers(&src[..self.scanned]) {
+                    let len = content_length(&src[..self.scanned]).map_err(invalid_data)?;
+                    check_message_size(len, self.max).map_err(invalid_data)?;
+                    src.advance(self.scanned);
+                    self.scanned = 0;
+                    self.content_length = Some(len);
+                }
+            }
+            match self.content_length {
+                Some(len) if src.len() >= len => {
+                    self.content_length = None;
+                    Ok(Some(src.split_to(len)))
+                }
+                Some(len) => {
+                    src.reserve(len - src.len());
+                    Ok(None)
+                }
+                None => Ok(None),
+            }
+        }
+
+        fn decode_unframed(&mut self, src: &mut BytesMut) -> IoResult<Option<BytesMut>> {
+            if self.scanned == 0 {
+                // Skip whitespace between values
+                let start = src
+                    .iter()
+                    .position(|b| !b.is_ascii_whitespace())
+                    .unwrap_or(src.len());
+                src.advance(start);
+            }
+            while self.scanned < src.len() {
+                let i = self.scanned;
+                let b = src[i];
+                if self.in_string {
+                    if self.escaped {
+                        self.escaped = false;
+                    } else if b == b'\\' {
+                        self.escaped = true;
+                    } else if b == b'"' {
+                        self.in_string = false;
+                        if self.depth == 0 {
+                            return self.split_value(src, i + 1).map(Some);
+                        }
+                    }
+                    self.scanned += 1;
+                    continue;
+                }
+                let ends_scalar = b.is_ascii_whitespace() || b"{}[]\",:".contains(&b);
+                if self.in_scalar() && ends_scalar {
+                    return self.split_value(src, i).map(Some);
+                }
+                self.scanned += 1;
+                match b {
+                    b'"' => self.in_string = true,
+                    b'{' | b'[' => self.depth += 1,
+                    b'}' | b']' => {
+                        if self.depth == 0 {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                "unbalanced json",
+                            ));
+                        }
+                        self.depth -= 1;
+                        if self.depth == 0 {
+                            return self.split_value(src, i + 1).map(Some);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            check_message_size(src.len(), self.max).map_err(invalid_data)?;
+            Ok(None)
         }
     }
     impl tokio_util::codec::Encoder<Bytes> for JSONCodec {
         type Error = std::io::Error;
         fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> IoResult<()> {
-            dst.put(item);
+            check_message_size(item.len(), self.max).map_err(invalid_data)?;
+            dst.put(&self.framing.frame(item.to_vec())[..]);
             Ok(())
         }
     }
@@ -293,16 +951,39 @@ mod async_client {
         type Item = BytesMut;
         type Error = std::io::Error;
         fn decode(&mut self, src: &mut BytesMut) -> IoResult<Option<Self::Item>> {
-            let s = match std::str::from_utf8(src) {
-                Ok(s) => s,
-                Err(_) => return Ok(None), // we might have spliced a sequence, so not a fatal error
-            };
-            match json::parse(s) {
-                // Ok, we're done. Remove the bytes from the buffer, return them
-                Ok(_) => Ok(Some(src.split())),
-                // Unexpected end of json just means we haven't read enough bytes yet
-                Err(json::Error::UnexpectedEndOfJson) => Ok(None),
-                Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            match self.framing {
+                JSONFraming::Unframed => self.decode_unframed(src),
+                JSONFraming::NewlineDelimited => self.decode_line(src),
+                JSONFraming::ContentLength => self.decode_content(src),
+            }
+        }
+
+        fn decode_eof(&mut self, src: &mut BytesMut) -> IoResult<Option<Self::Item>> {
+            if let Some(value) = self.decode(src)? {
+                return Ok(Some(value));
+            }
+            match self.framing {
+                JSONFraming::Unframed if self.in_scalar() => {
+                    let len = src.len();
+                    return self.split_value(src, len).map(Some);
+                }
+                // A last line may omit its newline
+                JSONFraming::NewlineDelimited => {
+                    self.scanned = 0;
+                    let line = src.split();
+                    if !line.iter().all(u8::is_ascii_whitespace) {
+                        return Ok(Some(line));
+                    }
+                }
+                _ => {}
+            }
+            if src.is_empty() && self.content_length.is_none() {
+                Ok(None)
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "EOF within json value",
+                ))
             }
         }
     }
@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::internal::SyncMutex;
+use crate::{
+    ClientTransport, Metadata, MethodId, PartialMethodId, RPCError, RPCErrorKind, RPCServer,
+    Result, ServerTransport,
+};
+
+/// How values are passed between client and server by a
+/// [LoopbackTransport](struct.LoopbackTransport.html).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopbackMode {
+    /// Values are converted to an in-memory representation, never
+    /// encoded to bytes.
+    Value,
+    /// Values are round-tripped through bincode, exactly as with
+    /// [BincodeTransport](struct.BincodeTransport.html).
+    #[cfg(feature = "bincode_transport")]
+    Bincode,
+    /// Values are round-tripped through JSON, exactly as with
+    /// [JSONTransport](struct.JSONTransport.html).
+    #[cfg(feature = "json_transport")]
+    JSON,
+}
+
+pub enum Payload {
+    Value(serde_value::Value),
+    #[cfg(any(feature = "bincode_transport", feature = "json_transport"))]
+    Bytes(Vec<u8>),
+}
+
+fn serialization_error(e: impl std::error::Error) -> RPCError {
+    RPCError::with_cause(
+        RPCErrorKind::SerializationError,
+        "loopback serialization failure",
+        e,
+    )
+}
+
+fn encode(mode: LoopbackMode, value: impl Serialize) -> Result<Payload> {
+    match mode {
+        LoopbackMode::Value => Ok(Payload::Value(
+            serde_value::to_value(value).map_err(serialization_error)?,
+        )),
+        #[cfg(feature = "bincode_transport")]
+        LoopbackMode::Bincode => Ok(Payload::Bytes(
+            bincode::serialize(&value).map_err(serialization_error)?,
+        )),
+        #[cfg(feature = "json_transport")]
+        LoopbackMode::JSON => Ok(Payload::Bytes(
+            serde_json::to_vec(&value).map_err(serialization_error)?,
+        )),
+    }
+}
+
+fn decode<T>(mode: LoopbackMode, payload: Payload) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    match (mode, payload) {
+        (_, Payload::Value(v)) => v.deserialize_into().map_err(serialization_error),
+        #[cfg(feature = "bincode_transport")]
+        (LoopbackMode::Bincode, Payload::Bytes(b)) => {
+            bincode::deserialize(&b).map_err(serialization_error)
+        }
+        #[cfg(feature = "json_transport")]
+        (LoopbackMode::JSON, Payload::Bytes(b)) => {
+            serde_json::from_slice(&b).map_err(serialization_error)
+        }
+        #[cfg(any(feature = "bincode_transport", feature = "json_transport"))]
+        _ => Err(RPCError::new(
+            RPCErrorKind::IllegalState,
+            "loopback payload does not match mode",
+        )),
+    }
+}
+
+pub struct Request {
+    method: u32,
+    metadata: Metadata,
+    params: VecDeque<Payload>,
+}
+
+// State shared between the two ends of a loopback.
+#[derive(Default)]
+struct Exchange {
+    request: Option<Request>,
+    response: Option<(Metadata, Payload)>,
+}
+
+/// Server side of a [LoopbackTransport](struct.LoopbackTransport.html).
+/// Created by `LoopbackTransport::new`.
+pub struct LoopbackServerTransport {
+    mode: LoopbackMode,
+    exchange: Arc<SyncMutex<Exchange>>,
+    rx_metadata: Metadata,
+    tx_metadata: Metadata,
+}
+
+impl ServerTransport for LoopbackServerTransport {
+    type RXState = VecDeque<Payload>;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, Self::RXState)> {
+        let request = self.exchange.lock().request.take().ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::IllegalState,
+                "loopback server has no pending call",
+            )
+        })?;
+        self.rx_metadata = request.metadata;
+        Ok((PartialMethodId::Num(request.method), request.params))
+    }
+
+    fn rx_metadata(&mut self, _state: &mut Self::RXState) -> Result<Metadata> {
+        Ok(std::mem::take(&mut self.rx_metadata))
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut Self::RXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        let payload = state.pop_front().ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!("parameters do not contain {}", name),
+            )
+        })?;
+        decode(self.mode, payload)
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.tx_metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+        let payload = encode(self.mode, value)?;
+        let metadata = std::mem::take(&mut self.tx_metadata);
+        self.exchange.lock().response = Some((metadata, payload));
+        Ok(())
+    }
+}
+
+/// Transport connecting a client directly to a server in the same
+/// process, without a channel. The server is owned by the transport
+/// and each call is served on the calling thread during
+/// `tx_finalize`. Useful for tests and for modules which may run
+/// either in-process or out-of-process. Generated servers provide a
+/// `loopback` constructor which creates a client using this transport.
+/// Enable the "loopback_transport" feature to use this.
+pub struct LoopbackTransport<S: RPCServer> {
+    server: S,
+    mode: LoopbackMode,
+    exchange: Arc<SyncMutex<Exchange>>,
+    rx_metadata: Metadata,
+}
+
+impl<S: RPCServer> LoopbackTransport<S> {
+    /// Create a loopback transport. `make_server` is given the
+    /// server side of the transport and must return the server to
+    /// dispatch calls to, for example
+    /// ```ignore
+    /// LoopbackTransport::new(LoopbackMode::Value, |tr| FooRPCServer::new(FooImpl::new(), tr))
+    /// ```
+    pub fn new(mode: LoopbackMode, make_server: impl FnOnce(LoopbackServerTransport) -> S) -> Self {
+        let exchange = Arc::new(SyncMutex::new(Exchange::default()));
+        let server = make_server(LoopbackServerTransport {
+            mode,
+            exchange: exchange.clone(),
+            rx_metadata: Metadata::new(),
+            tx_metadata: Metadata::new(),
+        });
+        LoopbackTransport {
+            server,
+            mode,
+            exchange,
+            rx_metadata: Metadata::new(),
+        }
+    }
+
+    /// Get the server calls are dispatched to.
+    pub fn server(&self) -> &S {
+        &self.server
+    }
+
+    fn begin_call(&self, method: MethodId) -> Request {
+        Request {
+            method: method.num,
+            metadata: Metadata::new(),
+            params: VecDeque::new(),
+        }
+    }
+
+    fn add_param(&self, value: impl Serialize, state: &mut Request) -> Result<()> {
+        state.params.push_back(encode(self.mode, value)?);
+        Ok(())
+    }
+
+    fn finalize(&mut self, state: Request) -> Result<()> {
+        self.exchange.lock().request = Some(state);
+        self.server.serve_single_call()
+    }
+
+    fn response<T>(&mut self) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let (metadata, payload) = self.exchange.lock().response.take().ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::IllegalState,
+                "loopback server did not respond",
+            )
+        })?;
+        self.rx_metadata = metadata;
+        decode(self.mode, payload)
+    }
+}
+
+impl<S: RPCServer> ClientTransport for LoopbackTransport<S> {
+    type TXState = Request;
+    type FinalState = ();
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<Request> {
+        Ok(self.begin_call(method))
+    }
+
+    fn tx_set_metadata(&mut self, metadata: &Metadata, state: &mut Request) -> Result<()> {
+        state.metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_add_param(
+        &mut self,
+        _name: &'static str,
+        value: impl Serialize,
+        state: &mut Request,
+    ) -> Result<()> {
+        self.add_param(value, state)
+    }
+
+    fn tx_finalize(&mut self, state: Request) -> Result<()> {
+        self.finalize(state)
+    }
+
+    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        self.response()
+    }
+
+    fn rx_response_metadata(&mut self) -> Metadata {
+        std::mem::take(&mut self.rx_metadata)
+    }
+}
+
+#[cfg(feature = "async_client")]
+mod async_client {
+    use super::*;
+    use crate::AsyncClientTransport;
+    use async_trait::async_trait;
+
+    /// Like LoopbackTransport except for use as
+    /// AsyncClientTransport. Calls are still served synchronously, on
+    /// the task awaiting `tx_finalize`.
+    pub struct LoopbackAsyncClientTransport<S: RPCServer + Send> {
+        inner: LoopbackTransport<S>,
+    }
+
+    impl<S: RPCServer + Send> LoopbackAsyncClientTransport<S> {
+        /// Create an async loopback transport. See `LoopbackTransport::new`.
+        pub fn new(
+            mode: LoopbackMode,
+            make_server: impl FnOnce(LoopbackServerTransport) -> S,
+        ) -> Self {
+            LoopbackAsyncClientTransport {
+                inner: LoopbackTransport::new(mode, make_server),
+            }
+        }
+
+        /// Get the server calls are dispatched to.
+        pub fn server(&self) -> &S {
+            self.inner.server()
+        }
+    }
+
+    #[async_trait]
+    impl<S: RPCServer + Send> AsyncClientTransport for LoopbackAsyncClientTransport<S> {
+        type TXState = Request;
+        type FinalState = ();
+
+        async fn tx_begin_call(&mut self, method: MethodId) -> Result<Request> {
+            Ok(self.inner.begin_call(method))
+        }
+
+        async fn tx_set_metadata(
+            &mut self,
+            metadata: &Metadata,
+            state: &mut Request,
+        ) -> Result<()> {
+            state.metadata = metadata.clone();
+            Ok(())
+        }
+
+        async fn tx_add_param(
+            &mut self,
+            _name: &'static str,
+            value: impl Serialize + Send + 'async_trait,
+            state: &mut Request,
+        ) -> Result<()> {
+            self.inner.add_param(value, state)
+        }
+
+        async fn tx_finalize(&mut self, state: Request) -> Result<()> {
+            self.inner.finalize(state)
+        }
+
+        async fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+        where
+            for<'de> T: Deserialize<'de>,
+        {
+            self.inner.response()
+        }
+
+        async fn rx_response_metadata(&mut self) -> Metadata {
+            std::mem::take(&mut self.inner.rx_metadata)
+        }
+    }
+}
+
+#[cfg(feature = "async_client")]
+pub use self::async_client::LoopbackAsyncClientTransport;
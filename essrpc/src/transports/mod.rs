@@ -1,31 +1,213 @@
 //! `Transport` implementations and helpers.
+#[cfg(feature = "std")]
 use std::io;
+#[cfg(feature = "std")]
 use std::io::{Read, Write};
 
+use crate::Result;
+#[cfg(any(feature = "std", feature = "postcard_transport"))]
+use crate::{RPCError, RPCErrorKind};
+
+#[cfg(feature = "std")]
+mod format;
+#[cfg(feature = "std")]
+mod framing;
+#[cfg(feature = "async_client")]
+pub use self::format::SerdeAsyncClientTransport;
+#[cfg(feature = "std")]
+pub use self::format::{MessageLayout, SerdeRXState, SerdeTXState, SerdeTransport, WireFormat};
+#[cfg(feature = "std")]
+pub use self::framing::Endian;
+
 #[cfg(feature = "bincode_transport")]
 mod bincode;
 #[cfg(all(feature = "bincode_transport", feature = "async_client"))]
 pub use self::bincode::BincodeAsyncClientTransport;
 #[cfg(feature = "bincode_transport")]
-pub use self::bincode::BincodeTransport;
+pub use self::bincode::{BincodeCall, BincodeFormat, BincodeTransport};
+
+#[cfg(feature = "cbor_transport")]
+mod cbor;
+#[cfg(all(feature = "cbor_transport", feature = "async_client"))]
+pub use self::cbor::CBORAsyncClientTransport;
+#[cfg(feature = "cbor_transport")]
+pub use self::cbor::{CBORFormat, CBORRXState, CBORTXState, CBORTransport};
 
+#[cfg(feature = "http_transport")]
+mod http;
 #[cfg(feature = "json_transport")]
 mod json;
+#[cfg(all(feature = "http_transport", feature = "async_client"))]
+pub use self::http::{HTTPAsyncClientTransport, HTTPAsyncServer, HTTPRequest};
+#[cfg(feature = "http_transport")]
+pub use self::http::{HTTPClientTransport, HTTPServerTransport};
 #[cfg(all(feature = "json_transport", feature = "async_client"))]
 pub use self::json::JSONAsyncClientTransport;
 #[cfg(feature = "json_transport")]
-pub use self::json::JSONTransport;
+pub use self::json::{JRXState, JSONCall, JSONFormat, JSONFraming, JSONTransport};
+
+#[cfg(feature = "msgpack_transport")]
+mod msgpack;
+#[cfg(all(feature = "msgpack_transport", feature = "async_client"))]
+pub use self::msgpack::MsgPackAsyncClientTransport;
+#[cfg(feature = "msgpack_transport")]
+pub use self::msgpack::{MsgPackFormat, MsgPackRXState, MsgPackTXState, MsgPackTransport};
+
+#[cfg(feature = "postcard_transport")]
+mod postcard;
+#[cfg(feature = "postcard_transport")]
+pub use self::postcard::{PostcardRXState, PostcardTXState, PostcardTransport};
+
+#[cfg(feature = "std")]
+mod serial;
+#[cfg(feature = "std")]
+pub use self::serial::{SerialChannel, SerialFraming};
+
+#[cfg(feature = "loopback_transport")]
+mod loopback;
+#[cfg(all(feature = "loopback_transport", feature = "async_client"))]
+pub use self::loopback::LoopbackAsyncClientTransport;
+#[cfg(feature = "loopback_transport")]
+pub use self::loopback::{LoopbackMode, LoopbackServerTransport, LoopbackTransport};
+
+/// Default maximum size of a message, in bytes, for the built-in
+/// transports. Each transport has a `with_max_message_size` method to
+/// change it. A message which exceeds the maximum is discarded by the
+/// receiver, and by the sender before it is sent, with an error of
+/// kind `RPCErrorKind::MessageTooLarge`. A server goes on serving
+/// after discarding a call, responding with the error if its
+/// transport can (see `ServerTransport::tx_error`). Only the HTTP
+/// transport can; with the others the client receives no response,
+/// and the server passes the error to `RPCServer::report_error` (see
+/// the `on_error` method of generated servers). The client and server
+/// should therefore use the same maximum.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+#[cfg(any(feature = "std", feature = "postcard_transport"))]
+pub(crate) fn message_too_large(len: usize, max: usize) -> RPCError {
+    RPCError::new(
+        RPCErrorKind::MessageTooLarge,
+        alloc::format!(
+            "message of {} bytes exceeds maximum size of {} bytes",
+            len,
+            max
+        ),
+    )
+}
+
+// Check the size of a message about to be sent.
+#[cfg(any(feature = "std", feature = "postcard_transport"))]
+pub(crate) fn check_message_size(len: usize, max: usize) -> Result<()> {
+    if len > max {
+        return Err(message_too_large(len, max));
+    }
+    Ok(())
+}
+
+/// Wrap an error for a codec, whose errors are `io::Error`s. The
+/// conversion from `io::Error` to `RPCError` unwraps it.
+#[cfg(all(
+    feature = "async_client",
+    any(feature = "json_transport", feature = "cbor_transport")
+))]
+pub(crate) fn invalid_data(e: RPCError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Reader which records the bytes read from a channel, up to a
+/// maximum beyond which they are counted but discarded. Used to read
+/// self-delimiting messages while scanning them for their end.
+#[cfg(any(feature = "json_transport", feature = "cbor_transport"))]
+pub(crate) struct Recorder<R: Read> {
+    inner: R,
+    max: usize,
+    len: usize,
+    buf: Vec<u8>,
+}
+
+#[cfg(any(feature = "json_transport", feature = "cbor_transport"))]
+impl<R: Read> Recorder<R> {
+    pub(crate) fn new(inner: R, max: usize) -> Self {
+        Recorder {
+            inner,
+            max,
+            len: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The bytes read, or an error if there were more than the maximum.
+    pub(crate) fn into_message(self) -> Result<Vec<u8>> {
+        check_message_size(self.len, self.max)?;
+        Ok(self.buf)
+    }
+}
+
+#[cfg(any(feature = "json_transport", feature = "cbor_transport"))]
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if self.len + n <= self.max {
+            self.buf.extend_from_slice(&buf[..n]);
+        } else {
+            self.buf = Vec::new();
+        }
+        self.len += n;
+        Ok(n)
+    }
+}
+
+/// Minimal blocking byte stream, such as a UART, for transports which
+/// do not require `std`, such as
+/// [PostcardTransport](struct.PostcardTransport.html).
+pub trait ByteStream {
+    /// Read exactly enough bytes to fill `buf`. Should return an
+    /// error of kind `RPCErrorKind::TransportEOF` if the stream ends
+    /// first.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()>;
+    /// Write all of `buf`.
+    fn write_all(&mut self, buf: &[u8]) -> Result<()>;
+    /// Flush buffered output. The default implementation does nothing.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Channel which can be duplicated to read and write from different
+/// threads, such as a socket. Required to use a transport with a
+/// [ConcurrentServer](../server/struct.ConcurrentServer.html).
+#[cfg(feature = "std")]
+pub trait DuplicateChannel: Sized {
+    /// Create a new handle to the same channel.
+    fn duplicate(&self) -> io::Result<Self>;
+}
+
+#[cfg(feature = "std")]
+impl DuplicateChannel for std::net::TcpStream {
+    fn duplicate(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+}
+
+#[cfg(all(unix, feature = "std"))]
+impl DuplicateChannel for std::os::unix::net::UnixStream {
+    fn duplicate(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+}
 
 /// Type which combines a `Read` and a `Write` to implement both
 /// `Read` and `Write` in a single type. May be useful in satisfying
 /// the construction requirements of transports such as
 /// [BincodeTransport](struct.BincodeTransport.html) or
 /// [JSONTransport](struct.JSONTransport.html).
+#[cfg(feature = "std")]
 pub struct ReadWrite<R: Read, W: Write> {
     r: R,
     w: W,
 }
 
+#[cfg(feature = "std")]
 impl<R: Read, W: Write> ReadWrite<R, W> {
     pub fn new(r: R, w: W) -> Self {
         ReadWrite { r, w }
@@ -40,12 +222,14 @@ impl<R: Read, W: Write> ReadWrite<R, W> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<R: Read, W: Write> Read for ReadWrite<R, W> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.r.read(buf)
     }
 }
 
+#[cfg(feature = "std")]
 impl<R: Read, W: Write> Write for ReadWrite<R, W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.w.write(buf)
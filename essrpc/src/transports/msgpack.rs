@@ -0,0 +1,479 @@
+use super::{MessageLayout, SerdeTransport, WireFormat};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Cursor, Read, Write};
+
+use crate::{
+    metadata_unsupported, ClientTransport, Metadata, MethodId, PartialMethodId, RPCError,
+    RPCErrorKind, Result, ServerTransport,
+};
+
+// Serialize a value, with structs as maps keyed by field name if
+// `named` is set and as arrays otherwise.
+fn serialize(w: impl Write, value: impl Serialize, named: bool) -> Result<()> {
+    let result = if named {
+        value.serialize(&mut rmp_serde::Serializer::new(w).with_struct_map())
+    } else {
+        value.serialize(&mut rmp_serde::Serializer::new(w))
+    };
+    result.map_err(|e| {
+        RPCError::with_cause(
+            RPCErrorKind::SerializationError,
+            "msgpack serialization failure",
+            e,
+        )
+    })
+}
+
+fn deserialize<T>(r: impl Read) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    rmp_serde::from_read(r).map_err(|e| {
+        if let rmp_serde::decode::Error::InvalidMarkerRead(e)
+        | rmp_serde::decode::Error::InvalidDataRead(e) = &e
+        {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return RPCError::new(
+                    RPCErrorKind::TransportEOF,
+                    "EOF during msgpack deserialization",
+                );
+            }
+        }
+        RPCError::with_cause(
+            RPCErrorKind::SerializationError,
+            "msgpack deserialization failure",
+            e,
+        )
+    })
+}
+
+fn from_value<T>(value: rmpv::Value) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    rmpv::ext::from_value(value).map_err(|e| {
+        RPCError::with_cause(
+            RPCErrorKind::SerializationError,
+            "msgpack deserialization failure",
+            e,
+        )
+    })
+}
+
+// Layout of MsgPackTransport messages, holding its options.
+#[derive(Clone, Copy, Debug)]
+struct MsgPackLayout {
+    // Client options
+    metadata: bool,
+    named: bool,
+    // Server: how to encode the response to the call being served,
+    // and whether it carries metadata (if the call did).
+    named_response: bool,
+    metadata_response: bool,
+}
+
+impl Default for MsgPackLayout {
+    fn default() -> Self {
+        MsgPackLayout {
+            metadata: false,
+            named: true,
+            named_response: true,
+            metadata_response: false,
+        }
+    }
+}
+
+/// A call being sent by a
+/// [MsgPackTransport](struct.MsgPackTransport.html).
+pub struct MsgPackTXState {
+    method: MethodId,
+    metadata: Metadata,
+    params: Vec<u8>,
+    count: u32,
+}
+
+/// Parameters of a call being served by a
+/// [MsgPackTransport](struct.MsgPackTransport.html).
+pub struct MsgPackRXState {
+    params: Params,
+}
+
+enum Params {
+    Positional(Cursor<Vec<u8>>),
+    Named(HashMap<String, rmpv::Value>),
+}
+
+fn method_id(value: rmpv::Value) -> Result<PartialMethodId> {
+    match value {
+        rmpv::Value::Integer(n) => n
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .map(PartialMethodId::Num),
+        rmpv::Value::String(s) => s.into_str().map(PartialMethodId::Name),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        RPCError::new(
+            RPCErrorKind::SerializationError,
+            "msgpack call has an invalid method id",
+        )
+    })
+}
+
+impl MessageLayout for MsgPackLayout {
+    type TXState = MsgPackTXState;
+    type RXState = MsgPackRXState;
+
+    fn begin_call(&self, method: MethodId) -> Result<MsgPackTXState> {
+        Ok(MsgPackTXState {
+            method,
+            metadata: Metadata::new(),
+            params: Vec::new(),
+            count: 0,
+        })
+    }
+
+    fn set_metadata(&self, metadata: &Metadata, state: &mut MsgPackTXState) -> Result<()> {
+        if !self.metadata {
+            return metadata_unsupported(metadata);
+        }
+        state.metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn add_param(
+        &self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut MsgPackTXState,
+    ) -> Result<()> {
+        if self.named {
+            serialize(&mut state.params, name, true)?;
+        }
+        serialize(&mut state.params, value, self.named)?;
+        state.count += 1;
+        Ok(())
+    }
+
+    fn encode_call(&self, state: MsgPackTXState) -> Result<Vec<u8>> {
+        let mut msg = Vec::new();
+        if self.metadata {
+            serialize(&mut msg, &state.metadata, false)?;
+        }
+        if self.named {
+            serialize(&mut msg, state.method.name, true)?;
+            rmp::encode::write_map_len(&mut msg, state.count).map_err(|e| {
+                RPCError::with_cause(
+                    RPCErrorKind::SerializationError,
+                    "msgpack serialization failure",
+                    e,
+                )
+            })?;
+        } else {
+            serialize(&mut msg, state.method.num, false)?;
+        }
+        msg.extend(state.params);
+        Ok(msg)
+    }
+
+    fn decode_response<T>(&self, msg: Vec<u8>) -> Result<(Metadata, T)>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let mut r = &*msg;
+        let metadata = if self.metadata {
+            deserialize(&mut r)?
+        } else {
+            Metadata::new()
+        };
+        Ok((metadata, deserialize(r)?))
+    }
+
+    fn decode_call(&mut self, msg: Vec<u8>) -> Result<(PartialMethodId, Metadata, MsgPackRXState)> {
+        let mut r = Cursor::new(msg);
+        // A call begins with its metadata map, if any, which cannot
+        // be confused with a method id.
+        let mut first: rmpv::Value = deserialize(&mut r)?;
+        let mut metadata = Metadata::new();
+        self.metadata_response = first.is_map();
+        if self.metadata_response {
+            metadata = from_value(first)?;
+            first = deserialize(&mut r)?;
+        }
+        let method = method_id(first)?;
+        let params = match method {
+            PartialMethodId::Name(_) => Params::Named(deserialize(&mut r)?),
+            PartialMethodId::Num(_) => Params::Positional(r),
+        };
+        self.named_response = matches!(params, Params::Named(_));
+        Ok((method, metadata, MsgPackRXState { params }))
+    }
+
+    fn read_param<T>(&self, name: &'static str, state: &mut MsgPackRXState) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        match &mut state.params {
+            Params::Positional(r) => deserialize(r),
+            Params::Named(params) => match params.remove(name) {
+                Some(value) => from_value(value),
+                None => Err(RPCError::new(
+                    RPCErrorKind::SerializationError,
+                    format!("msgpack call is missing parameter {}", name),
+                )),
+            },
+        }
+    }
+
+    fn encode_response(&self, metadata: &Metadata, value: impl Serialize) -> Result<Vec<u8>> {
+        let mut msg = Vec::new();
+        if self.metadata_response {
+            serialize(&mut msg, metadata, false)?;
+        }
+        serialize(&mut msg, value, self.named_response)?;
+        Ok(msg)
+    }
+}
+
+/// Transport implementation using
+/// [MessagePack](https://msgpack.org) serialization, a compact
+/// self-describing binary format with implementations in many
+/// languages. Can be used over any `Read+Write` channel.
+/// Enable the "msgpack_transport" feature to use this.
+///
+/// This wraps a [SerdeTransport](struct.SerdeTransport.html) with a
+/// [MessageLayout](trait.MessageLayout.html) of its own. Messages are
+/// framed as for
+/// [BincodeTransport](struct.BincodeTransport.html): each is preceded
+/// by its length as a 4-byte little-endian integer. A call then holds
+/// a sequence of MessagePack values:
+/// + The call's [Metadata](../struct.Metadata.html) as a map, if
+///   metadata is enabled with `with_metadata`.
+/// + The method name as a string followed by a map from parameter
+///   name to value (the default), or, with `with_positional_params`,
+///   the method number as an integer followed by each parameter in
+///   turn.
+///
+/// With named parameters, structs are encoded as maps keyed by field
+/// name; with positional parameters, as arrays. A response holds the
+/// metadata map, if the call had one, followed by the return value
+/// encoded in the same style as the call. These options concern only
+/// the client: the server accepts calls in either style.
+pub struct MsgPackTransport<C: Read + Write> {
+    inner: SerdeTransport<C, MsgPackLayout>,
+}
+
+impl<C: Read + Write> MsgPackTransport<C> {
+    pub fn new(channel: C) -> Self {
+        MsgPackTransport {
+            inner: SerdeTransport::with_layout(channel, MsgPackLayout::default()),
+        }
+    }
+
+    /// Carry metadata with each call and response.
+    pub fn with_metadata(mut self) -> Self {
+        self.inner.format_mut().metadata = true;
+        self
+    }
+
+    /// Identify methods by number and send parameters positionally,
+    /// rather than by name.
+    pub fn with_positional_params(mut self) -> Self {
+        self.inner.format_mut().named = false;
+        self
+    }
+
+    /// Set the maximum size of a message, in bytes. See
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.inner = self.inner.with_max_message_size(max);
+        self
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        self.inner.channel()
+    }
+}
+
+impl<C: Read + Write> ClientTransport for MsgPackTransport<C> {
+    type TXState = MsgPackTXState;
+    type FinalState = ();
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<MsgPackTXState> {
+        self.inner.tx_begin_call(method)
+    }
+
+    fn tx_set_metadata(&mut self, metadata: &Metadata, state: &mut MsgPackTXState) -> Result<()> {
+        self.inner.tx_set_metadata(metadata, state)
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut MsgPackTXState,
+    ) -> Result<()> {
+        self.inner.tx_add_param(name, value, state)
+    }
+
+    fn tx_finalize(&mut self, state: MsgPackTXState) -> Result<()> {
+        self.inner.tx_finalize(state)
+    }
+
+    fn rx_response<T>(&mut self, state: ()) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        self.inner.rx_response(state)
+    }
+
+    fn rx_response_metadata(&mut self) -> Metadata {
+        self.inner.rx_response_metadata()
+    }
+}
+
+impl<C: Read + Write> ServerTransport for MsgPackTransport<C> {
+    type RXState = MsgPackRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, MsgPackRXState)> {
+        self.inner.rx_begin_call()
+    }
+
+    fn rx_metadata(&mut self, state: &mut MsgPackRXState) -> Result<Metadata> {
+        self.inner.rx_metadata(state)
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut MsgPackRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        self.inner.rx_read_param(name, state)
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.inner.tx_set_response_metadata(metadata)
+    }
+
+    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+        self.inner.tx_response(value)
+    }
+
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        self.inner.tx_response_sized(value)
+    }
+}
+
+/// [WireFormat](trait.WireFormat.html) using MessagePack, with
+/// structs encoded as maps, for use with
+/// [SerdeTransport](struct.SerdeTransport.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsgPackFormat;
+
+impl WireFormat for MsgPackFormat {
+    fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        serialize(&mut buf, value, true)?;
+        Ok(buf)
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        deserialize(bytes)
+    }
+}
+
+#[cfg(feature = "async_client")]
+mod async_client {
+    use super::*;
+    use crate::transports::SerdeAsyncClientTransport;
+    use crate::AsyncClientTransport;
+    use async_trait::async_trait;
+    use tokio::io::{AsyncRead, AsyncWrite};
+
+    /// Like MsgPackTransport except for use as AsyncClientTransport.
+    /// Can be used over any `AsyncRead+AsyncWrite+Send` channel.
+    pub struct MsgPackAsyncClientTransport<C: AsyncRead + AsyncWrite + Send> {
+        inner: SerdeAsyncClientTransport<C, MsgPackLayout>,
+    }
+
+    impl<C: AsyncRead + AsyncWrite + Send> MsgPackAsyncClientTransport<C> {
+        pub fn new(channel: C) -> Self {
+            MsgPackAsyncClientTransport {
+                inner: SerdeAsyncClientTransport::with_layout(channel, MsgPackLayout::default()),
+            }
+        }
+
+        /// Carry metadata with each call and response. See
+        /// `MsgPackTransport::with_metadata`.
+        pub fn with_metadata(mut self) -> Self {
+            self.inner.format_mut().metadata = true;
+            self
+        }
+
+        /// Identify methods by number and send parameters
+        /// positionally. See `MsgPackTransport::with_positional_params`.
+        pub fn with_positional_params(mut self) -> Self {
+            self.inner.format_mut().named = false;
+            self
+        }
+
+        /// Set the maximum size of a message, in bytes. See
+        /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+        pub fn with_max_message_size(mut self, max: usize) -> Self {
+            self.inner = self.inner.with_max_message_size(max);
+            self
+        }
+    }
+
+    #[async_trait]
+    impl<C: AsyncRead + AsyncWrite + Send + Unpin> AsyncClientTransport
+        for MsgPackAsyncClientTransport<C>
+    {
+        type TXState = MsgPackTXState;
+        type FinalState = ();
+
+        async fn tx_begin_call(&mut self, method: MethodId) -> Result<MsgPackTXState> {
+            self.inner.tx_begin_call(method).await
+        }
+
+        async fn tx_set_metadata(
+            &mut self,
+            metadata: &Metadata,
+            state: &mut MsgPackTXState,
+        ) -> Result<()> {
+            self.inner.tx_set_metadata(metadata, state).await
+        }
+
+        async fn tx_add_param(
+            &mut self,
+            name: &'static str,
+            value: impl Serialize + Send + 'async_trait,
+            state: &mut MsgPackTXState,
+        ) -> Result<()> {
+            self.inner.tx_add_param(name, value, state).await
+        }
+
+        async fn tx_finalize(&mut self, state: MsgPackTXState) -> Result<()> {
+            self.inner.tx_finalize(state).await
+        }
+
+        async fn rx_response<T>(&mut self, state: ()) -> Result<T>
+        where
+            for<'de> T: Deserialize<'de>,
+            T: 'static,
+        {
+            self.inner.rx_response(state).await
+        }
+
+        async fn rx_response_metadata(&mut self) -> Metadata {
+            self.inner.rx_response_metadata().await
+        }
+    }
+}
+
+#[cfg(feature = "async_client")]
+pub use self::async_client::MsgPackAsyncClientTransport;
@@ -0,0 +1,209 @@
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use super::{check_message_size, message_too_large, ByteStream, DEFAULT_MAX_MESSAGE_SIZE};
+use crate::{
+    ClientTransport, Metadata, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result,
+    ServerTransport,
+};
+
+fn serialize(value: &(impl Serialize + ?Sized), msg: Vec<u8>) -> Result<Vec<u8>> {
+    postcard::to_extend(value, msg).map_err(|e| {
+        RPCError::new(
+            RPCErrorKind::SerializationError,
+            format!("postcard serialization failure: {}", e),
+        )
+    })
+}
+
+// Deserialize a value from the start of `msg`, advancing `pos` past it.
+fn deserialize<T>(msg: &[u8], pos: &mut usize) -> Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let (value, rest) = postcard::take_from_bytes(&msg[*pos..]).map_err(|e| {
+        RPCError::new(
+            RPCErrorKind::SerializationError,
+            format!("postcard deserialization failure: {}", e),
+        )
+    })?;
+    *pos = msg.len() - rest.len();
+    Ok(value)
+}
+
+// Read a message of at most `max` bytes. A longer message is read and
+// discarded, leaving the stream at the start of the next one.
+fn read_msg(stream: &mut impl ByteStream, max: usize) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > max {
+        let mut discard = [0u8; 64];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(discard.len());
+            stream.read_exact(&mut discard[..n])?;
+            remaining -= n;
+        }
+        return Err(message_too_large(len, max));
+    }
+    let mut msg = vec![0; len];
+    stream.read_exact(&mut msg)?;
+    Ok(msg)
+}
+
+fn write_msg(stream: &mut impl ByteStream, msg: &[u8], max: usize) -> Result<()> {
+    check_message_size(msg.len(), max)?;
+    stream.write_all(&(msg.len() as u32).to_le_bytes())?;
+    stream.write_all(msg)?;
+    stream.flush()
+}
+
+/// A call being sent by a [PostcardTransport](struct.PostcardTransport.html).
+pub struct PostcardTXState {
+    method: u32,
+    metadata: Metadata,
+    params: Vec<u8>,
+}
+
+/// A call being served by a [PostcardTransport](struct.PostcardTransport.html).
+pub struct PostcardRXState {
+    msg: Vec<u8>,
+    pos: usize,
+}
+
+/// Transport implementation using
+/// [postcard](https://docs.rs/postcard) serialization over a
+/// [ByteStream](trait.ByteStream.html), such as a UART. Unlike the
+/// other transports it does not require `std`, only an
+/// allocator. Enable the "postcard_transport" feature to use this.
+///
+/// Each message is preceded by its length as a 4-byte little-endian
+/// integer. A call holds the method number, the call's
+/// [Metadata](../struct.Metadata.html) and then each parameter, one
+/// after another. A response holds its metadata followed by the
+/// return value. A message longer than the maximum set with
+/// `with_max_message_size` is discarded.
+pub struct PostcardTransport<S: ByteStream> {
+    stream: S,
+    max_message_size: usize,
+    // Metadata of the call being served or the response most
+    // recently received, and of the next response to transmit.
+    rx_metadata: Metadata,
+    tx_metadata: Metadata,
+}
+
+impl<S: ByteStream> PostcardTransport<S> {
+    pub fn new(stream: S) -> Self {
+        PostcardTransport {
+            stream,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            rx_metadata: Metadata::new(),
+            tx_metadata: Metadata::new(),
+        }
+    }
+
+    /// Set the maximum size of a message, in bytes. See
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = max;
+        self
+    }
+
+    /// Get the underlying byte stream
+    pub fn channel(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S: ByteStream> ClientTransport for PostcardTransport<S> {
+    type TXState = PostcardTXState;
+    type FinalState = ();
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<PostcardTXState> {
+        Ok(PostcardTXState {
+            method: method.num,
+            metadata: Metadata::new(),
+            params: Vec::new(),
+        })
+    }
+
+    fn tx_set_metadata(&mut self, metadata: &Metadata, state: &mut PostcardTXState) -> Result<()> {
+        state.metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_add_param(
+        &mut self,
+        _name: &'static str,
+        value: impl Serialize,
+        state: &mut PostcardTXState,
+    ) -> Result<()> {
+        state.params = serialize(&value, core::mem::take(&mut state.params))?;
+        Ok(())
+    }
+
+    fn tx_finalize(&mut self, state: PostcardTXState) -> Result<()> {
+        let mut msg = serialize(&state.method, Vec::new())?;
+        msg = serialize(&state.metadata, msg)?;
+        msg.extend(state.params);
+        write_msg(&mut self.stream, &msg, self.max_message_size)
+    }
+
+    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let msg = read_msg(&mut self.stream, self.max_message_size)?;
+        let mut pos = 0;
+        self.rx_metadata = deserialize(&msg, &mut pos)?;
+        deserialize(&msg, &mut pos)
+    }
+
+    fn rx_response_metadata(&mut self) -> Metadata {
+        core::mem::take(&mut self.rx_metadata)
+    }
+}
+
+impl<S: ByteStream> ServerTransport for PostcardTransport<S> {
+    type RXState = PostcardRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, PostcardRXState)> {
+        let msg = read_msg(&mut self.stream, self.max_message_size)?;
+        let mut pos = 0;
+        let method = deserialize(&msg, &mut pos)?;
+        self.rx_metadata = deserialize(&msg, &mut pos)?;
+        Ok((PartialMethodId::Num(method), PostcardRXState { msg, pos }))
+    }
+
+    fn rx_metadata(&mut self, _state: &mut PostcardRXState) -> Result<Metadata> {
+        Ok(core::mem::take(&mut self.rx_metadata))
+    }
+
+    fn rx_read_param<T>(&mut self, _name: &'static str, state: &mut PostcardRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        deserialize(&state.msg, &mut state.pos)
+    }
+
+    fn tx_set_response_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.tx_metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
+        self.tx_response_sized(value)?;
+        Ok(())
+    }
+
+    fn tx_response_sized(&mut self, value: impl Serialize) -> Result<Option<usize>> {
+        let msg = serialize(&self.tx_metadata, Vec::new())?;
+        let msg = serialize(&value, msg)?;
+        self.tx_metadata = Metadata::new();
+        write_msg(&mut self.stream, &msg, self.max_message_size)?;
+        Ok(Some(msg.len()))
+    }
+}
@@ -0,0 +1,334 @@
+use std::io;
+use std::io::{Read, Write};
+
+use super::DEFAULT_MAX_MESSAGE_SIZE;
+
+/// Delimiters and escaping used by a
+/// [SerialChannel](struct.SerialChannel.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialFraming {
+    /// Consistent Overhead Byte Stuffing. Frames are delimited by
+    /// zero bytes, which do not otherwise appear on the wire.
+    Cobs,
+    /// SLIP (RFC 1055). Frames are delimited by 0xC0 bytes, which are
+    /// escaped where they appear in the frame.
+    Slip,
+}
+
+const COBS_DELIMITER: u8 = 0;
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+const CRC_LEN: usize = 4;
+
+impl SerialFraming {
+    fn delimiter(self) -> u8 {
+        match self {
+            SerialFraming::Cobs => COBS_DELIMITER,
+            SerialFraming::Slip => SLIP_END,
+        }
+    }
+
+    // Encode `payload` and its CRC as a frame, including delimiters.
+    fn encode(self, payload: &[u8]) -> Vec<u8> {
+        let mut data = payload.to_vec();
+        data.extend_from_slice(&crc32(payload).to_le_bytes());
+        // A leading delimiter ends any noise received before the frame.
+        let mut frame = vec![self.delimiter()];
+        match self {
+            SerialFraming::Cobs => cobs_encode(&data, &mut frame),
+            SerialFraming::Slip => slip_encode(&data, &mut frame),
+        }
+        frame.push(self.delimiter());
+        frame
+    }
+
+    // Decode the bytes between two delimiters, returning the payload
+    // if the frame is well formed and its CRC matches.
+    fn decode(self, frame: &[u8]) -> Option<Vec<u8>> {
+        let mut data = match self {
+            SerialFraming::Cobs => cobs_decode(frame)?,
+            SerialFraming::Slip => slip_decode(frame)?,
+        };
+        if data.len() < CRC_LEN {
+            return None;
+        }
+        let crc = data.split_off(data.len() - CRC_LEN);
+        if crc32(&data).to_le_bytes() != crc.as_slice() {
+            return None;
+        }
+        Some(data)
+    }
+}
+
+// CRC-32 (IEEE 802.3), as used by zlib and Ethernet.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn cobs_encode(data: &[u8], out: &mut Vec<u8>) {
+    let mut code_pos = out.len();
+    out.push(0);
+    let mut code = 1u8;
+    for &b in data {
+        if b != 0 {
+            out.push(b);
+            code += 1;
+        }
+        if b == 0 || code == 0xFF {
+            out[code_pos] = code;
+            code_pos = out.len();
+            out.push(0);
+            code = 1;
+        }
+    }
+    out[code_pos] = code;
+}
+
+fn cobs_decode(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(frame.len());
+    let mut i = 0;
+    while i < frame.len() {
+        let code = frame[i] as usize;
+        if code == 0 || i + code > frame.len() {
+            return None;
+        }
+        data.extend_from_slice(&frame[i + 1..i + code]);
+        i += code;
+        if code < 0xFF && i < frame.len() {
+            data.push(0);
+        }
+    }
+    Some(data)
+}
+
+fn slip_encode(data: &[u8], out: &mut Vec<u8>) {
+    for &b in data {
+        match b {
+            SLIP_END => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            b => out.push(b),
+        }
+    }
+}
+
+fn slip_decode(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(frame.len());
+    let mut bytes = frame.iter();
+    while let Some(&b) = bytes.next() {
+        data.push(match b {
+            SLIP_ESC => match bytes.next() {
+                Some(&SLIP_ESC_END) => SLIP_END,
+                Some(&SLIP_ESC_ESC) => SLIP_ESC,
+                _ => return None,
+            },
+            b => b,
+        });
+    }
+    Some(data)
+}
+
+/// Channel which carries each message written to it in a frame of its
+/// own, checked by a CRC, over a byte stream which may corrupt or lose
+/// data, such as a serial port. Wrap the channel of any transport
+/// which flushes after each message, such as
+/// [SerdeTransport](struct.SerdeTransport.html) or
+/// [BincodeTransport](struct.BincodeTransport.html), for example
+/// ```ignore
+/// let transport = SerdeTransport::new(SerialChannel::cobs(port), BincodeFormat);
+/// ```
+///
+/// Bytes written are buffered until `flush`, then sent as one frame:
+/// the bytes followed by their CRC-32 (little-endian), encoded with
+/// COBS or SLIP and surrounded by delimiters. When reading, frames
+/// which are malformed or fail their CRC are dropped, and reading
+/// resumes at the next delimiter, so a corrupted message is lost
+/// without desynchronizing those which follow. A client whose call or
+/// response is lost will wait for a response until the underlying
+/// channel times out. Frames holding more than the maximum set with
+/// `with_max_frame_size` are dropped likewise.
+pub struct SerialChannel<C: Read + Write> {
+    channel: C,
+    framing: SerialFraming,
+    tx: Vec<u8>,
+    // Payload of the frame being read, and how much has been read.
+    rx: Vec<u8>,
+    rx_pos: usize,
+    // Bytes received from the channel but not yet framed, and the
+    // frame they are being gathered into.
+    raw: Vec<u8>,
+    raw_pos: usize,
+    frame: Vec<u8>,
+    // Whether the frame being received is too long and is being
+    // skipped up to the next delimiter.
+    skipping: bool,
+    max_frame_size: usize,
+    dropped_frames: u64,
+}
+
+impl<C: Read + Write> SerialChannel<C> {
+    pub fn new(channel: C, framing: SerialFraming) -> Self {
+        SerialChannel {
+            channel,
+            framing,
+            tx: Vec::new(),
+            rx: Vec::new(),
+            rx_pos: 0,
+            raw: Vec::new(),
+            raw_pos: 0,
+            frame: Vec::new(),
+            skipping: false,
+            max_frame_size: DEFAULT_MAX_MESSAGE_SIZE,
+            dropped_frames: 0,
+        }
+    }
+
+    /// Channel using COBS framing.
+    pub fn cobs(channel: C) -> Self {
+        Self::new(channel, SerialFraming::Cobs)
+    }
+
+    /// Channel using SLIP framing.
+    pub fn slip(channel: C) -> Self {
+        Self::new(channel, SerialFraming::Slip)
+    }
+
+    /// Set the maximum number of bytes a frame received may hold,
+    /// which defaults to
+    /// [DEFAULT_MAX_MESSAGE_SIZE](constant.DEFAULT_MAX_MESSAGE_SIZE.html).
+    pub fn with_max_frame_size(mut self, max: usize) -> Self {
+        self.max_frame_size = max;
+        self
+    }
+
+    /// Get the underlying channel
+    pub fn channel(&self) -> &C {
+        &self.channel
+    }
+
+    pub fn framing(&self) -> SerialFraming {
+        self.framing
+    }
+
+    /// Number of frames received which were malformed, failed their
+    /// CRC or were too long, and so were dropped.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    // Longest encoding of a frame of the maximum size, as SLIP may
+    // double its length.
+    fn max_encoded_len(&self) -> usize {
+        self.max_frame_size
+            .saturating_add(CRC_LEN)
+            .saturating_mul(2)
+    }
+
+    // Add received bytes to the frame being gathered, skipping it if it
+    // becomes too long.
+    fn gather(&mut self, start: usize, end: usize) {
+        if self.skipping {
+            return;
+        }
+        self.frame.extend_from_slice(&self.raw[start..end]);
+        if self.frame.len() > self.max_encoded_len() {
+            self.frame = Vec::new();
+            self.skipping = true;
+        }
+    }
+
+    // Read the next frame with a valid CRC into `self.rx`. Returns
+    // false at EOF.
+    fn read_frame(&mut self) -> io::Result<bool> {
+        let delimiter = self.framing.delimiter();
+        loop {
+            if self.raw_pos == self.raw.len() {
+                let mut buf = [0u8; 4096];
+                let n = self.channel.read(&mut buf)?;
+                self.raw.clear();
+                self.raw.extend_from_slice(&buf[..n]);
+                self.raw_pos = 0;
+                if n == 0 {
+                    // A partial frame at EOF is lost
+                    self.frame.clear();
+                    self.skipping = false;
+                    return Ok(false);
+                }
+            }
+            let start = self.raw_pos;
+            match self.raw[start..].iter().position(|&b| b == delimiter) {
+                None => {
+                    self.gather(start, self.raw.len());
+                    self.raw_pos = self.raw.len();
+                }
+                Some(end) => {
+                    self.gather(start, start + end);
+                    self.raw_pos += end + 1;
+                    if self.skipping {
+                        self.skipping = false;
+                        self.dropped_frames += 1;
+                        continue;
+                    }
+                    if self.frame.is_empty() {
+                        // Between adjacent delimiters
+                        continue;
+                    }
+                    let decoded = self.framing.decode(&self.frame);
+                    self.frame.clear();
+                    match decoded {
+                        Some(payload) if payload.len() <= self.max_frame_size => {
+                            self.rx = payload;
+                            self.rx_pos = 0;
+                            return Ok(true);
+                        }
+                        _ => self.dropped_frames += 1,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<C: Read + Write> Read for SerialChannel<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.rx_pos == self.rx.len() {
+            if !self.read_frame()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.rx.len() - self.rx_pos);
+        buf[..n].copy_from_slice(&self.rx[self.rx_pos..self.rx_pos + n]);
+        self.rx_pos += n;
+        Ok(n)
+    }
+}
+
+impl<C: Read + Write> Write for SerialChannel<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.tx.is_empty() {
+            let frame = self.framing.encode(&self.tx);
+            self.tx.clear();
+            self.channel.write_all(&frame)?;
+        }
+        self.channel.flush()
+    }
+}
@@ -34,6 +34,27 @@ pub trait Foo {
     fn expect_error(&self) -> Result<String, TestError>;
     fn big_buffer(&self) -> Result<Vec<u8>, TestError>;
     fn big_argument(&self, v: Vec<u8>) -> Result<(), TestError>;
+    fn anyhow_err(&self) -> anyhow::Result<i32>;
+    // Not part of FooAsync
+    #[essrpc(local)]
+    fn bar42(&self) -> Result<String, TestError> {
+        self.bar("the answer".to_string(), 42)
+    }
+}
+
+#[essrpc(async)]
+pub trait Repo {
+    type Item: Serialize + for<'de> Deserialize<'de>;
+    fn get(&self, id: u64) -> Result<Self::Item, TestError>;
+}
+
+struct SquareRepo;
+
+impl Repo for SquareRepo {
+    type Item = u64;
+    fn get(&self, id: u64) -> Result<u64, TestError> {
+        Ok(id * id)
+    }
 }
 
 struct FooImpl;
@@ -63,6 +84,9 @@ impl Foo for FooImpl {
     fn big_argument(&self, _v: Vec<u8>) -> Result<(), TestError> {
         Ok(())
     }
+    fn anyhow_err(&self) -> anyhow::Result<i32> {
+        Err(anyhow::anyhow!("async anyhow"))
+    }
 }
 
 #[tokio::test]
@@ -83,6 +107,13 @@ async fn basic_bincode_async() {
     }
 }
 
+#[tokio::test]
+async fn anyhow_error_async() {
+    let foo = json_foo();
+    let e = foo.anyhow_err().await.unwrap_err();
+    assert_eq!(e.to_string(), "async anyhow");
+}
+
 #[tokio::test]
 async fn big_buffer_async() {
     let foo = bincode_foo();
@@ -99,6 +130,17 @@ async fn big_buffer_argument() {
     assert!(res.is_ok());
 }
 
+#[tokio::test]
+async fn associated_types_async() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    thread::spawn(move || RepoRPCServer::new(SquareRepo, BincodeTransport::new(s2)).serve());
+    let repo: RepoAsyncRPCClient<_, u64> =
+        RepoAsyncRPCClient::new(BincodeAsyncClientTransport::new(s1));
+    assert_eq!(repo.get(4).await.unwrap(), 16);
+}
+
 fn json_foo() -> impl FooAsync {
     let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
     // The server isn't actually async, so convert into a non-asyn Unix stream
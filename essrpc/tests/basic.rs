@@ -10,7 +10,7 @@ use serde::{Deserialize, Serialize};
 
 use essrpc::essrpc;
 use essrpc::transports::{BincodeTransport, JSONTransport};
-use essrpc::{RPCClient, RPCErrorKind, RPCServer};
+use essrpc::{RPCClient, RPCErrorKind, RPCServer, RemoteError};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TestError {
@@ -38,6 +38,126 @@ pub trait Foo {
     fn expect_error(&self) -> Result<String, TestError>;
 }
 
+// Private trait with custom names for the generated types.
+#[essrpc(client = "BazClient", server = "BazServer")]
+trait Baz {
+    /// Doubles its argument.
+    fn double(&self, a: i32) -> Result<i32, TestError>;
+    #[cfg(any())]
+    fn configured_out(&self, a: NotATypeThatExists) -> Result<(), TestError>;
+}
+
+struct BazImpl;
+
+impl Baz for BazImpl {
+    fn double(&self, a: i32) -> Result<i32, TestError> {
+        Ok(a * 2)
+    }
+}
+
+#[essrpc]
+pub trait Defaults {
+    const SCALE: i32 = 10;
+
+    fn base(&self) -> Result<i32, TestError>;
+
+    #[essrpc(local)]
+    fn scaled(&self) -> Result<i32, TestError> {
+        Ok(self.base()? * Self::SCALE)
+    }
+
+    fn remote_default(&self) -> Result<String, TestError> {
+        Ok("default".to_string())
+    }
+
+    fn make_impl() -> DefaultsImpl
+    where
+        Self: Sized,
+    {
+        DefaultsImpl
+    }
+}
+
+pub struct DefaultsImpl;
+
+impl Defaults for DefaultsImpl {
+    fn base(&self) -> Result<i32, TestError> {
+        Ok(4)
+    }
+    fn scaled(&self) -> Result<i32, TestError> {
+        Ok(-1)
+    }
+    fn remote_default(&self) -> Result<String, TestError> {
+        Ok("overridden".to_string())
+    }
+}
+
+#[essrpc]
+pub trait Repo {
+    type Item: Serialize + for<'de> Deserialize<'de>;
+    fn get(&self, id: u64) -> Result<Self::Item, TestError>;
+    fn echo(&self, item: Self::Item) -> Result<Self::Item, TestError>;
+}
+
+struct NameRepo;
+
+impl Repo for NameRepo {
+    type Item = String;
+    fn get(&self, id: u64) -> Result<String, TestError> {
+        Ok(format!("name {}", id))
+    }
+    fn echo(&self, item: String) -> Result<String, TestError> {
+        Ok(item)
+    }
+}
+
+struct SquareRepo;
+
+impl Repo for SquareRepo {
+    type Item = u64;
+    fn get(&self, id: u64) -> Result<u64, TestError> {
+        Ok(id * id)
+    }
+    fn echo(&self, item: u64) -> Result<u64, TestError> {
+        Ok(item)
+    }
+}
+
+#[essrpc]
+pub trait Flexible {
+    fn anyhow_ok(&self, a: i32) -> anyhow::Result<i32>;
+    fn anyhow_err(&self) -> anyhow::Result<i32>;
+    fn boxed_err(&self) -> Result<i32, Box<dyn std::error::Error + Send + Sync>>;
+    fn remote_err(&self) -> Result<(), RemoteError>;
+    fn infallible(&self, a: i32) -> i32;
+    #[essrpc(on_error = "default")]
+    fn infallible_default(&self) -> Vec<u8>;
+}
+
+struct FlexibleImpl;
+
+impl Flexible for FlexibleImpl {
+    fn anyhow_ok(&self, a: i32) -> anyhow::Result<i32> {
+        Ok(a + 1)
+    }
+    fn anyhow_err(&self) -> anyhow::Result<i32> {
+        let e = std::io::Error::new(std::io::ErrorKind::Other, "disk on fire");
+        Err(anyhow::Error::new(e).context("cannot read"))
+    }
+    fn boxed_err(&self) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+        Err("boxed".into())
+    }
+    fn remote_err(&self) -> Result<(), RemoteError> {
+        Err(RemoteError::msg("remote"))
+    }
+    fn infallible(&self, a: i32) -> i32 {
+        a * 3
+    }
+    fn infallible_default(&self) -> Vec<u8> {
+        vec![1, 2, 3]
+    }
+}
+
 struct FooImpl;
 
 impl FooImpl {
@@ -145,6 +265,87 @@ fn serve_multiple_eof_on_disconnect_json() {
     }
 }
 
+#[test]
+fn custom_names() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = BazServer::new(BazImpl, BincodeTransport::new(s2));
+        serve.serve_single_call()
+    });
+    let baz = BazClient::new(BincodeTransport::new(s1));
+    assert_eq!(baz.double(21).unwrap(), 42);
+}
+
+#[test]
+fn local_and_remote_defaults() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = DefaultsRPCServer::new(
+            <DefaultsImpl as Defaults>::make_impl(),
+            BincodeTransport::new(s2),
+        );
+        serve.serve()
+    });
+    let client = DefaultsRPCClient::new(BincodeTransport::new(s1));
+    // Runs the default body on the client, which calls base remotely
+    assert_eq!(client.scaled().unwrap(), 40);
+    // Dispatched to the server's implementation
+    assert_eq!(client.remote_default().unwrap(), "overridden");
+}
+
+#[test]
+fn associated_types() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || RepoRPCServer::new(NameRepo, BincodeTransport::new(s2)).serve());
+    let names: RepoRPCClient<_, String> = RepoRPCClient::new(BincodeTransport::new(s1));
+    assert_eq!(names.get(3).unwrap(), "name 3");
+    assert_eq!(names.echo("x".to_string()).unwrap(), "x");
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || RepoRPCServer::new(SquareRepo, JSONTransport::new(s2)).serve());
+    let squares: RepoRPCClient<_, u64> = RepoRPCClient::new(JSONTransport::new(s1));
+    assert_eq!(squares.get(3).unwrap(), 9);
+    assert_eq!(squares.echo(5).unwrap(), 5);
+}
+
+#[test]
+fn flexible_error_types() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || FlexibleRPCServer::new(FlexibleImpl, BincodeTransport::new(s2)).serve());
+    let client = FlexibleRPCClient::new(BincodeTransport::new(s1));
+
+    assert_eq!(client.anyhow_ok(1).unwrap(), 2);
+    let e = client.anyhow_err().unwrap_err();
+    assert!(e.to_string().starts_with("cannot read"));
+    assert_eq!(e.chain().nth(1).unwrap().to_string(), "disk on fire");
+    assert_eq!(client.boxed_err().unwrap_err().to_string(), "boxed");
+    let e = client.remote_err().unwrap_err();
+    assert_eq!(e.to_string(), "remote");
+    assert!(e.rpc_error_kind().is_none());
+    assert_eq!(client.infallible(3), 9);
+    assert_eq!(client.infallible_default(), vec![1, 2, 3]);
+}
+
+#[test]
+fn flexible_rpc_failures() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    drop(s2);
+    let client = FlexibleRPCClient::new(BincodeTransport::new(s1));
+    assert!(client.anyhow_ok(1).is_err());
+    let e = client.remote_err().unwrap_err();
+    assert!(e.rpc_error_kind().is_some());
+    assert_eq!(client.infallible_default(), Vec::<u8>::new());
+}
+
+#[test]
+#[should_panic(expected = "RPC call to infallible failed")]
+fn infallible_panics_on_rpc_failure() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    drop(s2);
+    let client = FlexibleRPCClient::new(BincodeTransport::new(s1));
+    client.infallible(1);
+}
+
 fn client42<T: Foo>(client: &T) {
     match client.bar("the answer".to_string(), 42) {
         Ok(result) => assert_eq!("the answer is 42", result),
@@ -169,3 +370,55 @@ fn bincode_foo() -> impl Foo {
     });
     FooRPCClient::new(BincodeTransport::new(s1))
 }
+
+// Return types named like `Result`, in a module of their own to import
+// `anyhow::Result`.
+mod result_names {
+    use anyhow::{bail, Result};
+    use serde::{Deserialize, Serialize};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    use essrpc::essrpc;
+    use essrpc::transports::BincodeTransport;
+    use essrpc::{RPCClient, RPCServer};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    pub struct SearchResult {
+        hits: u32,
+    }
+
+    #[essrpc]
+    pub trait Search {
+        fn search(&self, query: String) -> SearchResult;
+        fn halve(&self, a: i32) -> Result<i32>;
+    }
+
+    struct SearchImpl;
+
+    impl Search for SearchImpl {
+        fn search(&self, query: String) -> SearchResult {
+            SearchResult {
+                hits: query.len() as u32,
+            }
+        }
+        fn halve(&self, a: i32) -> Result<i32> {
+            if a % 2 != 0 {
+                bail!("{} is odd", a);
+            }
+            Ok(a / 2)
+        }
+    }
+
+    #[test]
+    fn result_names() {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || SearchRPCServer::new(SearchImpl, BincodeTransport::new(s2)).serve());
+        let client = SearchRPCClient::new(BincodeTransport::new(s1));
+        // A struct whose name ends in Result is returned as it is
+        assert_eq!(client.search("abc".to_string()), SearchResult { hits: 3 });
+        // An imported Result alias carries a dynamic error
+        assert_eq!(client.halve(4).unwrap(), 2);
+        assert_eq!(client.halve(3).unwrap_err().to_string(), "3 is odd");
+    }
+}
@@ -0,0 +1,249 @@
+use std::io::{Cursor, Write};
+use std::os::unix::net::UnixStream;
+use std::result::Result;
+use std::{fmt, thread};
+
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{BincodeAsyncClientTransport, BincodeTransport, Endian};
+use essrpc::{
+    AsyncRPCClient, ClientTransport, MethodId, RPCClient, RPCError, RPCErrorKind, RPCServer,
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TestError {
+    msg: String,
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for TestError {}
+impl From<essrpc::RPCError> for TestError {
+    fn from(error: essrpc::RPCError) -> Self {
+        TestError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[essrpc(async, sync)]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError>;
+    fn sum(&self, values: Vec<u64>) -> u64;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError> {
+        Ok(format!("{} is {}", a, b))
+    }
+    fn sum(&self, values: Vec<u64>) -> u64 {
+        values.iter().sum()
+    }
+}
+
+// Configure a transport with every encoding option.
+fn configure<C: std::io::Read + Write>(tr: BincodeTransport<C>) -> BincodeTransport<C> {
+    tr.with_varint_encoding()
+        .with_endian(Endian::Big)
+        .with_trailing_bytes_rejected()
+        .with_frame_header()
+}
+
+fn serve(
+    tr: BincodeTransport<UnixStream>,
+    calls: usize,
+) -> thread::JoinHandle<Result<(), RPCError>> {
+    thread::spawn(move || {
+        let mut server = FooRPCServer::new(FooImpl, tr);
+        for _ in 0..calls {
+            server.serve_single_call()?;
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn bincode_options() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(configure(BincodeTransport::new(s2)), 2);
+    let client = FooRPCClient::new(configure(BincodeTransport::new(s1)));
+    assert_eq!(
+        client.bar("the answer".to_string(), -42).unwrap(),
+        "the answer is -42"
+    );
+    assert_eq!(client.sum(vec![1, 2, 3, u64::MAX - 6]), u64::MAX);
+}
+
+#[test]
+fn bincode_options_with_request_ids_and_metadata() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(
+        configure(BincodeTransport::new(s2))
+            .with_request_ids()
+            .with_metadata(),
+        1,
+    );
+    let client = FooRPCClient::new(
+        configure(BincodeTransport::new(s1))
+            .with_request_ids()
+            .with_metadata(),
+    );
+    assert_eq!(client.sum(vec![1, 2]), 3);
+}
+
+#[tokio::test]
+async fn bincode_options_async() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    serve(configure(BincodeTransport::new(s2)), 2);
+    let client = FooAsyncRPCClient::new(
+        BincodeAsyncClientTransport::new(s1)
+            .with_varint_encoding()
+            .with_endian(Endian::Big)
+            .with_trailing_bytes_rejected()
+            .with_frame_header(),
+    );
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).await.unwrap(),
+        "the answer is 42"
+    );
+    assert_eq!(client.sum(vec![1, 2, 3]).await, 6);
+}
+
+#[tokio::test]
+async fn bincode_request_ids_async() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let mut s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    // A response to a request the client has not made, which it drops
+    let stale = [5u32.to_le_bytes(), 99u32.to_le_bytes()].concat();
+    s2.write_all(&(stale.len() as u32).to_le_bytes()).unwrap();
+    s2.write_all(&stale).unwrap();
+    serve(BincodeTransport::new(s2).with_request_ids(), 2);
+    let client = FooAsyncRPCClient::new(BincodeAsyncClientTransport::new(s1).with_request_ids());
+    assert_eq!(client.sum(vec![1, 2]).await, 3);
+    assert_eq!(client.bar("x".to_string(), 1).await.unwrap(), "x is 1");
+}
+
+// The bytes written by the client for a call to `bar("x", 1)`.
+fn call_bytes(
+    configure: impl FnOnce(BincodeTransport<Cursor<Vec<u8>>>) -> BincodeTransport<Cursor<Vec<u8>>>,
+) -> Vec<u8> {
+    let mut tr = configure(BincodeTransport::new(Cursor::new(Vec::new())));
+    let mut state = tr
+        .tx_begin_call(MethodId {
+            name: "bar",
+            num: 0,
+        })
+        .unwrap();
+    tr.tx_add_param("a", "x", &mut state).unwrap();
+    tr.tx_add_param("b", 1i32, &mut state).unwrap();
+    tr.tx_finalize(state).unwrap();
+    tr.channel().get_ref().clone()
+}
+
+#[test]
+fn bincode_frame_format() {
+    // The default format is unchanged.
+    let mut expected = vec![17, 0, 0, 0];
+    expected.extend_from_slice(&[0, 0, 0, 0]);
+    expected.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, b'x']);
+    expected.extend_from_slice(&[1, 0, 0, 0]);
+    assert_eq!(call_bytes(|tr| tr), expected);
+
+    // Big-endian varint, with a header and request id. The i32 is
+    // zigzag encoded.
+    assert_eq!(
+        call_bytes(|tr| configure(tr).with_request_ids()),
+        vec![0, 0, 0, 10, 1, 0x07, 0, 0, 0, 0, 0, 1, b'x', 2]
+    );
+}
+
+#[test]
+fn bincode_frame_header_mismatch() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server = serve(BincodeTransport::new(s2).with_frame_header(), 1);
+    let mut tr = BincodeTransport::new(s1)
+        .with_frame_header()
+        .with_varint_encoding();
+    let state = tr
+        .tx_begin_call(MethodId {
+            name: "sum",
+            num: 1,
+        })
+        .unwrap();
+    tr.tx_finalize(state).unwrap();
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::SerializationError);
+    assert!(e.to_string().contains("flags"), "{}", e);
+}
+
+#[test]
+fn bincode_frame_version() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = serve(BincodeTransport::new(s2).with_frame_header(), 1);
+    s1.write_all(&[6, 0, 0, 0, 2, 0, 1, 0, 0, 0]).unwrap();
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::SerializationError);
+    assert!(e.to_string().contains("version 2"), "{}", e);
+}
+
+// Send a call to `sum` with a parameter too many.
+fn call_with_extra_param(tr: &mut BincodeTransport<UnixStream>) -> u32 {
+    let mut state = tr
+        .tx_begin_call(MethodId {
+            name: "sum",
+            num: 1,
+        })
+        .unwrap();
+    tr.tx_add_param("values", vec![1u64, 2], &mut state)
+        .unwrap();
+    tr.tx_add_param("extra", 3u64, &mut state).unwrap();
+    tr.tx_finalize(state).unwrap()
+}
+
+#[test]
+fn bincode_trailing_bytes() {
+    // Accepted by default
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(BincodeTransport::new(s2), 1);
+    let mut tr = BincodeTransport::new(s1);
+    let id = call_with_extra_param(&mut tr);
+    let sum: u64 = tr.rx_response(id).unwrap();
+    assert_eq!(sum, 3);
+
+    // Rejected by the server, which does not execute the call
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server = serve(BincodeTransport::new(s2).with_trailing_bytes_rejected(), 1);
+    call_with_extra_param(&mut BincodeTransport::new(s1));
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::SerializationError);
+    assert!(e.to_string().contains("trailing"), "{}", e);
+}
+
+#[test]
+fn bincode_trailing_bytes_response() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(BincodeTransport::new(s2), 1);
+    let mut tr = BincodeTransport::new(s1).with_trailing_bytes_rejected();
+    let mut state = tr
+        .tx_begin_call(MethodId {
+            name: "sum",
+            num: 1,
+        })
+        .unwrap();
+    tr.tx_add_param("values", vec![1u64, 2], &mut state)
+        .unwrap();
+    let id = tr.tx_finalize(state).unwrap();
+    // The response is a u64, not a u32
+    let e = tr.rx_response::<u32>(id).unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::SerializationError);
+}
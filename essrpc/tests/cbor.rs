@@ -0,0 +1,210 @@
+use std::fmt;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::result::Result;
+use std::thread;
+
+use ciborium::value::Value;
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{CBORAsyncClientTransport, CBORTransport};
+use essrpc::{AsyncRPCClient, RPCClient, RPCErrorKind, RPCServer};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TestError {
+    msg: String,
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for TestError {}
+impl From<essrpc::RPCError> for TestError {
+    fn from(error: essrpc::RPCError) -> Self {
+        TestError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[essrpc(sync, async)]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError>;
+    fn expect_error(&self) -> Result<String, TestError>;
+    fn flip(&self, p: Point) -> Result<Point, TestError>;
+    fn big_buffer(&self) -> Result<Vec<u8>, TestError>;
+    fn user(&self) -> Result<String, TestError>;
+}
+
+struct FooImpl;
+
+const BIG_BUFFER_SIZE: usize = 256 * 1024;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError> {
+        Ok(format!("{} is {}", a, b))
+    }
+    fn expect_error(&self) -> Result<String, TestError> {
+        Err(TestError {
+            msg: "iamerror".to_string(),
+        })
+    }
+    fn flip(&self, p: Point) -> Result<Point, TestError> {
+        Ok(Point { x: p.y, y: p.x })
+    }
+    fn big_buffer(&self) -> Result<Vec<u8>, TestError> {
+        Ok(vec![0; BIG_BUFFER_SIZE])
+    }
+    fn user(&self) -> Result<String, TestError> {
+        let metadata = essrpc::server::request_metadata();
+        Ok(metadata.get("user").unwrap_or_default().to_string())
+    }
+}
+
+fn serve(s: UnixStream) {
+    thread::spawn(move || FooRPCServer::new(FooImpl, CBORTransport::new(s)).serve());
+}
+
+#[test]
+fn basic_cbor() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(s2);
+    let client = FooRPCClient::new(CBORTransport::new(s1));
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    assert_eq!(client.expect_error().unwrap_err().msg, "iamerror");
+    assert_eq!(
+        client.flip(Point { x: 1, y: 2 }).unwrap(),
+        Point { x: 2, y: 1 }
+    );
+    assert_eq!(client.big_buffer().unwrap().len(), BIG_BUFFER_SIZE);
+}
+
+#[test]
+fn cbor_metadata() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(s2);
+    let mut tr = CBORTransport::new(s1);
+    let mut state = essrpc::ClientTransport::tx_begin_call(
+        &mut tr,
+        essrpc::MethodId {
+            name: "user",
+            num: 4,
+        },
+    )
+    .unwrap();
+    let metadata: essrpc::Metadata = vec![("user", "alice")].into_iter().collect();
+    essrpc::ClientTransport::tx_set_metadata(&mut tr, &metadata, &mut state).unwrap();
+    essrpc::ClientTransport::tx_finalize(&mut tr, state).unwrap();
+    let user: Result<String, TestError> =
+        essrpc::ClientTransport::rx_response(&mut tr, ()).unwrap();
+    assert_eq!(user.unwrap(), "alice");
+}
+
+#[test]
+fn cbor_wire_format() {
+    // Two calls written at once as a CBOR sequence
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    serve(s2);
+    let call = |b: i32| {
+        Value::Map(vec![
+            (Value::from("method"), Value::from("bar")),
+            (
+                Value::from("params"),
+                Value::Map(vec![
+                    (Value::from("b"), Value::from(b)),
+                    (Value::from("a"), Value::from("n")),
+                ]),
+            ),
+        ])
+    };
+    let mut msg = Vec::new();
+    ciborium::ser::into_writer(&call(1), &mut msg).unwrap();
+    ciborium::ser::into_writer(&call(2), &mut msg).unwrap();
+    s1.write_all(&msg).unwrap();
+    for b in 1..=2 {
+        let response: Result<String, TestError> = ciborium::de::from_reader(&mut s1).unwrap();
+        assert_eq!(response.unwrap(), format!("n is {}", b));
+    }
+}
+
+#[test]
+fn cbor_eof() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        FooRPCServer::new(FooImpl, CBORTransport::new(s2)).serve_single_call()
+    });
+    drop(s1);
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+}
+
+#[test]
+fn cbor_eof_within_item() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        FooRPCServer::new(FooImpl, CBORTransport::new(s2)).serve_single_call()
+    });
+    // A map of two members with only one present
+    s1.write_all(&[0xa2, 0x61, b'a', 0x01]).unwrap();
+    drop(s1);
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+}
+
+#[test]
+fn cbor_invalid_call() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        FooRPCServer::new(FooImpl, CBORTransport::new(s2)).serve_single_call()
+    });
+    ciborium::ser::into_writer(&Value::from(7), &mut s1).unwrap();
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::SerializationError);
+}
+
+#[tokio::test]
+async fn basic_cbor_async() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    // The server isn't actually async, so convert into a non-async Unix stream
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    serve(s2);
+    let client = FooAsyncRPCClient::new(CBORAsyncClientTransport::new(s1));
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).await.unwrap(),
+        "the answer is 42"
+    );
+    assert_eq!(client.expect_error().await.unwrap_err().msg, "iamerror");
+    assert_eq!(
+        client.flip(Point { x: 3, y: 4 }).await.unwrap(),
+        Point { x: 4, y: 3 }
+    );
+    assert_eq!(client.big_buffer().await.unwrap().len(), BIG_BUFFER_SIZE);
+}
+
+#[tokio::test]
+async fn cbor_async_eof() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    thread::spawn(move || {
+        let mut s2 = s2;
+        let _: Value = ciborium::de::from_reader(&mut s2).unwrap();
+    });
+    let client = FooAsyncRPCClient::new(CBORAsyncClientTransport::new(s1));
+    let e = client.bar("a".to_string(), 1).await.unwrap_err();
+    assert!(e.msg.contains("EOF"), "{}", e);
+}
@@ -0,0 +1,263 @@
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use essrpc::client::{AfterCall, ClientCall, ClientInterceptor};
+use essrpc::essrpc;
+use essrpc::transports::{BincodeAsyncClientTransport, BincodeTransport, JSONTransport};
+use essrpc::{AsyncRPCClient, RPCClient, RPCError, RPCErrorKind, RPCServer, RemoteError};
+
+#[essrpc(sync, async)]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, RemoteError>;
+    fn fail(&self) -> Result<u32, RemoteError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, RemoteError> {
+        Ok(format!("{} is {}", a, b))
+    }
+
+    fn fail(&self) -> Result<u32, RemoteError> {
+        Err(RemoteError::msg("iffy"))
+    }
+}
+
+// Records each call, its parameters and its error in a shared log.
+struct Recorder {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl ClientInterceptor for Recorder {
+    fn before_call(&mut self, call: &mut ClientCall) -> Result<(), RPCError> {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{} before {}", self.name, call.method().name));
+        Ok(())
+    }
+
+    fn on_param(
+        &mut self,
+        _call: &ClientCall,
+        name: &'static str,
+        value: &dyn essrpc::server::erased_serde::Serialize,
+    ) {
+        self.log.lock().unwrap().push(format!(
+            "{} param {}={}",
+            self.name,
+            name,
+            serde_json::to_string(value).unwrap()
+        ));
+    }
+
+    fn after_call(&mut self, call: &ClientCall, error: Option<&mut RPCError>) -> AfterCall {
+        self.log.lock().unwrap().push(format!(
+            "{} after {} {:?}",
+            self.name,
+            call.method().name,
+            error.map(|e| e.to_string())
+        ));
+        AfterCall::Done
+    }
+}
+
+// Fails the first attempt of every call before it is transmitted.
+struct FailFirst;
+
+impl ClientInterceptor for FailFirst {
+    fn before_call(&mut self, call: &mut ClientCall) -> Result<(), RPCError> {
+        if call.attempt() == 0 {
+            Err(RPCError::new(RPCErrorKind::TransportError, "flaky"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Retries calls failing with a transport error.
+struct Retry {
+    max: u32,
+}
+
+impl ClientInterceptor for Retry {
+    fn after_call(&mut self, call: &ClientCall, error: Option<&mut RPCError>) -> AfterCall {
+        match error {
+            Some(e) if e.kind == RPCErrorKind::TransportError && call.attempt() < self.max => {
+                AfterCall::Retry
+            }
+            _ => AfterCall::Done,
+        }
+    }
+}
+
+// Replaces errors with a friendlier one.
+struct Unavailable;
+
+impl ClientInterceptor for Unavailable {
+    fn after_call(&mut self, _call: &ClientCall, error: Option<&mut RPCError>) -> AfterCall {
+        if let Some(e) = error {
+            let cause = std::mem::replace(e, RPCError::new(RPCErrorKind::Other, ""));
+            *e = RPCError::with_cause(RPCErrorKind::Other, "service unavailable", cause);
+        }
+        AfterCall::Done
+    }
+}
+
+// Records the duration of each call.
+struct Latency {
+    start: Option<Instant>,
+    durations: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl ClientInterceptor for Latency {
+    fn before_call(&mut self, _call: &mut ClientCall) -> Result<(), RPCError> {
+        self.start = Some(Instant::now());
+        Ok(())
+    }
+
+    fn after_call(&mut self, _call: &ClientCall, _error: Option<&mut RPCError>) -> AfterCall {
+        let elapsed = self.start.take().unwrap().elapsed();
+        self.durations.lock().unwrap().push(elapsed);
+        AfterCall::Done
+    }
+}
+
+fn foo_client() -> FooRPCClient<JSONTransport<UnixStream>> {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl, JSONTransport::new(s2)).serve());
+    FooRPCClient::new(JSONTransport::new(s1))
+}
+
+#[test]
+fn client_interceptor_order() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let client = foo_client()
+        .with_interceptor(Recorder {
+            name: "outer",
+            log: log.clone(),
+        })
+        .with_interceptor(Recorder {
+            name: "inner",
+            log: log.clone(),
+        });
+    assert_eq!(client.bar("a".to_string(), 1).unwrap(), "a is 1");
+    // Errors returned by the implementation are not seen as call errors
+    assert!(client.fail().is_err());
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "outer before bar",
+            "inner before bar",
+            r#"outer param a="a""#,
+            r#"inner param a="a""#,
+            "outer param b=1",
+            "inner param b=1",
+            "inner after bar None",
+            "outer after bar None",
+            "outer before fail",
+            "inner before fail",
+            "inner after fail None",
+            "outer after fail None",
+        ]
+    );
+}
+
+#[test]
+fn client_interceptor_retries() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    // Only a single call reaches the server
+    let server = thread::spawn(move || {
+        FooRPCServer::new(FooImpl, BincodeTransport::new(s2)).serve_single_call()
+    });
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let client = FooRPCClient::new(BincodeTransport::new(s1))
+        .with_interceptor(Retry { max: 3 })
+        .with_interceptor(Recorder {
+            name: "r",
+            log: log.clone(),
+        })
+        .with_interceptor(FailFirst);
+    assert_eq!(client.bar("a".to_string(), 1).unwrap(), "a is 1");
+    server.join().unwrap().unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "r before bar",
+            r#"r after bar Some("flaky")"#,
+            "r before bar",
+            r#"r param a="a""#,
+            "r param b=1",
+            "r after bar None",
+        ]
+    );
+}
+
+#[test]
+fn client_interceptor_gives_up() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    drop(s2);
+    let attempts = Arc::new(Mutex::new(Vec::new()));
+    let client = FooRPCClient::new(BincodeTransport::new(s1))
+        .with_interceptor(Retry { max: 2 })
+        .with_interceptor(Recorder {
+            name: "r",
+            log: attempts.clone(),
+        });
+    assert!(client.bar("a".to_string(), 1).is_err());
+    let befores = attempts
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|l| l.starts_with("r before"))
+        .count();
+    assert_eq!(befores, 3);
+}
+
+#[test]
+fn client_interceptor_transforms_error() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    drop(s2);
+    let client = FooRPCClient::new(BincodeTransport::new(s1)).with_interceptor(Unavailable);
+    let e = client.bar("a".to_string(), 1).unwrap_err();
+    assert_eq!(e.rpc_error_kind(), Some(&RPCErrorKind::Other));
+    assert!(e.to_string().starts_with("service unavailable"));
+}
+
+#[tokio::test]
+async fn client_interceptor_async() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl, BincodeTransport::new(s2)).serve());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let durations = Arc::new(Mutex::new(Vec::new()));
+    let client = FooAsyncRPCClient::new(BincodeAsyncClientTransport::new(s1))
+        .with_interceptor(Latency {
+            start: None,
+            durations: durations.clone(),
+        })
+        .with_interceptor(Recorder {
+            name: "r",
+            log: log.clone(),
+        })
+        .with_interceptor(FailFirst)
+        .with_interceptor(Retry { max: 1 });
+    assert_eq!(client.bar("a".to_string(), 1).await.unwrap(), "a is 1");
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "r before bar",
+            r#"r after bar Some("flaky")"#,
+            "r before bar",
+            r#"r param a="a""#,
+            "r param b=1",
+            "r after bar None",
+        ]
+    );
+    assert_eq!(durations.lock().unwrap().len(), 2);
+}
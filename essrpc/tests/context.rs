@@ -0,0 +1,191 @@
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use essrpc::essrpc;
+use essrpc::server::{ConcurrentServer, Listener, PeerAddr, ShutdownHandle};
+use essrpc::transports::{BincodeAsyncClientTransport, BincodeTransport};
+use essrpc::{AsyncRPCClient, CallContext, RPCClient, RPCServer, RemoteError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Seen {
+    greeting: String,
+    connection_id: Option<u64>,
+    tcp_peer: bool,
+    user: Option<String>,
+    timeout_ms: Option<u64>,
+    cancelled: bool,
+}
+
+#[essrpc(sync, async, arc)]
+pub trait Foo {
+    fn seen(
+        &self,
+        greeting: String,
+        #[essrpc(context)] ctx: &CallContext,
+        metadata: u32,
+    ) -> Result<Seen, RemoteError>;
+    fn plain(&self, a: i32) -> Result<i32, RemoteError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn seen(
+        &self,
+        greeting: String,
+        ctx: &CallContext,
+        metadata: u32,
+    ) -> Result<Seen, RemoteError> {
+        Ok(Seen {
+            greeting: format!("{} {}", greeting, metadata),
+            connection_id: ctx.connection_id(),
+            tcp_peer: matches!(ctx.peer(), Some(PeerAddr::Tcp(_))),
+            user: ctx.metadata().get("user").map(String::from),
+            timeout_ms: ctx
+                .deadline()
+                .map(|d| d.saturating_duration_since(Instant::now()).as_millis() as u64),
+            cancelled: ctx.is_cancelled(),
+        })
+    }
+
+    fn plain(&self, a: i32) -> Result<i32, RemoteError> {
+        Ok(a)
+    }
+}
+
+fn foo_client() -> FooRPCClient<BincodeTransport<UnixStream>> {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl, BincodeTransport::new(s2).with_metadata()).serve()
+    });
+    FooRPCClient::new(BincodeTransport::new(s1).with_metadata())
+}
+
+#[test]
+fn context_empty() {
+    let client = foo_client();
+    let seen = client
+        .seen("hi".to_string(), &CallContext::new(), 3)
+        .unwrap();
+    assert_eq!(
+        seen,
+        Seen {
+            greeting: "hi 3".to_string(),
+            connection_id: None,
+            tcp_peer: false,
+            user: None,
+            timeout_ms: None,
+            cancelled: false,
+        }
+    );
+    // Other methods are unaffected
+    assert_eq!(client.plain(4).unwrap(), 4);
+}
+
+#[test]
+fn context_metadata_and_deadline() {
+    let client = foo_client();
+    let ctx = CallContext::new()
+        .with_metadata("user", "alice")
+        .with_timeout(Duration::from_secs(60));
+    let seen = client.seen("hi".to_string(), &ctx, 3).unwrap();
+    assert_eq!(seen.user.as_deref(), Some("alice"));
+    let timeout_ms = seen.timeout_ms.unwrap();
+    assert!(timeout_ms > 50_000 && timeout_ms <= 60_000);
+    assert!(!seen.cancelled);
+}
+
+#[test]
+fn context_deadline_passed() {
+    let client = foo_client();
+    let ctx = CallContext::new().with_deadline(Instant::now());
+    let seen = client.seen("hi".to_string(), &ctx, 3).unwrap();
+    assert_eq!(seen.timeout_ms, Some(0));
+    assert!(seen.cancelled);
+}
+
+#[test]
+fn context_listener_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = ShutdownHandle::new();
+    let listener_shutdown = shutdown.clone();
+    let imp = Arc::new(FooImpl);
+    let server = thread::spawn(move || {
+        Listener::new(listener)
+            .shutdown(listener_shutdown)
+            .serve_bincode(move |tr, _conn| FooRPCServer::new(imp.clone(), tr))
+    });
+    let first = FooRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+    let second = FooRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+    let seen1 = first.seen("a".to_string(), &CallContext::new(), 1).unwrap();
+    let seen2 = second
+        .seen("b".to_string(), &CallContext::new(), 2)
+        .unwrap();
+    assert!(seen1.tcp_peer && seen2.tcp_peer);
+    assert!(seen1.connection_id.is_some());
+    assert_ne!(seen1.connection_id, seen2.connection_id);
+    assert_eq!(
+        first
+            .seen("a".to_string(), &CallContext::new(), 1)
+            .unwrap()
+            .connection_id,
+        seen1.connection_id
+    );
+    shutdown.trigger();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn context_concurrent_listener_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        Listener::new(listener).serve(|stream, _conn| {
+            let tr = BincodeTransport::new(stream).with_request_ids();
+            ConcurrentServer::new(tr, 2, |call| FooRPCServer::new(FooImpl, call))
+        })
+    });
+    let client = FooRPCClient::new(
+        BincodeTransport::new(TcpStream::connect(addr).unwrap()).with_request_ids(),
+    );
+    let seen = client
+        .seen("a".to_string(), &CallContext::new(), 1)
+        .unwrap();
+    assert!(seen.tcp_peer);
+    assert!(seen.connection_id.is_some());
+}
+
+#[test]
+fn context_cancelled_by_shutdown() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let shutdown = ShutdownHandle::new();
+    shutdown.trigger();
+    let mut server = FooRPCServer::new(FooImpl, BincodeTransport::new(s2)).with_shutdown(shutdown);
+    let handle = thread::spawn(move || server.serve_single_call());
+    let client = FooRPCClient::new(BincodeTransport::new(s1));
+    let seen = client
+        .seen("a".to_string(), &CallContext::new(), 1)
+        .unwrap();
+    assert!(seen.cancelled);
+    handle.join().unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn context_async() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl, BincodeTransport::new(s2).with_metadata()).serve()
+    });
+    let client = FooAsyncRPCClient::new(BincodeAsyncClientTransport::new(s1).with_metadata());
+    let ctx = CallContext::new().with_metadata("user", "bob");
+    let seen = client.seen("hi".to_string(), &ctx, 5).await.unwrap();
+    assert_eq!(seen.greeting, "hi 5");
+    assert_eq!(seen.user.as_deref(), Some("bob"));
+}
@@ -0,0 +1,118 @@
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+
+use essrpc::essrpc;
+use essrpc::server::{Listener, PeerAllowlist, PeerCredentials};
+use essrpc::transports::BincodeTransport;
+use essrpc::{CallContext, RPCClient, RPCErrorKind, RemoteError};
+
+#[essrpc]
+pub trait Foo {
+    fn whoami(&self, #[essrpc(context)] ctx: &CallContext) -> Result<(u32, u32, u32), RemoteError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn whoami(&self, ctx: &CallContext) -> Result<(u32, u32, u32), RemoteError> {
+        let c = ctx
+            .peer_credentials()
+            .ok_or_else(|| RemoteError::msg("no credentials"))?;
+        Ok((c.uid(), c.gid(), c.pid().unwrap()))
+    }
+}
+
+// Our own uid and gid, as given to a file we create.
+fn own_ids(path: &PathBuf) -> (u32, u32) {
+    let metadata = std::fs::metadata(path).unwrap();
+    (metadata.uid(), metadata.gid())
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "essrpc-credentials-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn serve_unix(
+    path: &PathBuf,
+    allowlist: Option<PeerAllowlist>,
+) -> FooRPCClient<BincodeTransport<UnixStream>> {
+    let listener = UnixListener::bind(path).unwrap();
+    thread::spawn(move || {
+        Listener::new(listener).serve_bincode(move |tr, _conn| {
+            let server = FooRPCServer::new(FooImpl, tr);
+            match &allowlist {
+                Some(allowlist) => server.with_interceptor(allowlist.clone()),
+                None => server,
+            }
+        })
+    });
+    FooRPCClient::new(BincodeTransport::new(UnixStream::connect(path).unwrap()))
+}
+
+#[test]
+fn credentials_of_stream() {
+    let (s1, _s2) = UnixStream::pair().unwrap();
+    let credentials = PeerCredentials::of(&s1).unwrap();
+    assert_eq!(credentials.pid(), Some(std::process::id()));
+}
+
+#[test]
+fn credentials_in_context() {
+    let path = socket_path("context");
+    let client = serve_unix(&path, None);
+    let (uid, gid) = own_ids(&path);
+    assert_eq!(
+        client.whoami(&CallContext::new()).unwrap(),
+        (uid, gid, std::process::id())
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn allowlist_permits() {
+    let path = socket_path("permits");
+    let listener = UnixListener::bind(&path).unwrap();
+    let (uid, gid) = own_ids(&path);
+    drop(listener);
+    std::fs::remove_file(&path).unwrap();
+
+    let client = serve_unix(&path, Some(PeerAllowlist::new().allow_uid(uid)));
+    assert_eq!(client.whoami(&CallContext::new()).unwrap().0, uid);
+    std::fs::remove_file(&path).unwrap();
+
+    let client = serve_unix(&path, Some(PeerAllowlist::new().allow_gid(gid)));
+    assert_eq!(client.whoami(&CallContext::new()).unwrap().1, gid);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn allowlist_denies() {
+    let path = socket_path("denies");
+    let client = serve_unix(&path, Some(PeerAllowlist::new().allow_uid(u32::MAX - 1)));
+    let e = client.whoami(&CallContext::new()).unwrap_err();
+    assert_eq!(e.rpc_error_kind(), Some(&RPCErrorKind::PermissionDenied));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn allowlist_denies_without_credentials() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        Listener::new(listener).serve_bincode(|tr, _conn| {
+            FooRPCServer::new(FooImpl, tr).with_interceptor(PeerAllowlist::new().allow_uid(0))
+        })
+    });
+    let client = FooRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+    let e = client.whoami(&CallContext::new()).unwrap_err();
+    assert_eq!(e.rpc_error_kind(), Some(&RPCErrorKind::PermissionDenied));
+}
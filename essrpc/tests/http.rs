@@ -0,0 +1,548 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::result::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use essrpc::essrpc;
+use essrpc::server::{Listener, PeerAddr, ShutdownHandle};
+use essrpc::transports::{
+    HTTPAsyncClientTransport, HTTPAsyncServer, HTTPClientTransport, HTTPServerTransport,
+};
+use essrpc::{AsyncRPCClient, RPCClient, RPCError, RPCErrorKind, RPCServer};
+
+#[essrpc(async, sync, arc)]
+pub trait Foo {
+    fn greet(&self, name: String) -> Result<String, RPCError>;
+    fn add(&self, a: i32, b: i32) -> Result<i32, RPCError>;
+    // Panics, so that it fails with an internal error
+    fn fail(&self) -> i32;
+}
+
+// A trait the server does not implement, to call unknown methods.
+#[essrpc(async, sync)]
+pub trait Other {
+    fn missing(&self) -> Result<(), RPCError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn greet(&self, name: String) -> Result<String, RPCError> {
+        Ok(format!("hello {}", name))
+    }
+    fn add(&self, a: i32, b: i32) -> Result<i32, RPCError> {
+        Ok(a + b)
+    }
+    fn fail(&self) -> i32 {
+        panic!("failed")
+    }
+}
+
+const MAX: usize = 1024;
+
+// Serve on a listener on 127.0.0.1, returning its address.
+fn serve() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let imp = Arc::new(FooImpl);
+    thread::spawn(move || {
+        Listener::new(listener).serve_http(move |tr, _conn| {
+            FooRPCServer::new(imp.clone(), tr.with_max_message_size(MAX))
+        })
+    });
+    addr
+}
+
+async fn serve_async() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let imp = Arc::new(FooImpl);
+    let server = HTTPAsyncServer::new(listener, move |tr| FooRPCServer::new(imp.clone(), tr))
+        .with_max_message_size(MAX);
+    let addr = server.listener().local_addr().unwrap();
+    tokio::spawn(server.serve());
+    addr
+}
+
+fn url(addr: SocketAddr) -> String {
+    format!("http://{}/rpc", addr)
+}
+
+// Send a raw request on a new connection, returning the status, head
+// and body of the response.
+fn request(addr: SocketAddr, request: &str) -> (u16, String, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head[9..12].parse().unwrap();
+    (status, head.to_string(), body.to_string())
+}
+
+fn post(addr: SocketAddr, body: &str) -> (u16, Value) {
+    let (status, _, body) = request(
+        addr,
+        &format!(
+            "POST /rpc HTTP/1.1\r\nHost: test\r\nContent-Type: application/json\r\n\
+             Connection: close\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+    );
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&body).unwrap()
+    };
+    (status, body)
+}
+
+fn check_client(client: &impl Foo) {
+    assert_eq!(client.greet("world".to_string()).unwrap(), "hello world");
+    assert_eq!(client.add(2, 3).unwrap(), 5);
+}
+
+#[test]
+fn http_client_server() {
+    let addr = serve();
+    let foo = FooRPCClient::new(HTTPClientTransport::new(&url(addr)).unwrap());
+    check_client(&foo);
+    // The connection is still served after an unknown method
+    let other = OtherRPCClient::new(HTTPClientTransport::new(&url(addr)).unwrap());
+    assert_eq!(
+        other.missing().unwrap_err().kind,
+        RPCErrorKind::UnknownMethod
+    );
+    assert_eq!(
+        other.missing().unwrap_err().kind,
+        RPCErrorKind::UnknownMethod
+    );
+    check_client(&foo);
+}
+
+#[tokio::test]
+async fn http_async_client_server() {
+    let addr = serve_async().await;
+    let foo = FooAsyncRPCClient::new(HTTPAsyncClientTransport::new(&url(addr)).unwrap());
+    assert_eq!(foo.greet("world".to_string()).await.unwrap(), "hello world");
+    assert_eq!(foo.add(2, 3).await.unwrap(), 5);
+    let other = OtherAsyncRPCClient::new(HTTPAsyncClientTransport::new(&url(addr)).unwrap());
+    assert_eq!(
+        other.missing().await.unwrap_err().kind,
+        RPCErrorKind::UnknownMethod
+    );
+    assert_eq!(foo.add(4, 5).await.unwrap(), 9);
+}
+
+#[tokio::test]
+async fn http_sync_client_async_server() {
+    let addr = serve_async().await;
+    tokio::task::spawn_blocking(move || {
+        check_client(&FooRPCClient::new(
+            HTTPClientTransport::new(&url(addr)).unwrap(),
+        ));
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn http_client_reconnects() {
+    // A server which closes the connection after each request
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut server = FooRPCServer::new(FooImpl, HTTPServerTransport::new(stream.unwrap()));
+            server.serve_single_call().unwrap();
+        }
+    });
+    // The next request may be written before the connection is closed
+    let foo = FooRPCClient::new(
+        HTTPClientTransport::new(&url(addr))
+            .unwrap()
+            .retry_unanswered(true),
+    );
+    check_client(&foo);
+    check_client(&foo);
+}
+
+#[test]
+fn http_client_replaces_idle_connection() {
+    // A server which closes the connection after each request, then
+    // lets the client make its next call
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (closed, wait_closed) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut server = FooRPCServer::new(FooImpl, HTTPServerTransport::new(stream.unwrap()));
+            server.serve_single_call().unwrap();
+            drop(server);
+            closed.send(()).unwrap();
+        }
+    });
+    let foo = FooRPCClient::new(HTTPClientTransport::new(&url(addr)).unwrap());
+    for i in 0..3 {
+        assert_eq!(foo.add(i, 1).unwrap(), i + 1);
+        wait_closed.recv().unwrap();
+    }
+}
+
+// Serve one call on each connection, then read the next request and
+// close the connection without responding to it. Returns the address
+// and the number of requests received.
+fn serve_unanswered() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let count = requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let tr = HTTPServerTransport::new(stream.try_clone().unwrap());
+            FooRPCServer::new(FooImpl, tr).serve_single_call().unwrap();
+            count.fetch_add(1, Ordering::SeqCst);
+            if stream.read(&mut [0; 1024]).unwrap() > 0 {
+                count.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+    (addr, requests)
+}
+
+#[test]
+fn http_client_does_not_resend_unanswered() {
+    let (addr, requests) = serve_unanswered();
+    let foo = FooRPCClient::new(HTTPClientTransport::new(&url(addr)).unwrap());
+    assert_eq!(foo.add(1, 2).unwrap(), 3);
+    let e = foo.add(3, 4).unwrap_err();
+    assert!(
+        matches!(
+            e.kind,
+            RPCErrorKind::TransportEOF | RPCErrorKind::TransportError
+        ),
+        "{:?}",
+        e
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn http_client_retry_unanswered() {
+    let (addr, _) = serve_unanswered();
+    let foo = FooRPCClient::new(
+        HTTPClientTransport::new(&url(addr))
+            .unwrap()
+            .retry_unanswered(true),
+    );
+    assert_eq!(foo.add(1, 2).unwrap(), 3);
+    // Sent again on a new connection
+    assert_eq!(foo.add(3, 4).unwrap(), 7);
+}
+
+fn check_statuses(addr: SocketAddr) {
+    let call = r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":7}"#;
+    let (status, response) = post(addr, call);
+    assert_eq!(status, 200);
+    assert_eq!(
+        response,
+        json!({"jsonrpc": "2.0", "result": {"Ok": 3}, "id": 7})
+    );
+
+    let (status, response) = post(addr, r#"{"jsonrpc":"2.0","method":"nope","id":1}"#);
+    assert_eq!(status, 404);
+    assert_eq!(response["error"]["code"], -32601);
+    assert_eq!(response["id"], 1);
+
+    let (status, response) = post(
+        addr,
+        r#"{"jsonrpc":"2.0","method":"add","params":{"a":1},"id":2}"#,
+    );
+    assert_eq!(status, 400);
+    assert_eq!(response["error"]["code"], -32602);
+
+    let (status, response) = post(addr, r#"{"jsonrpc":"2.0","method":"fail","id":4}"#);
+    assert_eq!(status, 500);
+    assert_eq!(response["error"]["code"], -32603);
+
+    let (status, response) = post(addr, "{not json");
+    assert_eq!(status, 400);
+    assert_eq!(response["error"]["code"], -32700);
+    assert_eq!(response["id"], Value::Null);
+
+    let (status, response) = post(addr, r#"{"jsonrpc":"2.0","id":3}"#);
+    assert_eq!(status, 400);
+    assert_eq!(response["error"]["code"], -32600);
+
+    let (status, response) = post(addr, "[]");
+    assert_eq!(status, 400);
+    assert_eq!(response["error"]["code"], -32600);
+
+    // A notification has no response
+    let (status, response) = post(
+        addr,
+        r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2}}"#,
+    );
+    assert_eq!(status, 204);
+    assert_eq!(response, Value::Null);
+}
+
+fn check_batch(addr: SocketAddr) {
+    let (status, response) = post(
+        addr,
+        r#"[
+            {"jsonrpc":"2.0","method":"greet","params":{"name":"a"},"id":"x"},
+            {"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2}},
+            1,
+            {"jsonrpc":"2.0","method":"nope","id":"y"},
+            {"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":"z"}
+        ]"#,
+    );
+    assert_eq!(status, 200);
+    assert_eq!(
+        response
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| (
+                r["id"].clone(),
+                r["result"].clone(),
+                r["error"]["code"].clone()
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            (Value::Null, Value::Null, json!(-32600)),
+            (json!("x"), json!({"Ok": "hello a"}), Value::Null),
+            (json!("y"), Value::Null, json!(-32601)),
+            (json!("z"), json!({"Ok": 3}), Value::Null),
+        ]
+    );
+
+    // A batch of notifications has no response
+    let (status, _) = post(
+        addr,
+        r#"[{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2}}]"#,
+    );
+    assert_eq!(status, 204);
+
+    // A batch of only failed calls still succeeds as a whole
+    let (status, response) = post(
+        addr,
+        r#"[
+            {"jsonrpc":"2.0","method":"add","params":{"a":1},"id":1},
+            {"jsonrpc":"2.0","method":"fail","id":2}
+        ]"#,
+    );
+    assert_eq!(status, 200);
+    assert_eq!(
+        response
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["error"]["code"].clone())
+            .collect::<Vec<_>>(),
+        vec![json!(-32602), json!(-32603)]
+    );
+}
+
+fn check_rejections(addr: SocketAddr) {
+    let (status, head, _) = request(addr, "GET /rpc HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(status, 405);
+    assert!(head.contains("Allow: POST"), "{}", head);
+
+    let (status, _, _) = request(addr, "POST /rpc HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(status, 411);
+
+    let (status, _, _) = request(
+        addr,
+        "POST /rpc HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\
+         Connection: close\r\n\r\n{}",
+    );
+    assert_eq!(status, 415);
+
+    let (status, _, _) = request(
+        addr,
+        "POST /rpc HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n",
+    );
+    assert_eq!(status, 501);
+
+    let (status, _, _) = request(addr, "nonsense\r\n\r\n");
+    assert_eq!(status, 400);
+
+    // Ambiguous body lengths
+    let (status, _, _) = request(
+        addr,
+        "POST /rpc HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\n{} ",
+    );
+    assert_eq!(status, 400);
+
+    let (status, _, _) = request(
+        addr,
+        "POST /rpc HTTP/1.1\r\nContent-Length: 2, 3\r\n\r\n{} ",
+    );
+    assert_eq!(status, 400);
+
+    let (status, _, _) = request(
+        addr,
+        "POST /rpc HTTP/1.1\r\nContent-Length: 2\r\nTransfer-Encoding: chunked\r\n\r\n\
+         2\r\n{}\r\n0\r\n\r\n",
+    );
+    assert_eq!(status, 400);
+
+    // The same length may be repeated
+    let call = r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":1}"#;
+    let (status, _, body) = request(
+        addr,
+        &format!(
+            "POST /rpc HTTP/1.1\r\nContent-Length: {0}\r\nContent-Length: {0}\r\n\
+             Connection: close\r\n\r\n{1}",
+            call.len(),
+            call
+        ),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(
+        serde_json::from_str::<Value>(&body).unwrap()["result"],
+        json!({"Ok": 3})
+    );
+
+    // Rejected requests are discarded, and the connection goes on to
+    // serve the next request
+    let call = r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":1}"#;
+    let (status, head, body) = request(
+        addr,
+        &format!(
+            "POST /rpc HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}\
+             POST /rpc HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            2 * MAX,
+            " ".repeat(2 * MAX),
+            call.len(),
+            call
+        ),
+    );
+    assert_eq!(status, 413);
+    let (first, second) = body.split_once("HTTP/1.1 ").unwrap();
+    assert_eq!(first, "Payload Too Large");
+    assert!(second.starts_with("200 OK"), "{}\r\n\r\n{}", head, body);
+    assert!(second.contains(r#""result":{"Ok":3}"#), "{}", second);
+}
+
+#[test]
+fn http_status_codes() {
+    check_statuses(serve());
+}
+
+#[test]
+fn http_batch() {
+    check_batch(serve());
+}
+
+#[test]
+fn http_rejections() {
+    check_rejections(serve());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn http_async_server() {
+    let addr = serve_async().await;
+    tokio::task::spawn_blocking(move || {
+        check_statuses(addr);
+        check_batch(addr);
+        check_rejections(addr);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn http_async_server_shutdown() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = ShutdownHandle::new();
+    let (errors, reported) = mpsc::channel();
+    let errors = Mutex::new(errors);
+    let server = HTTPAsyncServer::new(listener, |tr| FooRPCServer::new(FooImpl, tr))
+        .shutdown(shutdown.clone())
+        .on_error(move |conn, e| {
+            let peer = conn.map(|conn| conn.peer().clone());
+            errors.lock().unwrap().send((peer, e.kind)).unwrap();
+        });
+    let serving = tokio::spawn(server.serve());
+
+    // A connection which is left open, waiting for another request
+    let foo = FooAsyncRPCClient::new(HTTPAsyncClientTransport::new(&url(addr)).unwrap());
+    assert_eq!(foo.add(2, 3).await.unwrap(), 5);
+
+    // Errors on a connection are reported with the connection
+    let local = tokio::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let local = stream.local_addr().unwrap();
+        stream.write_all(b"nonsense\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+        local
+    })
+    .await
+    .unwrap();
+    let (peer, kind) = reported.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(peer, Some(PeerAddr::Tcp(peer)) if peer == local));
+    assert_ne!(kind, RPCErrorKind::TransportEOF);
+
+    shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(5), serving)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn http_expect_continue() {
+    let addr = serve();
+    let call = r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":1}"#;
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST /rpc HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        call.len()
+    )
+    .unwrap();
+    let mut interim = [0u8; 25];
+    stream.read_exact(&mut interim).unwrap();
+    assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(call.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+}
+
+#[test]
+fn http_client_limits() {
+    let addr = serve();
+    let foo = FooRPCClient::new(
+        HTTPClientTransport::new(&url(addr))
+            .unwrap()
+            .with_max_message_size(MAX),
+    );
+    assert_eq!(
+        foo.greet("x".repeat(2 * MAX)).unwrap_err().kind,
+        RPCErrorKind::MessageTooLarge
+    );
+    check_client(&foo);
+}
+
+#[test]
+fn http_invalid_url() {
+    for url in ["https://127.0.0.1/", "127.0.0.1:80", "http:///rpc"] {
+        assert!(HTTPClientTransport::new(url).is_err(), "{}", url);
+    }
+}
@@ -0,0 +1,185 @@
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use essrpc::essrpc;
+use essrpc::server::{CallInfo, Interceptor};
+use essrpc::transports::{BincodeTransport, JSONTransport};
+use essrpc::{
+    ClientTransport, MethodId, RPCClient, RPCError, RPCErrorKind, RPCServer, RemoteError,
+    ServerTransport,
+};
+
+#[essrpc]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, RemoteError>;
+    fn secret(&self) -> Result<String, RemoteError>;
+    fn count(&self) -> u32;
+}
+
+#[derive(Default)]
+struct FooImpl {
+    secrets: Arc<Mutex<u32>>,
+}
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, RemoteError> {
+        Ok(format!("{} is {}", a, b))
+    }
+
+    fn secret(&self) -> Result<String, RemoteError> {
+        *self.secrets.lock().unwrap() += 1;
+        Ok("hunter2".to_string())
+    }
+
+    fn count(&self) -> u32 {
+        7
+    }
+}
+
+// Records each call and its response in a shared log.
+struct Recorder {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl<TR: ServerTransport> Interceptor<TR> for Recorder {
+    fn before_call(&mut self, call: &CallInfo, _state: &mut TR::RXState) -> Result<(), RPCError> {
+        self.log.lock().unwrap().push(format!(
+            "{} before {}",
+            self.name,
+            call.method_name().unwrap()
+        ));
+        Ok(())
+    }
+
+    fn after_call(
+        &mut self,
+        call: &CallInfo,
+        response: &dyn essrpc::server::erased_serde::Serialize,
+        size: Option<usize>,
+    ) {
+        self.log.lock().unwrap().push(format!(
+            "{} after {} {} {:?}",
+            self.name,
+            call.method_name().unwrap(),
+            serde_json::to_string(response).unwrap(),
+            size
+        ));
+    }
+}
+
+// Rejects calls of `secret`.
+struct DenySecret;
+
+impl<TR: ServerTransport> Interceptor<TR> for DenySecret {
+    fn before_call(&mut self, call: &CallInfo, _state: &mut TR::RXState) -> Result<(), RPCError> {
+        match call.method_name() {
+            Some("secret") | Some("count") => {
+                Err(RPCError::new(RPCErrorKind::Other, "access denied"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Rejects calls of `bar` with a negative `b`, inspecting the request.
+struct NonNegative;
+
+impl<C: std::io::Read + std::io::Write> Interceptor<JSONTransport<C>> for NonNegative {
+    fn before_call(
+        &mut self,
+        _call: &CallInfo,
+        state: &mut essrpc::transports::JRXState,
+    ) -> Result<(), RPCError> {
+        match state.json()["params"]["b"].as_i64() {
+            Some(b) if b < 0 => Err(RPCError::new(RPCErrorKind::Other, "b is negative")),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn interceptor_order() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let server_log = log.clone();
+    let server = thread::spawn(move || {
+        let mut server = FooRPCServer::new(FooImpl::default(), BincodeTransport::new(s2))
+            .with_interceptor(Recorder {
+                name: "outer",
+                log: server_log.clone(),
+            })
+            .with_interceptor(Recorder {
+                name: "inner",
+                log: server_log,
+            });
+        server.serve_single_call()
+    });
+    let client = FooRPCClient::new(BincodeTransport::new(s1));
+    assert_eq!(client.bar("a".to_string(), 1).unwrap(), "a is 1");
+    server.join().unwrap().unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "outer before bar",
+            "inner before bar",
+            r#"inner after bar {"Ok":"a is 1"} Some(18)"#,
+            r#"outer after bar {"Ok":"a is 1"} Some(18)"#,
+        ]
+    );
+}
+
+#[test]
+fn interceptor_rejects_call() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let imp = FooImpl::default();
+    let secrets = imp.secrets.clone();
+    thread::spawn(move || {
+        FooRPCServer::new(imp, BincodeTransport::new(s2))
+            .with_interceptor(DenySecret)
+            .serve()
+    });
+    let client = FooRPCClient::new(BincodeTransport::new(s1));
+    let e = client.secret().unwrap_err();
+    assert_eq!(e.to_string(), "access denied");
+    assert_eq!(e.rpc_error_kind(), Some(&RPCErrorKind::Other));
+    assert_eq!(*secrets.lock().unwrap(), 0);
+    // Other methods are unaffected
+    assert_eq!(client.bar("a".to_string(), 1).unwrap(), "a is 1");
+}
+
+#[test]
+fn interceptor_rejects_infallible_call() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let mut client_tr = BincodeTransport::new(s1);
+    let state = client_tr
+        .tx_begin_call(MethodId {
+            name: "count",
+            num: 2,
+        })
+        .unwrap();
+    client_tr.tx_finalize(state).unwrap();
+    // The rejection cannot be sent, so it is reported
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let errors = reported.clone();
+    let mut server = FooRPCServer::new(FooImpl::default(), BincodeTransport::new(s2))
+        .with_interceptor(DenySecret)
+        .on_error(move |_, e| errors.lock().unwrap().push(e.to_string()));
+    server.serve_single_call().unwrap();
+    assert_eq!(*reported.lock().unwrap(), vec!["access denied"]);
+}
+
+#[test]
+fn interceptor_reads_request_state() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl::default(), JSONTransport::new(s2))
+            .with_interceptor(NonNegative)
+            .serve()
+    });
+    let client = FooRPCClient::new(JSONTransport::new(s1));
+    assert_eq!(client.bar("a".to_string(), 1).unwrap(), "a is 1");
+    let e = client.bar("a".to_string(), -1).unwrap_err();
+    assert_eq!(e.to_string(), "b is negative");
+}
@@ -0,0 +1,229 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::result::Result;
+use std::time::Duration;
+use std::{fmt, thread};
+
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{JSONAsyncClientTransport, JSONFraming, JSONTransport};
+use essrpc::{AsyncRPCClient, RPCClient, RPCServer};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TestError {
+    msg: String,
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for TestError {}
+impl From<essrpc::RPCError> for TestError {
+    fn from(error: essrpc::RPCError) -> Self {
+        TestError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[essrpc(async, sync)]
+pub trait Foo {
+    fn name(&self) -> Result<String, TestError>;
+    fn count(&self) -> u64;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn name(&self) -> Result<String, TestError> {
+        Ok("foo".to_string())
+    }
+    fn count(&self) -> u64 {
+        7
+    }
+}
+
+fn serve(s: UnixStream, framing: JSONFraming) {
+    let tr = JSONTransport::new(s)
+        .with_framing(framing)
+        .with_max_message_size(1024)
+        .with_request_ids();
+    thread::spawn(move || FooRPCServer::new(FooImpl, tr).serve());
+}
+
+const FRAMINGS: [JSONFraming; 2] = [JSONFraming::NewlineDelimited, JSONFraming::ContentLength];
+
+// A client whose server answers every call with the bytes `write`
+// sends, regardless of the calls made.
+fn client(write: impl FnOnce(UnixStream) + Send + 'static) -> impl FooAsync {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    thread::spawn(move || write(s2));
+    FooAsyncRPCClient::new(JSONAsyncClientTransport::new_unframed(s1))
+}
+
+#[tokio::test]
+async fn json_back_to_back() {
+    let foo = client(|mut s| {
+        s.write_all(br#"{"Ok":"a}\"{["}{"Ok":"b"} ["#).unwrap();
+        thread::sleep(Duration::from_secs(1));
+    });
+    assert_eq!(foo.name().await.unwrap(), r#"a}"{["#);
+    assert_eq!(foo.name().await.unwrap(), "b");
+}
+
+#[tokio::test]
+async fn json_byte_at_a_time() {
+    let foo = client(|mut s| {
+        for b in r#" {"Ok" : "x\\é"}{"Ok":"y"}"#.bytes() {
+            s.write_all(&[b]).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        thread::sleep(Duration::from_secs(1));
+    });
+    assert_eq!(foo.name().await.unwrap(), "x\\\u{e9}");
+    assert_eq!(foo.name().await.unwrap(), "y");
+}
+
+#[tokio::test]
+async fn json_bare_numbers() {
+    // The last number ends at EOF
+    let foo = client(|mut s| {
+        s.write_all(b"42\n43").unwrap();
+        s.shutdown(Shutdown::Write).unwrap();
+        thread::sleep(Duration::from_secs(1));
+    });
+    assert_eq!(foo.count().await, 42);
+    assert_eq!(foo.count().await, 43);
+}
+
+#[tokio::test]
+async fn json_unbalanced() {
+    let foo = client(|mut s| {
+        s.write_all(b"]").unwrap();
+        thread::sleep(Duration::from_secs(1));
+    });
+    assert!(foo.name().await.is_err());
+}
+
+#[tokio::test]
+async fn json_truncated() {
+    let foo = client(|mut s| {
+        s.write_all(br#"{"Ok":"a"#).unwrap();
+        s.shutdown(Shutdown::Write).unwrap();
+        thread::sleep(Duration::from_secs(1));
+    });
+    let e = foo.name().await.unwrap_err();
+    assert!(e.msg.contains("EOF within json value"), "{}", e);
+}
+
+#[test]
+fn json_framing_sync() {
+    for framing in FRAMINGS {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        serve(s2, framing);
+        let foo = FooRPCClient::new(
+            JSONTransport::new(s1)
+                .with_framing(framing)
+                .with_request_ids(),
+        );
+        assert_eq!(foo.name().unwrap(), "foo");
+        assert_eq!(foo.count(), 7);
+    }
+}
+
+#[tokio::test]
+async fn json_framing_async() {
+    for framing in FRAMINGS {
+        let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+        let s2 = s2.into_std().unwrap();
+        s2.set_nonblocking(false).unwrap();
+        // Bare responses, so a bare number must be delimited to be read
+        thread::spawn(move || {
+            FooRPCServer::new(FooImpl, JSONTransport::new(s2).with_framing(framing)).serve()
+        });
+        let foo = FooAsyncRPCClient::new(
+            JSONAsyncClientTransport::new_unframed(s1).with_framing(framing),
+        );
+        assert_eq!(foo.count().await, 7);
+        assert_eq!(foo.name().await.unwrap(), "foo");
+    }
+}
+
+fn response_result(response: &[u8]) -> serde_json::Value {
+    let mut response: serde_json::Value = serde_json::from_slice(response).unwrap();
+    response["result"].take()
+}
+
+#[test]
+fn json_newline_delimited_wire() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(s2, JSONFraming::NewlineDelimited);
+    let mut w = s1.try_clone().unwrap();
+    let mut r = BufReader::new(s1);
+    // Blank lines are skipped, and an oversized line is discarded
+    w.write_all(b"\n\r\n").unwrap();
+    w.write_all(&[b' '; 2000]).unwrap();
+    w.write_all(b"\n").unwrap();
+    w.write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"count\",\"params\":{},\"id\":1}\r\n")
+        .unwrap();
+    let mut line = String::new();
+    r.read_line(&mut line).unwrap();
+    assert!(line.ends_with("}\n"), "{:?}", line);
+    assert_eq!(line.matches('\n').count(), 1);
+    assert_eq!(response_result(line.as_bytes()), 7);
+}
+
+#[test]
+fn json_content_length_wire() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(s2, JSONFraming::ContentLength);
+    let mut w = s1.try_clone().unwrap();
+    let mut r = BufReader::new(s1);
+    let body = "{\"jsonrpc\":\"2.0\",\"method\":\"count\",\"params\":{},\"id\":1}";
+    // An oversized message is discarded
+    write!(w, "Content-Length: 2000\r\n\r\n{}", " ".repeat(2000)).unwrap();
+    write!(
+        w,
+        "content-length: {}\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut header = String::new();
+    r.read_line(&mut header).unwrap();
+    let len: usize = header
+        .strip_prefix("Content-Length: ")
+        .and_then(|len| len.strip_suffix("\r\n"))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut blank = String::new();
+    r.read_line(&mut blank).unwrap();
+    assert_eq!(blank, "\r\n");
+    let mut response = vec![0; len];
+    r.read_exact(&mut response).unwrap();
+    assert_eq!(response_result(&response), 7);
+}
+
+#[test]
+fn json_content_length_missing() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        FooRPCServer::new(
+            FooImpl,
+            JSONTransport::new(s2).with_framing(JSONFraming::ContentLength),
+        )
+        .serve_single_call()
+    });
+    let mut w = s1;
+    w.write_all(b"Content-Type: text/plain\r\n\r\n{}").unwrap();
+    let e = server.join().unwrap().unwrap_err();
+    assert!(e.to_string().contains("Content-Length"), "{}", e);
+}
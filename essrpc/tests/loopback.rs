@@ -0,0 +1,145 @@
+use essrpc::essrpc;
+use essrpc::transports::{LoopbackMode, LoopbackTransport};
+use essrpc::{RPCClient, RPCError, RPCErrorKind, RemoteError};
+use serde::{Deserialize, Serialize};
+
+#[essrpc(sync, async)]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, RemoteError>;
+    fn sum(&self, values: Vec<f64>) -> Result<f64, RemoteError>;
+    fn fail(&self, msg: String) -> Result<u32, RemoteError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, RemoteError> {
+        Ok(format!("{} is {}", a, b))
+    }
+
+    fn sum(&self, values: Vec<f64>) -> Result<f64, RemoteError> {
+        Ok(values.iter().sum())
+    }
+
+    fn fail(&self, msg: String) -> Result<u32, RemoteError> {
+        Err(RemoteError::msg(msg))
+    }
+}
+
+#[essrpc]
+pub trait Repo {
+    type Item: Serialize + for<'de> Deserialize<'de>;
+    fn get(&self, id: u64) -> Result<Self::Item, RemoteError>;
+}
+
+struct NameRepo;
+
+impl Repo for NameRepo {
+    type Item = String;
+    fn get(&self, id: u64) -> Result<String, RemoteError> {
+        Ok(format!("item {}", id))
+    }
+}
+
+fn check_sync(client: &impl Foo) {
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    assert_eq!(client.sum(vec![1.5, 2.5, 3.0]).unwrap(), 7.0);
+    let e = client.fail("iffy".to_string()).unwrap_err();
+    assert!(e.to_string().starts_with("iffy"));
+    assert_eq!(e.rpc_error_kind(), None);
+}
+
+#[test]
+fn loopback_value() {
+    check_sync(&FooRPCServer::loopback(FooImpl));
+}
+
+#[test]
+fn loopback_bincode() {
+    check_sync(&FooRPCServer::loopback_with_mode(
+        FooImpl,
+        LoopbackMode::Bincode,
+    ));
+}
+
+#[test]
+fn loopback_json() {
+    check_sync(&FooRPCServer::loopback_with_mode(
+        FooImpl,
+        LoopbackMode::JSON,
+    ));
+}
+
+#[test]
+fn loopback_explicit_transport() {
+    let transport =
+        LoopbackTransport::new(LoopbackMode::Value, |tr| FooRPCServer::new(FooImpl, tr));
+    check_sync(&FooRPCClient::new(transport));
+}
+
+#[test]
+fn loopback_associated_types() {
+    let client = RepoRPCServer::loopback(NameRepo);
+    assert_eq!(client.get(7).unwrap(), "item 7");
+}
+
+#[test]
+fn loopback_serialization_error() {
+    // A NaN survives the in-memory representation but not JSON.
+    let client = FooRPCServer::loopback_with_mode(FooImpl, LoopbackMode::JSON);
+    let e = client.sum(vec![f64::NAN]).unwrap_err();
+    assert_eq!(e.rpc_error_kind(), Some(&RPCErrorKind::SerializationError));
+    // The loopback remains usable
+    assert_eq!(client.sum(vec![1.0]).unwrap(), 1.0);
+}
+
+#[tokio::test]
+async fn loopback_async() {
+    for mode in [
+        LoopbackMode::Value,
+        LoopbackMode::Bincode,
+        LoopbackMode::JSON,
+    ] {
+        let client = FooRPCServer::loopback_async_with_mode(FooImpl, mode);
+        assert_eq!(
+            client.bar("the answer".to_string(), 42).await.unwrap(),
+            "the answer is 42"
+        );
+        assert_eq!(client.sum(vec![1.5, 2.5]).await.unwrap(), 4.0);
+        assert!(client.fail("iffy".to_string()).await.is_err());
+    }
+    let client = FooRPCServer::loopback_async(FooImpl);
+    assert_eq!(client.sum(vec![]).await.unwrap(), 0.0);
+}
+
+#[essrpc]
+pub trait Echo {
+    fn user(&self) -> Result<String, RemoteError>;
+}
+
+struct EchoImpl;
+
+impl Echo for EchoImpl {
+    fn user(&self) -> Result<String, RemoteError> {
+        let metadata = essrpc::server::request_metadata();
+        Ok(metadata.get("user").unwrap_or_default().to_string())
+    }
+}
+
+struct SetUser;
+
+impl essrpc::client::ClientInterceptor for SetUser {
+    fn before_call(&mut self, call: &mut essrpc::client::ClientCall) -> Result<(), RPCError> {
+        call.metadata_mut().insert("user", "alice");
+        Ok(())
+    }
+}
+
+#[test]
+fn loopback_metadata() {
+    let client = EchoRPCServer::loopback(EchoImpl).with_interceptor(SetUser);
+    assert_eq!(client.user().unwrap(), "alice");
+}
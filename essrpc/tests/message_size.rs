@@ -0,0 +1,289 @@
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::result::Result;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use essrpc::essrpc;
+use essrpc::transports::{
+    BincodeAsyncClientTransport, BincodeFormat, BincodeTransport, CBORAsyncClientTransport,
+    CBORTransport, JSONAsyncClientTransport, JSONFraming, JSONTransport, MsgPackTransport,
+    SerdeTransport,
+};
+use essrpc::{
+    AsyncRPCClient, ClientTransport, MethodId, RPCClient, RPCError, RPCErrorKind, RPCServer,
+    ServerTransport,
+};
+
+#[essrpc(async, sync)]
+pub trait Foo {
+    fn echo(&self, data: Vec<u8>) -> Result<Vec<u8>, RPCError>;
+    fn zeros(&self, len: usize) -> Result<Vec<u8>, RPCError>;
+    fn greet(&self, name: String) -> Result<String, RPCError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn echo(&self, data: Vec<u8>) -> Result<Vec<u8>, RPCError> {
+        Ok(data)
+    }
+    fn zeros(&self, len: usize) -> Result<Vec<u8>, RPCError> {
+        Ok(vec![0; len])
+    }
+    fn greet(&self, name: String) -> Result<String, RPCError> {
+        Ok(format!("hello {}", name))
+    }
+}
+
+const MAX: usize = 1024;
+
+fn serve<TR>(tr: TR) -> thread::JoinHandle<Result<(), RPCError>>
+where
+    TR: ServerTransport + Send + 'static,
+{
+    thread::spawn(move || FooRPCServer::new(FooImpl, tr).serve())
+}
+
+fn call_echo<TR: ClientTransport>(tr: &mut TR, data: Vec<u8>) -> TR::FinalState {
+    let mut state = tr
+        .tx_begin_call(MethodId {
+            name: "echo",
+            num: 0,
+        })
+        .unwrap();
+    tr.tx_add_param("data", data, &mut state).unwrap();
+    tr.tx_finalize(state).unwrap()
+}
+
+// Send an oversized call, which the server discards, then a call which
+// it serves.
+fn check_discarded<TR: ClientTransport>(mut tr: TR) {
+    call_echo(&mut tr, vec![0; 4 * MAX]);
+    let state = call_echo(&mut tr, vec![1, 2, 3]);
+    let ret: Result<Vec<u8>, RPCError> = tr.rx_response(state).unwrap();
+    assert_eq!(ret.unwrap(), vec![1, 2, 3]);
+}
+
+fn assert_too_large<T: std::fmt::Debug>(result: Result<T, RPCError>) {
+    assert_eq!(result.unwrap_err().kind, RPCErrorKind::MessageTooLarge);
+}
+
+#[test]
+fn bincode_oversized_call() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(BincodeTransport::new(s2).with_max_message_size(MAX));
+    check_discarded(BincodeTransport::new(s1));
+}
+
+#[test]
+fn bincode_oversized_call_reported() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let (errors, reported) = mpsc::channel();
+    let errors = Mutex::new(errors);
+    let server = thread::spawn(move || {
+        FooRPCServer::new(
+            FooImpl,
+            BincodeTransport::new(s2).with_max_message_size(MAX),
+        )
+        .on_error(move |conn, e| {
+            assert!(conn.is_none());
+            errors.lock().unwrap().send(e.kind).unwrap();
+        })
+        .serve()
+    });
+    check_discarded(BincodeTransport::new(s1));
+    assert_eq!(reported.recv().unwrap(), RPCErrorKind::MessageTooLarge);
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+}
+
+#[test]
+fn bincode_oversized_length() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = serve(BincodeTransport::new(s2));
+    // A length of 4GiB, which is not allocated
+    s1.write_all(&[0xff; 4]).unwrap();
+    drop(s1);
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+}
+
+#[test]
+fn bincode_nested_length() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let mut server = FooRPCServer::new(FooImpl, BincodeTransport::new(s2));
+    // A call to `greet` whose name claims to be 1TiB long, which is
+    // not allocated
+    let mut msg = 2u32.to_le_bytes().to_vec();
+    msg.extend_from_slice(&(1u64 << 40).to_le_bytes());
+    s1.write_all(&(msg.len() as u32).to_le_bytes()).unwrap();
+    s1.write_all(&msg).unwrap();
+    assert_too_large(server.serve_single_call());
+}
+
+#[test]
+fn bincode_client_limits() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(BincodeTransport::new(s2));
+    let client = FooRPCClient::new(BincodeTransport::new(s1).with_max_message_size(MAX));
+    // Not sent
+    assert_too_large(client.echo(vec![0; 4 * MAX]));
+    assert_too_large(client.zeros(4 * MAX));
+    assert_eq!(client.zeros(8).unwrap(), vec![0; 8]);
+}
+
+#[test]
+fn bincode_oversized_response() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let mut server = FooRPCServer::new(
+        FooImpl,
+        BincodeTransport::new(s2).with_max_message_size(MAX),
+    );
+    let mut tr = BincodeTransport::new(s1);
+    let mut state = tr
+        .tx_begin_call(MethodId {
+            name: "zeros",
+            num: 1,
+        })
+        .unwrap();
+    tr.tx_add_param("len", 4 * MAX, &mut state).unwrap();
+    tr.tx_finalize(state).unwrap();
+    assert_too_large(server.serve_single_call());
+}
+
+#[tokio::test]
+async fn bincode_async_limits() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    serve(BincodeTransport::new(s2));
+    let client =
+        FooAsyncRPCClient::new(BincodeAsyncClientTransport::new(s1).with_max_message_size(MAX));
+    assert_too_large(client.echo(vec![0; 4 * MAX]).await);
+    assert_too_large(client.zeros(4 * MAX).await);
+}
+
+#[tokio::test]
+async fn bincode_async_oversized_send() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    serve(BincodeTransport::new(s2));
+    let client =
+        FooAsyncRPCClient::new(BincodeAsyncClientTransport::new(s1).with_max_message_size(MAX));
+    let err = client.echo(vec![0; 4 * MAX]).await.unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::MessageTooLarge);
+    assert!(err
+        .to_string()
+        .contains(&format!("maximum size of {} bytes", MAX)));
+    // Nothing was sent, so the connection is still usable
+    assert_eq!(client.zeros(8).await.unwrap(), vec![0; 8]);
+}
+
+#[test]
+fn serde_oversized_call() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(SerdeTransport::new(s2, BincodeFormat).with_max_message_size(MAX));
+    check_discarded(SerdeTransport::new(s1, BincodeFormat));
+}
+
+#[test]
+fn msgpack_oversized_call() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(MsgPackTransport::new(s2).with_max_message_size(MAX));
+    check_discarded(MsgPackTransport::new(s1));
+}
+
+#[test]
+fn json_oversized_call() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(JSONTransport::new(s2).with_max_message_size(MAX));
+    check_discarded(JSONTransport::new(s1));
+}
+
+#[test]
+fn json_oversized_call_truncated() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let mut server = FooRPCServer::new(
+        FooImpl,
+        JSONTransport::new(s2)
+            .with_framing(JSONFraming::ContentLength)
+            .with_max_message_size(MAX),
+    );
+    // An oversized message which ends before its length
+    write!(s1, "Content-Length: {}\r\n\r\n{{\"method\"", 4 * MAX).unwrap();
+    drop(s1);
+    let e = server.serve_single_call().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+}
+
+#[test]
+fn json_client_limits() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(JSONTransport::new(s2));
+    let client = FooRPCClient::new(JSONTransport::new(s1).with_max_message_size(MAX));
+    assert_too_large(client.echo(vec![0; 4 * MAX]));
+    assert_too_large(client.zeros(4 * MAX));
+    assert_eq!(client.zeros(8).unwrap(), vec![0; 8]);
+}
+
+#[tokio::test]
+async fn json_async_limits() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    serve(JSONTransport::new(s2));
+    let client = FooAsyncRPCClient::new(
+        JSONAsyncClientTransport::new_unframed(s1).with_max_message_size(MAX),
+    );
+    assert_too_large(client.echo(vec![0; 4 * MAX]).await);
+    assert_too_large(client.zeros(4 * MAX).await);
+}
+
+#[test]
+fn cbor_oversized_call() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(CBORTransport::new(s2).with_max_message_size(MAX));
+    check_discarded(CBORTransport::new(s1));
+}
+
+#[test]
+fn cbor_client_limits() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(CBORTransport::new(s2));
+    let client = FooRPCClient::new(CBORTransport::new(s1).with_max_message_size(MAX));
+    assert_too_large(client.echo(vec![0; 4 * MAX]));
+    assert_too_large(client.zeros(4 * MAX));
+    assert_eq!(client.zeros(8).unwrap(), vec![0; 8]);
+}
+
+#[test]
+fn cbor_nested_items() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = serve(CBORTransport::new(s2).with_max_message_size(MAX));
+    // An indefinite-length array holding maps, a tag and an
+    // indefinite-length byte string, far larger than the maximum
+    let mut item = vec![0x9f];
+    for _ in 0..100 {
+        item.extend_from_slice(&[0xa1, 0x61, b'k', 0xc1, 0x5f, 0x59, 0x00, 0x20]);
+        item.extend_from_slice(&[7; 32]);
+        item.push(0xff);
+    }
+    item.push(0xff);
+    s1.write_all(&item).unwrap();
+    check_discarded(CBORTransport::new(s1));
+    drop(server);
+}
+
+#[tokio::test]
+async fn cbor_async_limits() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    serve(CBORTransport::new(s2));
+    let client =
+        FooAsyncRPCClient::new(CBORAsyncClientTransport::new(s1).with_max_message_size(MAX));
+    assert_too_large(client.echo(vec![0; 4 * MAX]).await);
+    assert_too_large(client.zeros(4 * MAX).await);
+}
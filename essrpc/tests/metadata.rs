@@ -0,0 +1,177 @@
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use essrpc::client::{AfterCall, ClientCall, ClientInterceptor};
+use essrpc::essrpc;
+use essrpc::server::{self, CallInfo, ConcurrentServer, Interceptor};
+use essrpc::transports::{BincodeAsyncClientTransport, BincodeTransport, JSONTransport};
+use essrpc::{
+    AsyncRPCClient, Metadata, RPCClient, RPCError, RPCErrorKind, RPCServer, RemoteError,
+    ServerTransport,
+};
+
+#[essrpc(sync, async)]
+pub trait Foo {
+    fn whoami(&self) -> Result<String, RemoteError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn whoami(&self) -> Result<String, RemoteError> {
+        let metadata = server::request_metadata();
+        server::set_response_metadata("served-by", "foo");
+        Ok(metadata.get("user").unwrap_or("nobody").to_string())
+    }
+}
+
+// Sends the user with each call and records the response metadata.
+struct User {
+    name: &'static str,
+    responses: Arc<Mutex<Vec<Metadata>>>,
+}
+
+impl User {
+    fn new(name: &'static str) -> Self {
+        User {
+            name,
+            responses: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl ClientInterceptor for User {
+    fn before_call(&mut self, call: &mut ClientCall) -> Result<(), RPCError> {
+        call.metadata_mut().insert("user", self.name);
+        Ok(())
+    }
+
+    fn after_call(&mut self, call: &ClientCall, _error: Option<&mut RPCError>) -> AfterCall {
+        self.responses
+            .lock()
+            .unwrap()
+            .push(call.response_metadata().clone());
+        AfterCall::Done
+    }
+}
+
+// Rejects calls without a user.
+struct RequireUser;
+
+impl<TR: ServerTransport> Interceptor<TR> for RequireUser {
+    fn before_call(&mut self, call: &CallInfo, _state: &mut TR::RXState) -> Result<(), RPCError> {
+        match call.metadata().get("user") {
+            Some(_) => Ok(()),
+            None => Err(RPCError::new(RPCErrorKind::Other, "no user")),
+        }
+    }
+}
+
+fn served_by_foo() -> Metadata {
+    vec![("served-by", "foo")].into_iter().collect()
+}
+
+#[test]
+fn metadata_bincode() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl, BincodeTransport::new(s2).with_metadata())
+            .with_interceptor(RequireUser)
+            .serve()
+    });
+    let user = User::new("alice");
+    let responses = user.responses.clone();
+    let client =
+        FooRPCClient::new(BincodeTransport::new(s1).with_metadata()).with_interceptor(user);
+    assert_eq!(client.whoami().unwrap(), "alice");
+    assert_eq!(client.whoami().unwrap(), "alice");
+    assert_eq!(
+        *responses.lock().unwrap(),
+        vec![served_by_foo(), served_by_foo()]
+    );
+}
+
+#[test]
+fn metadata_bincode_rejected() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl, BincodeTransport::new(s2).with_metadata())
+            .with_interceptor(RequireUser)
+            .serve()
+    });
+    let client = FooRPCClient::new(BincodeTransport::new(s1).with_metadata());
+    assert_eq!(client.whoami().unwrap_err().to_string(), "no user");
+}
+
+#[test]
+fn metadata_bincode_not_enabled() {
+    let (s1, _s2) = UnixStream::pair().unwrap();
+    let client = FooRPCClient::new(BincodeTransport::new(s1)).with_interceptor(User::new("bob"));
+    let e = client.whoami().unwrap_err();
+    assert_eq!(e.to_string(), "transport does not support metadata");
+}
+
+#[test]
+fn metadata_bincode_concurrent() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let tr = BincodeTransport::new(s2).with_request_ids().with_metadata();
+        ConcurrentServer::new(tr, 2, |call| FooRPCServer::new(FooImpl, call)).serve()
+    });
+    let user = User::new("carol");
+    let responses = user.responses.clone();
+    let client = FooRPCClient::new(BincodeTransport::new(s1).with_request_ids().with_metadata())
+        .with_interceptor(user);
+    assert_eq!(client.whoami().unwrap(), "carol");
+    assert_eq!(*responses.lock().unwrap(), vec![served_by_foo()]);
+}
+
+#[test]
+fn metadata_json() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl, JSONTransport::new(s2).with_request_ids()).serve()
+    });
+    let user = User::new("dave");
+    let responses = user.responses.clone();
+    let client =
+        FooRPCClient::new(JSONTransport::new(s1).with_request_ids()).with_interceptor(user);
+    assert_eq!(client.whoami().unwrap(), "dave");
+    assert_eq!(*responses.lock().unwrap(), vec![served_by_foo()]);
+}
+
+#[test]
+fn metadata_json_without_request_ids() {
+    // Request metadata is sent, but response metadata is discarded.
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl, JSONTransport::new(s2)).serve());
+    let user = User::new("erin");
+    let responses = user.responses.clone();
+    let client = FooRPCClient::new(JSONTransport::new(s1)).with_interceptor(user);
+    assert_eq!(client.whoami().unwrap(), "erin");
+    assert_eq!(*responses.lock().unwrap(), vec![Metadata::new()]);
+}
+
+#[test]
+fn metadata_outside_call() {
+    assert!(server::request_metadata().is_empty());
+    // Has no effect
+    server::set_response_metadata("a", "b");
+}
+
+#[tokio::test]
+async fn metadata_bincode_async() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl, BincodeTransport::new(s2).with_metadata()).serve()
+    });
+    let user = User::new("frank");
+    let responses = user.responses.clone();
+    let client = FooAsyncRPCClient::new(BincodeAsyncClientTransport::new(s1).with_metadata())
+        .with_interceptor(user);
+    assert_eq!(client.whoami().await.unwrap(), "frank");
+    assert_eq!(*responses.lock().unwrap(), vec![served_by_foo()]);
+}
@@ -0,0 +1,120 @@
+use essrpc::essrpc;
+use essrpc::{RPCErrorKind, RemoteError};
+use serde::{Deserialize, Serialize};
+
+#[essrpc(mock)]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, RemoteError>;
+    fn count(&self) -> u32;
+    #[essrpc(local)]
+    fn bar42(&self) -> Result<String, RemoteError> {
+        self.bar("the answer".to_string(), 42)
+    }
+}
+
+#[essrpc(mock)]
+pub trait Repo {
+    type Item: Serialize + for<'de> Deserialize<'de>;
+    fn get(&self, id: u64) -> Result<Self::Item, RemoteError>;
+}
+
+#[essrpc(mock)]
+pub trait Ctx {
+    fn user(
+        &self,
+        #[essrpc(context)] ctx: &essrpc::CallContext,
+        n: u32,
+    ) -> Result<u32, RemoteError>;
+}
+
+#[essrpc(async, mock)]
+pub trait Qux {
+    fn qux(&self, a: String) -> Result<String, RemoteError>;
+}
+
+// Code under test, which would ordinarily be given an RPC client.
+fn describe(client: &impl Foo) -> String {
+    match client.bar42() {
+        Ok(s) => s,
+        Err(e) => format!("failed: {}", e),
+    }
+}
+
+#[test]
+fn returning() {
+    let mut mock = MockFoo::new();
+    mock.expect_bar()
+        .with(("the answer".to_string(), 42))
+        .returning(|(a, b)| Ok(format!("{} is {}", a, b)));
+    assert_eq!(describe(&mock), "the answer is 42");
+    assert_eq!(mock.call_count("bar"), 1);
+    assert_eq!(mock.call_count("count"), 0);
+}
+
+#[test]
+fn expectations_in_order() {
+    let mut mock = MockFoo::new();
+    mock.expect_count().times(1).returning(|()| 1);
+    mock.expect_count().returning(|()| 2);
+    assert_eq!(mock.count(), 1);
+    assert_eq!(mock.count(), 2);
+    assert_eq!(mock.count(), 2);
+    assert_eq!(mock.call_count("count"), 3);
+    mock.checkpoint();
+}
+
+#[test]
+#[should_panic(expected = "Expected 2 calls to Foo::count, but 1 were made")]
+fn checkpoint_verifies_times() {
+    let mut mock = MockFoo::new();
+    mock.expect_count().times(2).returning(|()| 1);
+    mock.count();
+    mock.checkpoint();
+}
+
+#[test]
+fn withf_and_rpc_error() {
+    let mut mock = MockFoo::new();
+    mock.expect_bar()
+        .withf(|(_, b)| *b < 0)
+        .rpc_error(RPCErrorKind::TransportEOF);
+    mock.expect_bar().returning(|(a, _)| Ok(a));
+    let e = mock.bar("x".to_string(), -1).unwrap_err();
+    assert_eq!(e.rpc_error_kind(), Some(&RPCErrorKind::TransportEOF));
+    assert_eq!(mock.bar("y".to_string(), 1).unwrap(), "y");
+}
+
+#[test]
+#[should_panic(expected = "No matching expectation for call to Foo::bar")]
+fn unexpected_call_panics() {
+    let mut mock = MockFoo::new();
+    mock.expect_bar()
+        .with(("a".to_string(), 1))
+        .returning(|(a, _)| Ok(a));
+    let _ = mock.bar("b".to_string(), 2);
+}
+
+#[test]
+fn associated_types() {
+    let mut mock: MockRepo<String> = MockRepo::new();
+    mock.expect_get()
+        .returning(|(id,)| Ok(format!("item {}", id)));
+    assert_eq!(mock.get(7).unwrap(), "item 7");
+}
+
+#[tokio::test]
+async fn async_trait() {
+    let mut mock = MockQux::new();
+    mock.expect_qux().returning(|(a,)| Ok(a));
+    // Both Qux and QuxAsync are implemented
+    assert_eq!(QuxAsync::qux(&mock, "a".to_string()).await.unwrap(), "a");
+    assert_eq!(Qux::qux(&mock, "b".to_string()).unwrap(), "b");
+}
+
+#[test]
+fn context_param_not_matched() {
+    let mut mock = MockCtx::new();
+    mock.expect_user().with((3,)).returning(|(n,)| Ok(n * 2));
+    let ctx = essrpc::CallContext::new();
+    assert_eq!(mock.user(&ctx, 3).unwrap(), 6);
+}
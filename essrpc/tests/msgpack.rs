@@ -0,0 +1,229 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::result::Result;
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{MsgPackAsyncClientTransport, MsgPackTransport};
+use essrpc::{AsyncRPCClient, ClientTransport, RPCClient, RPCServer};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TestError {
+    msg: String,
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for TestError {}
+impl From<essrpc::RPCError> for TestError {
+    fn from(error: essrpc::RPCError) -> Self {
+        TestError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[essrpc(sync, async)]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError>;
+    fn expect_error(&self) -> Result<String, TestError>;
+    fn flip(&self, p: Point, label: Option<String>) -> Result<Point, TestError>;
+    fn big_buffer(&self) -> Result<Vec<u8>, TestError>;
+    fn anyhow_err(&self) -> anyhow::Result<i32>;
+    fn user(&self) -> Result<String, TestError>;
+}
+
+struct FooImpl;
+
+const BIG_BUFFER_SIZE: usize = 256 * 1024;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError> {
+        Ok(format!("{} is {}", a, b))
+    }
+    fn expect_error(&self) -> Result<String, TestError> {
+        Err(TestError {
+            msg: "iamerror".to_string(),
+        })
+    }
+    fn flip(&self, p: Point, label: Option<String>) -> Result<Point, TestError> {
+        assert_eq!(label.as_deref(), Some("p"));
+        Ok(Point { x: p.y, y: p.x })
+    }
+    fn big_buffer(&self) -> Result<Vec<u8>, TestError> {
+        Ok(vec![0; BIG_BUFFER_SIZE])
+    }
+    fn anyhow_err(&self) -> anyhow::Result<i32> {
+        Err(anyhow::anyhow!("msgpack anyhow"))
+    }
+    fn user(&self) -> Result<String, TestError> {
+        let metadata = essrpc::server::request_metadata();
+        essrpc::server::set_response_metadata("seen", "yes");
+        Ok(metadata.get("user").unwrap_or_default().to_string())
+    }
+}
+
+fn serve(s: UnixStream) {
+    thread::spawn(move || FooRPCServer::new(FooImpl, MsgPackTransport::new(s)).serve());
+}
+
+fn check_sync(client: &impl Foo) {
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    assert_eq!(client.expect_error().unwrap_err().msg, "iamerror");
+    assert_eq!(
+        client
+            .flip(Point { x: 1, y: 2 }, Some("p".to_string()))
+            .unwrap(),
+        Point { x: 2, y: 1 }
+    );
+    assert_eq!(client.big_buffer().unwrap().len(), BIG_BUFFER_SIZE);
+    assert_eq!(
+        client.anyhow_err().unwrap_err().to_string(),
+        "msgpack anyhow"
+    );
+}
+
+#[test]
+fn basic_msgpack_named() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(s2);
+    check_sync(&FooRPCClient::new(MsgPackTransport::new(s1)));
+}
+
+#[test]
+fn basic_msgpack_positional() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(s2);
+    check_sync(&FooRPCClient::new(
+        MsgPackTransport::new(s1).with_positional_params(),
+    ));
+}
+
+#[test]
+fn msgpack_metadata() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve(s2);
+    let mut tr = MsgPackTransport::new(s1).with_metadata();
+    let mut state = tr
+        .tx_begin_call(essrpc::MethodId {
+            name: "user",
+            num: 5,
+        })
+        .unwrap();
+    let metadata: essrpc::Metadata = vec![("user", "alice")].into_iter().collect();
+    tr.tx_set_metadata(&metadata, &mut state).unwrap();
+    tr.tx_finalize(state).unwrap();
+    let user: Result<String, TestError> = tr.rx_response(()).unwrap();
+    assert_eq!(user.unwrap(), "alice");
+    assert_eq!(tr.rx_response_metadata().get("seen"), Some("yes"));
+}
+
+#[test]
+fn msgpack_metadata_unsupported() {
+    let (s1, _s2) = UnixStream::pair().unwrap();
+    let mut tr = MsgPackTransport::new(s1);
+    let mut state = tr
+        .tx_begin_call(essrpc::MethodId {
+            name: "user",
+            num: 5,
+        })
+        .unwrap();
+    let metadata: essrpc::Metadata = vec![("user", "alice")].into_iter().collect();
+    assert!(tr.tx_set_metadata(&metadata, &mut state).is_err());
+}
+
+#[test]
+fn msgpack_wire_format() {
+    // A call made by hand, as another language would, with the
+    // parameters in a different order from the trait.
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    serve(s2);
+    let mut msg = Vec::new();
+    rmpv::encode::write_value(&mut msg, &rmpv::Value::from("bar")).unwrap();
+    let params = rmpv::Value::Map(vec![
+        (rmpv::Value::from("b"), rmpv::Value::from(7)),
+        (rmpv::Value::from("a"), rmpv::Value::from("seven")),
+    ]);
+    rmpv::encode::write_value(&mut msg, &params).unwrap();
+    s1.write_all(&(msg.len() as u32).to_le_bytes()).unwrap();
+    s1.write_all(&msg).unwrap();
+
+    let mut len = [0u8; 4];
+    s1.read_exact(&mut len).unwrap();
+    let mut response = vec![0; u32::from_le_bytes(len) as usize];
+    s1.read_exact(&mut response).unwrap();
+    let value = rmpv::decode::read_value(&mut response.as_slice()).unwrap();
+    let ok = value.as_map().unwrap()[0].clone();
+    assert_eq!(ok.0.as_str(), Some("Ok"));
+    assert_eq!(ok.1.as_str(), Some("seven is 7"));
+}
+
+#[test]
+fn msgpack_unknown_param() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        FooRPCServer::new(FooImpl, MsgPackTransport::new(s2)).serve_single_call()
+    });
+    let mut msg = Vec::new();
+    rmpv::encode::write_value(&mut msg, &rmpv::Value::from("bar")).unwrap();
+    rmpv::encode::write_value(&mut msg, &rmpv::Value::Map(vec![])).unwrap();
+    s1.write_all(&(msg.len() as u32).to_le_bytes()).unwrap();
+    s1.write_all(&msg).unwrap();
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, essrpc::RPCErrorKind::SerializationError);
+    assert!(e.to_string().contains("missing parameter a"));
+}
+
+fn async_foo(positional: bool) -> impl FooAsync {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    // The server isn't actually async, so convert into a non-async Unix stream
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    serve(s2);
+    let tr = MsgPackAsyncClientTransport::new(s1);
+    let tr = if positional {
+        tr.with_positional_params()
+    } else {
+        tr
+    };
+    FooAsyncRPCClient::new(tr)
+}
+
+#[tokio::test]
+async fn basic_msgpack_async() {
+    for positional in [false, true] {
+        let foo = async_foo(positional);
+        assert_eq!(
+            foo.bar("the answer".to_string(), 42).await.unwrap(),
+            "the answer is 42"
+        );
+        assert_eq!(foo.expect_error().await.unwrap_err().msg, "iamerror");
+        assert_eq!(
+            foo.flip(Point { x: 3, y: 4 }, Some("p".to_string()))
+                .await
+                .unwrap(),
+            Point { x: 4, y: 3 }
+        );
+        assert_eq!(foo.big_buffer().await.unwrap().len(), BIG_BUFFER_SIZE);
+        assert_eq!(
+            foo.anyhow_err().await.unwrap_err().to_string(),
+            "msgpack anyhow"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::os::unix::net::UnixStream;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use essrpc::essrpc;
+use essrpc::transports::{BincodeAsyncClientTransport, BincodeTransport};
+use essrpc::{
+    AsyncRPCClient, ClientTransport, MethodId, RPCClient, RPCErrorKind, RPCServer, RemoteError,
+};
+
+#[essrpc(sync, async)]
+pub trait Foo {
+    fn boom(&self, msg: String) -> Result<u32, RemoteError>;
+    fn double(&self, a: i32) -> Result<i32, RemoteError>;
+    fn infallible_boom(&self) -> u32;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn boom(&self, msg: String) -> Result<u32, RemoteError> {
+        panic!("{}", msg)
+    }
+
+    fn double(&self, a: i32) -> Result<i32, RemoteError> {
+        Ok(a * 2)
+    }
+
+    fn infallible_boom(&self) -> u32 {
+        panic!("static message")
+    }
+}
+
+fn client_server() -> (
+    FooRPCClient<BincodeTransport<UnixStream>>,
+    FooRPCServer<FooImpl, BincodeTransport<UnixStream>>,
+) {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    (
+        FooRPCClient::new(BincodeTransport::new(s1)),
+        FooRPCServer::new(FooImpl, BincodeTransport::new(s2)),
+    )
+}
+
+#[test]
+fn panic_returned_to_client() {
+    let (client, mut server) = client_server();
+    thread::spawn(move || server.serve());
+    let e = client.boom("kaboom".to_string()).unwrap_err();
+    assert_eq!(e.rpc_error_kind(), Some(&RPCErrorKind::ServerPanic));
+    assert!(e.to_string().contains("kaboom"));
+    // The server keeps serving
+    assert_eq!(client.double(21).unwrap(), 42);
+    let e = client.boom("again".to_string()).unwrap_err();
+    assert!(e.to_string().contains("again"));
+    assert_eq!(client.double(2).unwrap(), 4);
+}
+
+#[test]
+fn panic_in_infallible_method() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let (errors, reported) = mpsc::channel();
+    let errors = Mutex::new(errors);
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl, BincodeTransport::new(s2))
+            .on_error(move |_, e| {
+                errors
+                    .lock()
+                    .unwrap()
+                    .send((e.kind, e.to_string()))
+                    .unwrap()
+            })
+            .serve()
+    });
+    // The panicking call cannot carry the error, so it has no
+    // response, but the server goes on serving the connection
+    let mut tr = BincodeTransport::new(s1);
+    let state = tr
+        .tx_begin_call(MethodId {
+            name: "infallible_boom",
+            num: 2,
+        })
+        .unwrap();
+    tr.tx_finalize(state).unwrap();
+    let mut state = tr
+        .tx_begin_call(MethodId {
+            name: "double",
+            num: 1,
+        })
+        .unwrap();
+    tr.tx_add_param("a", 4, &mut state).unwrap();
+    let state = tr.tx_finalize(state).unwrap();
+    let ret: Result<i32, RemoteError> = tr.rx_response(state).unwrap();
+    assert_eq!(ret.unwrap(), 8);
+    let (kind, msg) = reported.recv().unwrap();
+    assert_eq!(kind, RPCErrorKind::ServerPanic);
+    assert!(msg.contains("static message"));
+}
+
+#[test]
+fn panics_not_caught() {
+    let (client, server) = client_server();
+    let mut server = server.catch_panics(false);
+    let handle = thread::spawn(move || server.serve_single_call());
+    thread::spawn(move || {
+        let _ = client.boom("unwind".to_string());
+    });
+    let payload = handle.join().unwrap_err();
+    assert_eq!(payload.downcast_ref::<String>().unwrap(), "unwind");
+}
+
+#[tokio::test]
+async fn panic_returned_to_async_client() {
+    let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+    let s2 = s2.into_std().unwrap();
+    s2.set_nonblocking(false).unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl, BincodeTransport::new(s2)).serve());
+    let client = FooAsyncRPCClient::new(BincodeAsyncClientTransport::new(s1));
+    let e = client.boom("async kaboom".to_string()).await.unwrap_err();
+    assert_eq!(e.rpc_error_kind(), Some(&RPCErrorKind::ServerPanic));
+    assert!(e.to_string().contains("async kaboom"));
+    assert_eq!(client.double(5).await.unwrap(), 10);
+}
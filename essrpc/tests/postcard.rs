@@ -0,0 +1,225 @@
+// Also run with `--no-default-features --features postcard_transport`
+// to exercise the crate without std.
+use std::collections::VecDeque;
+use std::fmt;
+use std::result::Result;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{ByteStream, PostcardTransport};
+use essrpc::{RPCClient, RPCError, RPCErrorKind, RPCServer};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TestError {
+    msg: String,
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for TestError {}
+impl From<essrpc::RPCError> for TestError {
+    fn from(error: essrpc::RPCError) -> Self {
+        TestError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[essrpc]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError>;
+    fn expect_error(&self) -> Result<String, TestError>;
+    fn flip(&self, p: Point, label: Option<String>) -> Result<Point, TestError>;
+    fn sum(&self, values: Vec<u64>) -> u64;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError> {
+        Ok(format!("{} is {}", a, b))
+    }
+    fn expect_error(&self) -> Result<String, TestError> {
+        Err(TestError {
+            msg: "iamerror".to_string(),
+        })
+    }
+    fn flip(&self, p: Point, label: Option<String>) -> Result<Point, TestError> {
+        assert_eq!(label.as_deref(), Some("p"));
+        Ok(Point { x: p.y, y: p.x })
+    }
+    fn sum(&self, values: Vec<u64>) -> u64 {
+        values.iter().sum()
+    }
+}
+
+// Bytes in flight, and whether the writer has gone.
+type BufferState = (VecDeque<u8>, bool);
+
+// One direction of an in-memory pipe.
+#[derive(Clone, Default)]
+struct Buffer {
+    state: Arc<(Mutex<BufferState>, Condvar)>,
+}
+
+impl Buffer {
+    fn close(&self) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().1 = true;
+        cvar.notify_all();
+    }
+}
+
+// One end of a duplex in-memory pipe, standing in for a UART.
+struct PipeEnd {
+    rx: Buffer,
+    tx: Buffer,
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        self.tx.close();
+    }
+}
+
+fn pipe() -> (PipeEnd, PipeEnd) {
+    let (a, b) = (Buffer::default(), Buffer::default());
+    (
+        PipeEnd {
+            rx: a.clone(),
+            tx: b.clone(),
+        },
+        PipeEnd { rx: b, tx: a },
+    )
+}
+
+impl ByteStream for PipeEnd {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), RPCError> {
+        let (lock, cvar) = &*self.rx.state;
+        let mut state = lock.lock().unwrap();
+        for b in buf.iter_mut() {
+            loop {
+                if let Some(next) = state.0.pop_front() {
+                    *b = next;
+                    break;
+                }
+                if state.1 {
+                    return Err(RPCError::new(RPCErrorKind::TransportEOF, "pipe closed"));
+                }
+                state = cvar.wait(state).unwrap();
+            }
+        }
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), RPCError> {
+        let (lock, cvar) = &*self.tx.state;
+        lock.lock().unwrap().0.extend(buf);
+        cvar.notify_all();
+        Ok(())
+    }
+}
+
+fn serve(end: PipeEnd) -> thread::JoinHandle<Result<(), RPCError>> {
+    thread::spawn(move || FooRPCServer::new(FooImpl, PostcardTransport::new(end)).serve())
+}
+
+#[test]
+fn basic_postcard() {
+    let (e1, e2) = pipe();
+    serve(e2);
+    let client = FooRPCClient::new(PostcardTransport::new(e1));
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    assert_eq!(client.expect_error().unwrap_err().msg, "iamerror");
+    assert_eq!(
+        client
+            .flip(Point { x: 1, y: 2 }, Some("p".to_string()))
+            .unwrap(),
+        Point { x: 2, y: 1 }
+    );
+    assert_eq!(client.sum(vec![1, 2, 3, u32::MAX as u64]), 4294967301);
+}
+
+#[test]
+fn postcard_eof() {
+    let (e1, e2) = pipe();
+    let server = serve(e2);
+    drop(e1);
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+}
+
+#[test]
+fn postcard_truncated_call() {
+    let (mut e1, e2) = pipe();
+    let server = serve(e2);
+    // A call to `bar` with neither parameter present
+    e1.write_all(&2u32.to_le_bytes()).unwrap();
+    e1.write_all(&[0, 0]).unwrap();
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::SerializationError);
+}
+
+#[test]
+fn postcard_metadata() {
+    use essrpc::ClientTransport;
+    let (e1, e2) = pipe();
+    serve(e2);
+    let mut tr = PostcardTransport::new(e1);
+    let mut state = tr
+        .tx_begin_call(essrpc::MethodId {
+            name: "bar",
+            num: 0,
+        })
+        .unwrap();
+    let metadata: essrpc::Metadata = vec![("user", "alice")].into_iter().collect();
+    tr.tx_set_metadata(&metadata, &mut state).unwrap();
+    tr.tx_add_param("a", "x", &mut state).unwrap();
+    tr.tx_add_param("b", 1, &mut state).unwrap();
+    tr.tx_finalize(state).unwrap();
+    let ret: Result<String, TestError> = tr.rx_response(()).unwrap();
+    assert_eq!(ret.unwrap(), "x is 1");
+    assert!(tr.rx_response_metadata().is_empty());
+}
+
+#[test]
+fn postcard_oversized_call() {
+    use essrpc::ClientTransport;
+    let (e1, e2) = pipe();
+    thread::spawn(move || {
+        FooRPCServer::new(
+            FooImpl,
+            PostcardTransport::new(e2).with_max_message_size(64),
+        )
+        .serve()
+    });
+    let mut tr = PostcardTransport::new(e1);
+    // Discarded by the server, so never answered
+    let mut state = tr
+        .tx_begin_call(essrpc::MethodId {
+            name: "sum",
+            num: 3,
+        })
+        .unwrap();
+    tr.tx_add_param("values", vec![1u64; 100], &mut state)
+        .unwrap();
+    tr.tx_finalize(state).unwrap();
+    let client = FooRPCClient::new(tr);
+    assert_eq!(client.sum(vec![1, 2]), 3);
+}
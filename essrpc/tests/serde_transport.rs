@@ -0,0 +1,199 @@
+use std::fmt;
+use std::os::unix::net::UnixStream;
+use std::result::Result;
+use std::thread;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{
+    BincodeFormat, CBORFormat, JSONFormat, MsgPackFormat, SerdeAsyncClientTransport,
+    SerdeTransport, WireFormat,
+};
+use essrpc::{AsyncRPCClient, RPCClient, RPCError, RPCErrorKind, RPCServer};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TestError {
+    msg: String,
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for TestError {}
+impl From<essrpc::RPCError> for TestError {
+    fn from(error: essrpc::RPCError) -> Self {
+        TestError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[essrpc(sync, async)]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError>;
+    fn expect_error(&self) -> Result<String, TestError>;
+    fn flip(&self, p: Point, label: Option<String>) -> Result<Point, TestError>;
+    fn user(&self) -> Result<String, TestError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError> {
+        Ok(format!("{} is {}", a, b))
+    }
+    fn expect_error(&self) -> Result<String, TestError> {
+        Err(TestError {
+            msg: "iamerror".to_string(),
+        })
+    }
+    fn flip(&self, p: Point, label: Option<String>) -> Result<Point, TestError> {
+        assert_eq!(label.as_deref(), Some("p"));
+        Ok(Point { x: p.y, y: p.x })
+    }
+    fn user(&self) -> Result<String, TestError> {
+        let metadata = essrpc::server::request_metadata();
+        essrpc::server::set_response_metadata("seen", "yes");
+        Ok(metadata.get("user").unwrap_or_default().to_string())
+    }
+}
+
+// A format the crate knows nothing about.
+#[derive(Clone, Copy)]
+struct RonFormat;
+
+impl WireFormat for RonFormat {
+    fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, RPCError> {
+        ron::to_string(value)
+            .map(String::into_bytes)
+            .map_err(|e| RPCError::with_cause(RPCErrorKind::SerializationError, "ron", e))
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, RPCError> {
+        ron::de::from_bytes(bytes)
+            .map_err(|e| RPCError::with_cause(RPCErrorKind::SerializationError, "ron", e))
+    }
+}
+
+fn check_sync<F: WireFormat + Copy + Send + 'static>(format: F) {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl, SerdeTransport::new(s2, format)).serve());
+    let client = FooRPCClient::new(SerdeTransport::new(s1, format));
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    assert_eq!(client.expect_error().unwrap_err().msg, "iamerror");
+    assert_eq!(
+        client
+            .flip(Point { x: 1, y: 2 }, Some("p".to_string()))
+            .unwrap(),
+        Point { x: 2, y: 1 }
+    );
+}
+
+#[test]
+fn serde_bincode() {
+    check_sync(BincodeFormat);
+}
+
+#[test]
+fn serde_json() {
+    check_sync(JSONFormat);
+}
+
+#[test]
+fn serde_msgpack() {
+    check_sync(MsgPackFormat);
+}
+
+#[test]
+fn serde_cbor() {
+    check_sync(CBORFormat);
+}
+
+#[test]
+fn serde_user_format() {
+    check_sync(RonFormat);
+}
+
+#[test]
+fn serde_metadata() {
+    use essrpc::ClientTransport;
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl, SerdeTransport::new(s2, JSONFormat)).serve());
+    let mut tr = SerdeTransport::new(s1, JSONFormat);
+    let mut state = tr
+        .tx_begin_call(essrpc::MethodId {
+            name: "user",
+            num: 3,
+        })
+        .unwrap();
+    let metadata: essrpc::Metadata = vec![("user", "alice")].into_iter().collect();
+    tr.tx_set_metadata(&metadata, &mut state).unwrap();
+    tr.tx_finalize(state).unwrap();
+    let user: Result<String, TestError> = tr.rx_response(()).unwrap();
+    assert_eq!(user.unwrap(), "alice");
+    assert_eq!(tr.rx_response_metadata().get("seen"), Some("yes"));
+}
+
+#[test]
+fn serde_truncated_call() {
+    use std::io::Write;
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        FooRPCServer::new(FooImpl, SerdeTransport::new(s2, JSONFormat)).serve_single_call()
+    });
+    // A frame holding a method number and a metadata segment which
+    // claims more bytes than the frame holds.
+    let mut msg = 0u32.to_le_bytes().to_vec();
+    msg.extend_from_slice(&100u32.to_le_bytes());
+    s1.write_all(&(msg.len() as u32).to_le_bytes()).unwrap();
+    s1.write_all(&msg).unwrap();
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::SerializationError);
+}
+
+#[test]
+fn serde_eof() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        FooRPCServer::new(FooImpl, SerdeTransport::new(s2, BincodeFormat)).serve_single_call()
+    });
+    drop(s1);
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+}
+
+#[tokio::test]
+async fn serde_async() {
+    async fn check<F: WireFormat + Copy + Send + 'static>(format: F) {
+        let (s1, s2) = tokio::net::UnixStream::pair().unwrap();
+        // The server isn't actually async, so convert into a non-async Unix stream
+        let s2 = s2.into_std().unwrap();
+        s2.set_nonblocking(false).unwrap();
+        thread::spawn(move || FooRPCServer::new(FooImpl, SerdeTransport::new(s2, format)).serve());
+        let client = FooAsyncRPCClient::new(SerdeAsyncClientTransport::new(s1, format));
+        assert_eq!(
+            client.bar("the answer".to_string(), 42).await.unwrap(),
+            "the answer is 42"
+        );
+        assert_eq!(client.expect_error().await.unwrap_err().msg, "iamerror");
+    }
+    check(BincodeFormat).await;
+    check(JSONFormat).await;
+    check(MsgPackFormat).await;
+    check(CBORFormat).await;
+    check(RonFormat).await;
+}
@@ -0,0 +1,221 @@
+#![cfg(unix)]
+
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::result::Result;
+use std::{fmt, ptr, thread};
+
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{
+    BincodeFormat, BincodeTransport, JSONFormat, SerdeTransport, SerialChannel, SerialFraming,
+};
+use essrpc::{RPCClient, RPCErrorKind, RPCServer};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TestError {
+    msg: String,
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for TestError {}
+impl From<essrpc::RPCError> for TestError {
+    fn from(error: essrpc::RPCError) -> Self {
+        TestError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[essrpc]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError>;
+    fn echo(&self, data: Vec<u8>) -> Result<Vec<u8>, TestError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, TestError> {
+        Ok(format!("{} is {}", a, b))
+    }
+    fn echo(&self, data: Vec<u8>) -> Result<Vec<u8>, TestError> {
+        Ok(data)
+    }
+}
+
+// A pseudo-terminal pair in raw mode, standing in for a serial link.
+// Returns the master and slave ends.
+fn pty() -> (File, File) {
+    let (mut master, mut slave) = (0, 0);
+    unsafe {
+        let ret = libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        );
+        assert_eq!(ret, 0, "openpty failed");
+        let mut termios = std::mem::zeroed();
+        assert_eq!(libc::tcgetattr(slave, &mut termios), 0);
+        libc::cfmakeraw(&mut termios);
+        assert_eq!(libc::tcsetattr(slave, libc::TCSANOW, &termios), 0);
+        (File::from_raw_fd(master), File::from_raw_fd(slave))
+    }
+}
+
+// Bytes including both delimiters and the SLIP escape.
+fn awkward_data() -> Vec<u8> {
+    (0..20_000u32)
+        .map(|i| [0, 0xC0, 0xDB, 1, 0xFF][i as usize % 5] ^ (i / 5) as u8)
+        .collect()
+}
+
+fn check(client: &impl Foo) {
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    let data = awkward_data();
+    assert_eq!(client.echo(data.clone()).unwrap(), data);
+}
+
+#[test]
+fn serial_cobs_pty() {
+    let (master, slave) = pty();
+    thread::spawn(move || {
+        FooRPCServer::new(
+            FooImpl,
+            SerdeTransport::new(SerialChannel::cobs(slave), BincodeFormat),
+        )
+        .serve()
+    });
+    check(&FooRPCClient::new(SerdeTransport::new(
+        SerialChannel::cobs(master),
+        BincodeFormat,
+    )));
+}
+
+#[test]
+fn serial_slip_pty() {
+    let (master, slave) = pty();
+    thread::spawn(move || {
+        FooRPCServer::new(
+            FooImpl,
+            SerdeTransport::new(SerialChannel::slip(slave), JSONFormat),
+        )
+        .serve()
+    });
+    check(&FooRPCClient::new(SerdeTransport::new(
+        SerialChannel::slip(master),
+        JSONFormat,
+    )));
+}
+
+#[test]
+fn serial_bincode_transport() {
+    let (master, slave) = pty();
+    thread::spawn(move || {
+        FooRPCServer::new(FooImpl, BincodeTransport::new(SerialChannel::cobs(slave))).serve()
+    });
+    check(&FooRPCClient::new(BincodeTransport::new(
+        SerialChannel::cobs(master),
+    )));
+}
+
+// A frame holding `payload`, with one byte corrupted.
+fn corrupt_frame(framing: SerialFraming, payload: &[u8]) -> Vec<u8> {
+    let mut channel = SerialChannel::new(Cursor::new(Vec::new()), framing);
+    channel.write_all(payload).unwrap();
+    channel.flush().unwrap();
+    let mut frame = channel.channel().get_ref().clone();
+    frame[5] ^= 0x20;
+    frame
+}
+
+#[test]
+fn serial_resync() {
+    for framing in [SerialFraming::Cobs, SerialFraming::Slip] {
+        let (mut master, slave) = pty();
+        let mut rx = SerialChannel::new(slave, framing);
+        // Line noise, a frame with a bad CRC, then a good frame
+        master.write_all(b"noise").unwrap();
+        master
+            .write_all(&corrupt_frame(framing, b"hello world"))
+            .unwrap();
+        let mut tx = SerialChannel::new(master, framing);
+        tx.write_all(b"goodbye").unwrap();
+        tx.flush().unwrap();
+
+        let mut buf = [0u8; 7];
+        rx.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"goodbye");
+        assert_eq!(rx.dropped_frames(), 2);
+    }
+}
+
+#[test]
+fn serial_resync_server() {
+    let (master, slave) = pty();
+    thread::spawn(move || {
+        FooRPCServer::new(
+            FooImpl,
+            SerdeTransport::new(SerialChannel::cobs(slave), BincodeFormat),
+        )
+        .serve()
+    });
+    // A corrupted frame is dropped by the server, which goes on to
+    // serve the calls which follow.
+    let mut raw = master.try_clone().unwrap();
+    raw.write_all(&corrupt_frame(SerialFraming::Cobs, &[0; 32]))
+        .unwrap();
+    let client = FooRPCClient::new(SerdeTransport::new(
+        SerialChannel::cobs(master),
+        BincodeFormat,
+    ));
+    check(&client);
+}
+
+#[test]
+fn serial_eof() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        FooRPCServer::new(
+            FooImpl,
+            SerdeTransport::new(SerialChannel::cobs(s2), BincodeFormat),
+        )
+        .serve_single_call()
+    });
+    // A partial frame, then EOF
+    s1.write_all(&[0, 5, 1, 2]).unwrap();
+    drop(s1);
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+}
+
+#[test]
+fn serial_max_frame_size() {
+    for framing in [SerialFraming::Cobs, SerialFraming::Slip] {
+        let (master, slave) = pty();
+        let mut rx = SerialChannel::new(slave, framing).with_max_frame_size(16);
+        let mut tx = SerialChannel::new(master, framing);
+        // Too long to gather, too long once decoded, then small enough
+        for payload in [&[1; 100][..], &[1; 20][..], b"small"] {
+            tx.write_all(payload).unwrap();
+            tx.flush().unwrap();
+        }
+        let mut buf = [0u8; 5];
+        rx.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"small");
+        assert_eq!(rx.dropped_frames(), 2);
+    }
+}
@@ -0,0 +1,417 @@
+use std::io::Write;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use essrpc::essrpc;
+use essrpc::server::{ConcurrentServer, Listener, PeerAddr, ShutdownHandle};
+use essrpc::transports::{BincodeTransport, JSONTransport};
+use essrpc::{ClientTransport, MethodId, RPCClient, RPCErrorKind, RPCServer, RemoteError};
+
+#[essrpc(arc)]
+pub trait Counter {
+    fn increment(&self, by: u32) -> Result<u32, RemoteError>;
+    fn sleep(&self, ms: u64) -> Result<u64, RemoteError>;
+    fn fail(&self) -> Result<(), RemoteError>;
+}
+
+#[derive(Default)]
+struct CounterImpl {
+    count: AtomicU32,
+}
+
+impl Counter for CounterImpl {
+    fn increment(&self, by: u32) -> Result<u32, RemoteError> {
+        Ok(self.count.fetch_add(by, Ordering::SeqCst) + by)
+    }
+
+    fn sleep(&self, ms: u64) -> Result<u64, RemoteError> {
+        thread::sleep(Duration::from_millis(ms));
+        Ok(ms)
+    }
+
+    fn fail(&self) -> Result<(), RemoteError> {
+        panic!("failed")
+    }
+}
+
+fn tcp_listener() -> (TcpListener, std::net::SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+#[test]
+fn tcp_shared_implementation() {
+    let (listener, addr) = tcp_listener();
+    let imp = Arc::new(CounterImpl::default());
+    let server_imp = imp.clone();
+    thread::spawn(move || {
+        Listener::new(listener)
+            .serve_bincode(move |tr, _| CounterRPCServer::new(server_imp.clone(), tr))
+    });
+
+    let clients: Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn(move || {
+                let client =
+                    CounterRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+                for _ in 0..10 {
+                    client.increment(1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for c in clients {
+        c.join().unwrap();
+    }
+    assert_eq!(imp.count.load(Ordering::SeqCst), 40);
+}
+
+#[test]
+fn unix_implementation_per_connection() {
+    let path = std::env::temp_dir().join(format!("essrpc-server-test-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let (conn_tx, conn_rx) = mpsc::channel();
+    thread::spawn(move || {
+        Listener::new(listener).serve_json(move |tr, conn| {
+            conn_tx.send(conn.clone()).unwrap();
+            CounterRPCServer::new(CounterImpl::default(), tr)
+        })
+    });
+
+    let c1 = CounterRPCClient::new(JSONTransport::new(UnixStream::connect(&path).unwrap()));
+    let c2 = CounterRPCClient::new(JSONTransport::new(UnixStream::connect(&path).unwrap()));
+    assert_eq!(c1.increment(2).unwrap(), 2);
+    assert_eq!(c2.increment(3).unwrap(), 3);
+    assert_eq!(c1.increment(2).unwrap(), 4);
+
+    let conn1 = conn_rx.recv().unwrap();
+    let conn2 = conn_rx.recv().unwrap();
+    assert_ne!(conn1.id(), conn2.id());
+    assert!(matches!(conn1.peer(), PeerAddr::Unix(_)));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn max_connections() {
+    let (listener, addr) = tcp_listener();
+    thread::spawn(move || {
+        Listener::new(listener)
+            .max_connections(1)
+            .serve_bincode(|tr, _| CounterRPCServer::new(CounterImpl::default(), tr))
+    });
+
+    let c1 = CounterRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+    assert_eq!(c1.increment(1).unwrap(), 1);
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let c2 = CounterRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+        tx.send(c2.increment(5).unwrap()).unwrap();
+    });
+    // The second connection is not served while the first is open
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    drop(c1);
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 5);
+}
+
+#[test]
+fn callbacks() {
+    let (listener, addr) = tcp_listener();
+    let connects = Arc::new(AtomicUsize::new(0));
+    let (disconnect_tx, disconnect_rx) = mpsc::channel();
+    let errors = Arc::new(Mutex::new(Vec::new()));
+
+    let listener = {
+        let connects = connects.clone();
+        let disconnect_tx = Mutex::new(disconnect_tx);
+        let errors = errors.clone();
+        Listener::new(listener)
+            .on_connect(move |conn| {
+                assert!(matches!(conn.peer(), PeerAddr::Tcp(_)));
+                connects.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_disconnect(move |conn| disconnect_tx.lock().unwrap().send(conn.id()).unwrap())
+            .on_error(move |conn, e| errors.lock().unwrap().push((conn.map(|c| c.id()), e.kind)))
+    };
+    thread::spawn(move || {
+        listener.serve_bincode(|tr, _| CounterRPCServer::new(CounterImpl::default(), tr))
+    });
+
+    // A well-behaved client disconnects without error
+    let client = CounterRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+    client.increment(1).unwrap();
+    drop(client);
+    let first = disconnect_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    // A client calling an unknown method causes an error
+    let mut bad = TcpStream::connect(addr).unwrap();
+    bad.write_all(&[4, 0, 0, 0, 0xff, 0xff, 0, 0]).unwrap();
+    let second = disconnect_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    assert_ne!(first, second);
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        *errors.lock().unwrap(),
+        vec![(Some(second), RPCErrorKind::UnknownMethod)]
+    );
+}
+
+#[test]
+fn listener_shutdown() {
+    let (listener, addr) = tcp_listener();
+    let shutdown = ShutdownHandle::new();
+    let server = {
+        let shutdown = shutdown.clone();
+        thread::spawn(move || {
+            Listener::new(listener)
+                .shutdown(shutdown)
+                .drain_timeout(Duration::from_secs(5))
+                .serve_bincode(|tr, _| CounterRPCServer::new(CounterImpl::default(), tr))
+        })
+    };
+
+    // One idle connection and one with a call in progress
+    let idle = CounterRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+    idle.increment(1).unwrap();
+    let busy = thread::spawn(move || {
+        let client =
+            CounterRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+        client.sleep(300)
+    });
+    thread::sleep(Duration::from_millis(100));
+    shutdown.trigger();
+
+    // The call in progress completes, then the listener stops
+    assert_eq!(busy.join().unwrap().unwrap(), 300);
+    server.join().unwrap().unwrap();
+    // The idle connection was closed
+    let e = idle.increment(1).unwrap_err();
+    assert!(e.rpc_error_kind().is_some());
+}
+
+#[test]
+fn server_shutdown() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let shutdown = ShutdownHandle::new();
+    let reader = s2.try_clone().unwrap();
+    let _registration = shutdown.on_shutdown(move || {
+        let _ = reader.shutdown(Shutdown::Read);
+    });
+    let server = {
+        let shutdown = shutdown.clone();
+        thread::spawn(move || {
+            CounterRPCServer::new(CounterImpl::default(), BincodeTransport::new(s2))
+                .with_shutdown(shutdown)
+                .serve()
+        })
+    };
+    let client = CounterRPCClient::new(BincodeTransport::new(s1));
+    assert_eq!(client.increment(2).unwrap(), 2);
+    shutdown.trigger();
+    assert!(server.join().unwrap().is_ok());
+    assert!(shutdown.is_triggered());
+}
+
+#[test]
+#[cfg(feature = "signal_shutdown")]
+fn sigterm_shutdown() {
+    let shutdown = ShutdownHandle::new();
+    shutdown.trigger_on_sigterm().unwrap();
+    signal_hook::low_level::raise(signal_hook::consts::SIGTERM).unwrap();
+    shutdown.wait();
+}
+
+// Start a call of Counter::sleep and a call of Counter::increment
+// without waiting for either response, then read the responses in
+// the opposite order. Returns the time taken for the second call.
+fn pipelined_calls<TR: ClientTransport>(tr: &mut TR) -> Duration {
+    let start = Instant::now();
+    let mut sleep = tr
+        .tx_begin_call(MethodId {
+            name: "sleep",
+            num: 1,
+        })
+        .unwrap();
+    tr.tx_add_param("ms", 300u64, &mut sleep).unwrap();
+    let sleep = tr.tx_finalize(sleep).unwrap();
+    let mut increment = tr
+        .tx_begin_call(MethodId {
+            name: "increment",
+            num: 0,
+        })
+        .unwrap();
+    tr.tx_add_param("by", 1u32, &mut increment).unwrap();
+    let increment = tr.tx_finalize(increment).unwrap();
+
+    let r: Result<u32, RemoteError> = tr.rx_response(increment).unwrap();
+    assert_eq!(r.unwrap(), 1);
+    let elapsed = start.elapsed();
+    let r: Result<u64, RemoteError> = tr.rx_response(sleep).unwrap();
+    assert_eq!(r.unwrap(), 300);
+    elapsed
+}
+
+fn serve_concurrent<TR>(transport: TR, max_concurrency: usize)
+where
+    TR: essrpc::ConcurrentServerTransport + Send + 'static,
+{
+    let imp = Arc::new(CounterImpl::default());
+    thread::spawn(move || {
+        ConcurrentServer::new(transport, max_concurrency, move |call| {
+            CounterRPCServer::new(imp.clone(), call)
+        })
+        .serve()
+    });
+}
+
+#[test]
+fn concurrent_bincode() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve_concurrent(BincodeTransport::new(s2).with_request_ids(), 4);
+    let elapsed = pipelined_calls(&mut BincodeTransport::new(s1).with_request_ids());
+    assert!(elapsed < Duration::from_millis(250));
+}
+
+#[test]
+fn concurrent_json() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve_concurrent(JSONTransport::new(s2).with_request_ids(), 4);
+    let elapsed = pipelined_calls(&mut JSONTransport::new(s1).with_request_ids());
+    assert!(elapsed < Duration::from_millis(250));
+}
+
+#[test]
+fn concurrent_max_concurrency() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    serve_concurrent(BincodeTransport::new(s2).with_request_ids(), 1);
+    // The second call waits for the first
+    let elapsed = pipelined_calls(&mut BincodeTransport::new(s1).with_request_ids());
+    assert!(elapsed >= Duration::from_millis(300));
+}
+
+#[test]
+fn concurrent_worker_survives_panic() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let imp = Arc::new(CounterImpl::default());
+    let server = thread::spawn(move || {
+        let mut server = ConcurrentServer::new(
+            BincodeTransport::new(s2).with_request_ids(),
+            1,
+            move |call| CounterRPCServer::new(imp.clone(), call).catch_panics(false),
+        );
+        let mut errors = Vec::new();
+        loop {
+            match server.serve_single_call() {
+                Ok(()) => {}
+                Err(e) if e.kind == RPCErrorKind::TransportEOF => return errors,
+                Err(e) => errors.push(e.kind),
+            }
+        }
+    });
+    s1.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut tr = BincodeTransport::new(s1).with_request_ids();
+    let fail = tr
+        .tx_begin_call(MethodId {
+            name: "fail",
+            num: 2,
+        })
+        .unwrap();
+    tr.tx_finalize(fail).unwrap();
+    // Served by the only worker, once the call which panicked is over
+    let mut increment = tr
+        .tx_begin_call(MethodId {
+            name: "increment",
+            num: 0,
+        })
+        .unwrap();
+    tr.tx_add_param("by", 1u32, &mut increment).unwrap();
+    let increment = tr.tx_finalize(increment).unwrap();
+    let r: Result<u32, RemoteError> = tr.rx_response(increment).unwrap();
+    assert_eq!(r.unwrap(), 1);
+    drop(tr);
+    assert_eq!(server.join().unwrap(), vec![RPCErrorKind::ServerPanic]);
+}
+
+#[test]
+fn client_per_caller() {
+    // Generated clients on connections of their own are not held up
+    // by each other's calls
+    let (listener, addr) = tcp_listener();
+    let imp = Arc::new(CounterImpl::default());
+    thread::spawn(move || {
+        Listener::new(listener).serve_bincode(move |tr, _| CounterRPCServer::new(imp.clone(), tr))
+    });
+    let connect =
+        || CounterRPCClient::new(BincodeTransport::new(TcpStream::connect(addr).unwrap()));
+    let (slow, fast) = (connect(), connect());
+    let start = Instant::now();
+    let sleeper = thread::spawn(move || slow.sleep(300).unwrap());
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(fast.increment(1).unwrap(), 1);
+    assert!(start.elapsed() < Duration::from_millis(250));
+    assert_eq!(sleeper.join().unwrap(), 300);
+}
+
+#[test]
+fn concurrent_listener() {
+    let (listener, addr) = tcp_listener();
+    let imp = Arc::new(CounterImpl::default());
+    thread::spawn(move || {
+        Listener::new(listener).serve(move |stream, _| {
+            let imp = imp.clone();
+            ConcurrentServer::new(
+                JSONTransport::new(stream).with_request_ids(),
+                4,
+                move |call| CounterRPCServer::new(imp.clone(), call),
+            )
+        })
+    });
+    let client = CounterRPCClient::new(
+        JSONTransport::new(TcpStream::connect(addr).unwrap()).with_request_ids(),
+    );
+    assert_eq!(client.increment(2).unwrap(), 2);
+    assert_eq!(client.sleep(1).unwrap(), 1);
+    assert_eq!(client.increment(3).unwrap(), 5);
+}
+
+#[test]
+fn request_ids_sequential_server() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        CounterRPCServer::new(
+            CounterImpl::default(),
+            BincodeTransport::new(s2).with_request_ids(),
+        )
+        .serve()
+    });
+    let client = CounterRPCClient::new(BincodeTransport::new(s1).with_request_ids());
+    assert_eq!(client.increment(2).unwrap(), 2);
+    assert_eq!(client.increment(3).unwrap(), 5);
+}
+
+#[test]
+fn request_ids_unexpected_response_dropped() {
+    let (s1, mut s2) = UnixStream::pair().unwrap();
+    // A response to request 1, arriving before the client makes it
+    let mut stale = 1u32.to_le_bytes().to_vec();
+    stale.extend_from_slice(&0u32.to_le_bytes());
+    stale.extend_from_slice(&99u32.to_le_bytes());
+    s2.write_all(&(stale.len() as u32).to_le_bytes()).unwrap();
+    s2.write_all(&stale).unwrap();
+    thread::spawn(move || {
+        CounterRPCServer::new(
+            CounterImpl::default(),
+            BincodeTransport::new(s2).with_request_ids(),
+        )
+        .serve()
+    });
+    let client = CounterRPCClient::new(BincodeTransport::new(s1).with_request_ids());
+    assert_eq!(client.increment(2).unwrap(), 2);
+    assert_eq!(client.increment(3).unwrap(), 5);
+}
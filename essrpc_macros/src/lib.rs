@@ -8,14 +8,21 @@ extern crate proc_macro2;
 extern crate quote;
 extern crate syn;
 
+#[cfg(feature = "loopback")]
+mod loopback;
+#[cfg(feature = "mock")]
+mod mock;
+
 use core::convert::AsRef;
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 use proc_macro2::{Ident, Span, TokenTree};
-use quote::quote;
+use quote::{quote, quote_spanned, ToTokens};
+use std::collections::HashMap;
 use syn::{
-    punctuated::Punctuated, token::Comma, /*spanned::Spanned,*/ FnArg, ItemTrait, LitStr, Pat,
-    TraitItem, TraitItemMethod,
+    punctuated::Punctuated, spanned::Spanned, token::Comma, Attribute, FnArg, GenericArgument,
+    ItemTrait, Lit, LitStr, Meta, MetaNameValue, NestedMeta, Pat, PathArguments, TraitItem,
+    TraitItemMethod, TraitItemType, Type, Visibility,
 };
 
 /// The main macro which does the magic. When applied to a trait `Foo`
@@ -29,58 +36,418 @@ use syn::{
 /// `Result` and a `FooAsyncRPCClient` type implementing `FooAsync`
 /// and [AsyncRPCClient](../essrpc/trait.AsyncRPCClient.html).
 ///
+/// Methods with a default implementation are normally called over
+/// RPC like any other. Marking such a method `#[essrpc(local)]`
+/// instead makes the client run the default implementation locally
+/// (which may in turn call remote methods); `#[essrpc(remote)]`
+/// states the default explicitly. Associated functions without a
+/// `self` parameter are always local, and local methods are omitted
+/// from the async trait. Associated consts must have a default value.
+///
+/// Methods returning a `Result` with a single type argument (such as
+/// `anyhow::Result<T>`, `eyre::Result<T>`, or `Result<T>` with either
+/// imported), or a `Result` whose error is `Box<dyn Error + Send +
+/// Sync>`, `anyhow::Error` or `eyre::Report`, transmit errors as a
+/// [GenericSerializableError](../essrpc/struct.GenericSerializableError.html).
+/// `#[essrpc(dyn_error)]` on a method requests the same treatment
+/// for a `Result` alias not recognized by name. Methods returning
+/// any type not named `Result` are treated as infallible: when
+/// the RPC call itself fails, the client panics, or returns
+/// `Default::default()` if `on_error = "default"` is given on the
+/// trait attribute or as `#[essrpc(on_error = "default")]` on the method.
+///
+/// One parameter of a method may be marked `#[essrpc(context)]`, with
+/// a reference type such as `&CallContext`. It is not transmitted;
+/// the server passes a
+/// [CallContext](../essrpc/struct.CallContext.html) describing
+/// the call, and the client sends the metadata and deadline of the
+/// context it is given. Context parameters require the `std` feature
+/// of essrpc.
+///
+/// Associated types of the trait become type parameters of the
+/// generated clients, following the transport type, so that for
+/// `trait Repo { type Item: ...; }` the client is
+/// `RepoRPCClient<TR, Item>`. The server uses the associated types of
+/// its implementation.
+///
+/// With the `loopback_transport` feature of essrpc enabled, the server
+/// additionally has `loopback(imp)` (for a sync client) and
+/// `loopback_async(imp)` (for an async client) constructors, which
+/// return a client calling `imp` in the same process through a
+/// [LoopbackTransport](../essrpc/transports/struct.LoopbackTransport.html).
+///
+/// With the `mock` feature of essrpc enabled, `#[essrpc(mock)]` also
+/// generates `MockFoo` implementing `Foo` (and `FooAsync` if an async
+/// client is generated) for use in unit tests. See the
+/// [mock](../essrpc/mock/index.html) module.
+///
+/// `#[essrpc(arc)]` also implements the trait for `Arc<T>` where `T`
+/// implements it, so that one implementation may be shared by the
+/// servers for many connections (see `essrpc::server::Listener`). This
+/// is skipped if the trait has required methods which cannot be
+/// forwarded (those not taking `&self`).
+///
+/// The generated types and traits have the same visibility as the
+/// annotated trait. Their names may be overridden with
+/// `client = "..."`, `async_client = "..."`, `async_trait = "..."`
+/// and `server = "..."` arguments, e.g.
+/// `#[essrpc(sync, client = "FooClient", server = "FooServer")]`.
+///
 /// See the crate-level documentation for examples.
 #[proc_macro_attribute]
 pub fn essrpc(args: TokenStream, input: TokenStream) -> TokenStream {
-    let args: TokenStream2 = args.into();
-    let mut sync_client = false;
-    let mut async_client = false;
-    for tok in args {
-        if let TokenTree::Ident(ident) = tok {
-            match ident.to_string().as_ref() {
-                "sync" => sync_client = true,
-                "async" => async_client = true,
-                _ => (),
-            }
+    let mut args = match parse_args(args.into()) {
+        Ok(args) => args,
+        Err(e) => {
+            // Keep the trait so that uses of it are not also errors
+            let input = TokenStream2::from(input);
+            let error = e.to_compile_error();
+            return quote!(#input #error).into();
         }
-    }
-
-    if !sync_client && !async_client {
-        sync_client = true
-    }
-
-    let mut result: TokenStream2 = input.clone().into();
+    };
 
     // TODO better error handling
-    let ast_trait: ItemTrait = syn::parse(input).unwrap();
-
-    let trait_ident = ast_trait.ident;
+    let mut ast_trait: ItemTrait = syn::parse(input).unwrap();
+
+    let trait_ident = ast_trait.ident.clone();
+    let mut info = TraitInfo {
+        vis: ast_trait.vis.clone(),
+        cfg_attrs: cfg_attrs(&ast_trait.attrs),
+        on_error: args.on_error.unwrap_or(ErrorPolicy::Panic),
+        assoc_types: Vec::new(),
+        context_params: HashMap::new(),
+    };
 
     let mut methods: Vec<TraitItemMethod> = Vec::new();
+    let mut errors = TokenStream2::new();
 
     // Look at each method
-    for item in ast_trait.items {
-        if let TraitItem::Method(m) = item {
-            methods.push(m.clone());
+    for item in &mut ast_trait.items {
+        match item {
+            TraitItem::Method(m) => {
+                // Validates the method's essrpc attributes.
+                is_remote(m);
+                if let Some(index) = take_context_param(m) {
+                    if !cfg!(feature = "std") {
+                        // CallContext only exists with std
+                        let msg = format!(
+                            "The context parameter of RPC trait method {} requires the std feature of essrpc",
+                            m.sig.ident
+                        );
+                        errors.extend(
+                            quote_spanned!(m.sig.inputs[index].span()=> compile_error!(#msg);),
+                        );
+                    }
+                    info.context_params.insert(m.sig.ident.to_string(), index);
+                }
+                methods.push(m.clone());
+                // essrpc attributes on methods are only meaningful to us, strip them
+                m.attrs.retain(|a| !a.path.is_ident("essrpc"));
+            }
+            TraitItem::Const(c) if c.default.is_none() => panic!(
+                "RPC trait associated const {} must have a default value",
+                c.ident
+            ),
+            TraitItem::Type(t) => {
+                if !t.generics.params.is_empty() || t.generics.where_clause.is_some() {
+                    panic!(
+                        "RPC trait associated type {} may not be generic or have a where clause",
+                        t.ident
+                    );
+                }
+                info.assoc_types.push(t.clone());
+            }
+            _ => (),
         }
     }
 
-    if async_client {
-        result.extend(create_async_client_trait(&trait_ident, &methods));
+    if !errors.is_empty() {
+        return quote!(#ast_trait #errors).into();
+    }
+
+    let mut result = quote!(#ast_trait);
+
+    let async_trait_ident = if args.async_client {
+        Some(
+            args.async_trait_ident
+                .take()
+                .unwrap_or_else(|| async_client_trait_ident(&trait_ident)),
+        )
+    } else {
+        None
+    };
+    let async_client_ident = match &async_trait_ident {
+        Some(async_trait_ident) => Some(
+            args.async_client_ident
+                .take()
+                .unwrap_or_else(|| client_ident(async_trait_ident)),
+        ),
+        None => None,
+    };
+    let sync_client_ident = if args.sync_client {
+        Some(
+            args.client_ident
+                .take()
+                .unwrap_or_else(|| client_ident(&trait_ident)),
+        )
+    } else {
+        None
+    };
+
+    if let (Some(async_trait_ident), Some(async_client_ident)) =
+        (&async_trait_ident, &async_client_ident)
+    {
+        result.extend(create_async_client_trait(
+            async_trait_ident,
+            &methods,
+            &info,
+        ));
         result.extend(create_client(
-            &async_client_trait_ident(&trait_ident),
+            async_trait_ident,
+            async_client_ident,
             &methods,
+            &info,
             true,
         ));
     }
-    if sync_client {
-        result.extend(create_client(&trait_ident, &methods, false));
+    if let Some(client_ident) = &sync_client_ident {
+        result.extend(create_client(
+            &trait_ident,
+            client_ident,
+            &methods,
+            &info,
+            false,
+        ));
+    }
+    let server_ident = args
+        .server_ident
+        .take()
+        .unwrap_or_else(|| server_ident(&trait_ident));
+    result.extend(create_server(&trait_ident, &server_ident, &methods, &info));
+    if args.arc {
+        if !cfg!(feature = "std") {
+            panic!("The std feature of essrpc must be enabled to use #[essrpc(arc)]");
+        }
+        result.extend(create_arc_impl(&ast_trait, &info));
+    }
+
+    #[cfg(feature = "loopback")]
+    result.extend(loopback::create_loopback(
+        &trait_ident,
+        &server_ident,
+        sync_client_ident.as_ref(),
+        async_client_ident.as_ref(),
+        &info,
+    ));
+
+    if args.mock {
+        #[cfg(feature = "mock")]
+        result.extend(mock::create_mock(
+            &trait_ident,
+            async_trait_ident.as_ref(),
+            &methods,
+            &info,
+        ));
+        #[cfg(not(feature = "mock"))]
+        panic!("The mock feature of essrpc must be enabled to use #[essrpc(mock)]");
     }
-    result.extend(create_server(&trait_ident, &methods));
 
     result.into()
 }
 
+/// Arguments given to the `essrpc` attribute.
+#[derive(Default)]
+struct Args {
+    sync_client: bool,
+    async_client: bool,
+    mock: bool,
+    arc: bool,
+    client_ident: Option<Ident>,
+    async_client_ident: Option<Ident>,
+    async_trait_ident: Option<Ident>,
+    server_ident: Option<Ident>,
+    on_error: Option<ErrorPolicy>,
+}
+
+fn parse_args(args: TokenStream2) -> syn::Result<Args> {
+    let mut parsed = Args::default();
+    let mut tokens = args.into_iter().peekable();
+    while let Some(tok) = tokens.next() {
+        let ident = match tok {
+            TokenTree::Ident(ident) => ident,
+            TokenTree::Punct(p) if p.as_char() == ',' => continue,
+            other => {
+                return Err(syn::Error::new(
+                    other.span(),
+                    format!("Unexpected essrpc argument {}", other),
+                ))
+            }
+        };
+        let is_assignment =
+            matches!(tokens.peek(), Some(TokenTree::Punct(p)) if p.as_char() == '=');
+        if !is_assignment {
+            match ident.to_string().as_ref() {
+                "sync" => parsed.sync_client = true,
+                "async" => parsed.async_client = true,
+                "mock" => parsed.mock = true,
+                "arc" => parsed.arc = true,
+                _ => {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        format!("Unknown essrpc argument {}", ident),
+                    ))
+                }
+            }
+            continue;
+        }
+        tokens.next(); // the '='
+        let value = match tokens.next() {
+            Some(TokenTree::Literal(lit)) => lit,
+            _ => {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!(
+                        "essrpc argument {} must be assigned a string literal",
+                        ident
+                    ),
+                ))
+            }
+        };
+        let value: LitStr = syn::parse2(TokenTree::Literal(value).into()).map_err(|e| {
+            syn::Error::new(
+                e.span(),
+                format!("essrpc argument {} must be a string literal", ident),
+            )
+        })?;
+        if ident == "on_error" {
+            parsed.on_error = Some(ErrorPolicy::parse(&value)?);
+            continue;
+        }
+        let value = Some(Ident::new(&value.value(), value.span()));
+        match ident.to_string().as_ref() {
+            "client" => parsed.client_ident = value,
+            "async_client" => parsed.async_client_ident = value,
+            "async_trait" => parsed.async_trait_ident = value,
+            "server" => parsed.server_ident = value,
+            _ => {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!("Unknown essrpc argument {}", ident),
+                ))
+            }
+        }
+    }
+
+    if !parsed.sync_client && !parsed.async_client {
+        parsed.sync_client = true
+    }
+    Ok(parsed)
+}
+
+/// Properties of the annotated trait which are carried over to all
+/// generated items.
+struct TraitInfo {
+    vis: Visibility,
+    cfg_attrs: Vec<Attribute>,
+    // Default policy for methods whose return type is not a Result.
+    on_error: ErrorPolicy,
+    // Associated types become type parameters of the generated clients.
+    assoc_types: Vec<TraitItemType>,
+    // Index (within the inputs) of each method's context parameter.
+    context_params: HashMap<String, usize>,
+}
+
+impl TraitInfo {
+    fn assoc_type_idents(&self) -> Vec<&Ident> {
+        self.assoc_types.iter().map(|t| &t.ident).collect()
+    }
+
+    // True if the parameter at `index` of the method's inputs is its
+    // context parameter, which is not transmitted.
+    fn is_context_param(&self, method: &TraitItemMethod, index: usize) -> bool {
+        self.context_params.get(&method.sig.ident.to_string()) == Some(&index)
+    }
+}
+
+// Find the method's parameter marked `#[essrpc(context)]`, if any,
+// returning its index. essrpc attributes are stripped from all
+// parameters.
+fn take_context_param(method: &mut TraitItemMethod) -> Option<usize> {
+    let method_ident = method.sig.ident.clone();
+    let mut context = None;
+    for (index, p) in method.sig.inputs.iter_mut().enumerate() {
+        let arg = match p {
+            FnArg::Typed(arg) => arg,
+            FnArg::Receiver(_) => continue,
+        };
+        for attr in arg.attrs.iter().filter(|a| a.path.is_ident("essrpc")) {
+            let is_context = match attr.parse_meta() {
+                Ok(Meta::List(list)) => list.nested.iter().all(
+                    |nested| matches!(nested, NestedMeta::Meta(Meta::Path(p)) if p.is_ident("context")),
+                ),
+                _ => false,
+            };
+            if !is_context {
+                panic!(
+                    "Unknown essrpc attribute argument on a parameter of method {}",
+                    method_ident
+                );
+            }
+            if context.is_some() {
+                panic!(
+                    "RPC trait method {} has several context parameters",
+                    method_ident
+                );
+            }
+            if !matches!(*arg.ty, Type::Reference(_)) || !matches!(*arg.pat, Pat::Ident(_)) {
+                panic!(
+                    "The context parameter of RPC trait method {} must be a named reference",
+                    method_ident
+                );
+            }
+            context = Some(index);
+        }
+        arg.attrs.retain(|a| !a.path.is_ident("essrpc"));
+    }
+    context
+}
+
+// Replace any use of `Self` with `replacement`. Used to refer to
+// associated types through the implementation type parameter
+// (e.g. `Self::Item` becomes `T::Item`).
+fn replace_self(tokens: TokenStream2, replacement: &Ident) -> TokenStream2 {
+    tokens
+        .into_iter()
+        .map(|tok| match tok {
+            TokenTree::Ident(ref ident) if ident == "Self" => TokenTree::Ident(replacement.clone()),
+            TokenTree::Group(g) => {
+                let mut new_group =
+                    proc_macro2::Group::new(g.delimiter(), replace_self(g.stream(), replacement));
+                new_group.set_span(g.span());
+                TokenTree::Group(new_group)
+            }
+            other => other,
+        })
+        .collect()
+}
+
+// Attributes which should follow a trait method onto generated methods.
+fn method_attrs(method: &TraitItemMethod) -> Vec<&Attribute> {
+    method
+        .attrs
+        .iter()
+        .filter(|a| a.path.is_ident("doc") || a.path.is_ident("cfg"))
+        .collect()
+}
+
+fn cfg_attrs(attrs: &[Attribute]) -> Vec<Attribute> {
+    attrs
+        .iter()
+        .filter(|a| a.path.is_ident("cfg"))
+        .cloned()
+        .collect()
+}
+
 fn client_ident(trait_ident: &Ident) -> Ident {
     Ident::new(&format!("{}RPCClient", trait_ident), Span::call_site())
 }
@@ -127,20 +494,100 @@ fn make_ident_literal_str(ident: &Ident) -> LitStr {
     LitStr::new(&as_str, Span::call_site())
 }
 
-// True if has self param, false if has default implementation. Panics
-// if no self and no default.
-fn verify_self_param_or_unneeded(method: &TraitItemMethod) -> bool {
-    if has_self_param(method) {
-        return true;
+/// What a client does when a call to a method whose return type is
+/// not a `Result` fails in the RPC machinery.
+#[derive(Clone, Copy)]
+enum ErrorPolicy {
+    Panic,
+    Default,
+}
+
+impl ErrorPolicy {
+    fn parse(lit: &LitStr) -> syn::Result<Self> {
+        match lit.value().as_ref() {
+            "panic" => Ok(ErrorPolicy::Panic),
+            "default" => Ok(ErrorPolicy::Default),
+            other => Err(syn::Error::new(
+                lit.span(),
+                format!(
+                    "Unknown essrpc on_error policy {}, expected panic or default",
+                    other
+                ),
+            )),
+        }
+    }
+}
+
+/// Options given by `#[essrpc(...)]` attributes on a trait method.
+#[derive(Default)]
+struct MethodArgs {
+    // Some(true) for local, Some(false) for remote
+    local: Option<bool>,
+    dyn_error: bool,
+    on_error: Option<ErrorPolicy>,
+}
+
+fn parse_method_args(method: &TraitItemMethod) -> MethodArgs {
+    let mut args = MethodArgs::default();
+    for attr in method.attrs.iter().filter(|a| a.path.is_ident("essrpc")) {
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list,
+            _ => panic!("Malformed essrpc attribute on method {}", &method.sig.ident),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::Path(p)) if p.is_ident("local") => args.local = Some(true),
+                NestedMeta::Meta(Meta::Path(p)) if p.is_ident("remote") => args.local = Some(false),
+                NestedMeta::Meta(Meta::Path(p)) if p.is_ident("dyn_error") => args.dyn_error = true,
+                NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                    path,
+                    lit: Lit::Str(lit),
+                    ..
+                })) if path.is_ident("on_error") => {
+                    args.on_error =
+                        Some(ErrorPolicy::parse(&lit).unwrap_or_else(|e| panic!("{}", e)))
+                }
+                _ => panic!(
+                    "Unknown essrpc attribute argument on method {}",
+                    &method.sig.ident
+                ),
+            }
+        }
     }
-    if method.default.is_some() {
-        // this method is not needed for the RPC client
+    args
+}
+
+// True if the method is called over RPC, false if the client runs
+// its default implementation (always the case for associated
+// functions without a self param). Panics if the method cannot be
+// handled either way.
+fn is_remote(method: &TraitItemMethod) -> bool {
+    let local = parse_method_args(method).local;
+    if !has_self_param(method) {
+        if method.default.is_none() {
+            panic!(
+                "RPC trait method {} has no self param and no default implementation",
+                &method.sig.ident
+            );
+        }
+        if local == Some(false) {
+            panic!(
+                "RPC trait method {} has no self param and cannot be remote",
+                &method.sig.ident
+            );
+        }
         return false;
     }
-    panic!(
-        "RPC trait method {:?} has no self param and no default implementation",
-        method
-    );
+    if local == Some(true) {
+        if method.default.is_none() {
+            panic!(
+                "RPC trait method {} is local but has no default implementation",
+                &method.sig.ident
+            );
+        }
+        return false;
+    }
+    true
 }
 
 fn has_self_param(method: &TraitItemMethod) -> bool {
@@ -150,76 +597,206 @@ fn has_self_param(method: &TraitItemMethod) -> bool {
 }
 
 // Client method implementation for the call to tx_begin_call through
-// tx_finalize. This portion is shared between sync and async.
-fn client_method_tx_send(method: &TraitItemMethod, id: u32) -> TokenStream2 {
+// rx_response, with interceptors invoked around it. Each attempt is
+// made by `attempt`, a closure or async block. Shared between sync
+// and async.
+fn client_method_call(
+    method: &TraitItemMethod,
+    id: u32,
+    info: &TraitInfo,
+    async_client: bool,
+) -> TokenStream2 {
     let ident = &method.sig.ident;
     let param_tokens = &method.sig.inputs;
+    let ident_literal = make_ident_literal_str(ident);
+    let dot_await = if async_client {
+        Some(quote!(.await))
+    } else {
+        None
+    };
 
     let mut add_param_tokens = TokenStream2::new();
-
-    for p in param_tokens.iter() {
+    let mut with_context = None;
+    for (index, p) in param_tokens.iter().enumerate() {
         if let FnArg::Typed(arg) = p {
             let name = &arg.pat;
+            if info.is_context_param(method, index) {
+                with_context = Some(quote!(.with_context(#name)));
+                continue;
+            }
             let name_literal = make_pat_literal_str(name);
-            add_param_tokens.extend(quote!(tr.tx_add_param(#name_literal, #name, &mut state)?;));
+            add_param_tokens.extend(quote!(
+                self.interceptors.lock().on_param(&call, #name_literal, &#name);
+                tr.tx_add_param(#name_literal, &#name, &mut state)#dot_await?;
+            ));
         }
     }
 
-    let ident_literal = make_ident_literal_str(ident);
-    quote!(
-        let mut tr = self.tr.lock();
-        let mut state = tr.tx_begin_call(essrpc::MethodId{name: #ident_literal, num: #id})?;
+    let attempt_body = quote!(
+        self.interceptors.lock().before_call(&mut call)?;
+        let mut tr = self.tr.lock()#dot_await;
+        let mut state = tr.tx_begin_call(call.method())#dot_await?;
+        tr.tx_set_metadata(call.metadata(), &mut state)#dot_await?;
         #add_param_tokens
-        let state = tr.tx_finalize(state)?;
-    )
-}
-
-fn async_client_method_tx_send(method: &TraitItemMethod, id: u32) -> TokenStream2 {
-    let ident = &method.sig.ident;
-    let param_tokens = &method.sig.inputs;
-
-    let mut add_param_tokens = TokenStream2::new();
-
-    for p in param_tokens.iter() {
-        if let FnArg::Typed(arg) = p {
-            let name = &arg.pat;
-            let name_literal = make_pat_literal_str(name);
-            add_param_tokens
-                .extend(quote!(tr.tx_add_param(#name_literal, #name, &mut state).await?;));
-        }
-    }
+        let state = tr.tx_finalize(state)#dot_await?;
+        let ret = tr.rx_response(state)#dot_await;
+        call.set_response_metadata(tr.rx_response_metadata()#dot_await);
+        ret
+    );
+    let attempt = if async_client {
+        quote!(async { #attempt_body }.await)
+    } else {
+        quote!((|| { #attempt_body })())
+    };
 
-    let ident_literal = make_ident_literal_str(ident);
     quote!(
-        let mut tr = self.tr.lock().await;
-        let mut state = tr.tx_begin_call(essrpc::MethodId{name: #ident_literal, num: #id}).await?;
-        #add_param_tokens
-        let state = tr.tx_finalize(state).await?;
+        let mut call = essrpc::client::ClientCall::new(
+            essrpc::MethodId{name: #ident_literal, num: #id})#with_context;
+        loop {
+            let ret = #attempt;
+            if let Some(ret) = self.interceptors.lock().after_call(&mut call, ret) {
+                return ret;
+            }
+        }
     )
 }
 
-fn impl_client_method(method: &TraitItemMethod, id: u32) -> TokenStream2 {
+fn impl_client_method(method: &TraitItemMethod, id: u32, info: &TraitInfo) -> TokenStream2 {
     let ident = &method.sig.ident;
     let param_tokens = &method.sig.inputs;
 
-    if !verify_self_param_or_unneeded(method) {
+    if !is_remote(method) {
         return TokenStream2::new();
     }
 
     let rettype = get_return_type(method);
+    let wire_type = wire_return_type(method, info);
+    let convert = client_convert_return(method, info);
+    let attrs = method_attrs(method);
 
-    let tx_send = client_method_tx_send(method, id);
+    let call = client_method_call(method, id, info, false);
 
     quote!(
+    #(#attrs)*
     fn #ident(#param_tokens) -> #rettype {
-        #tx_send
-        let ret: std::result::Result<#rettype, essrpc::RPCError> =
-            tr.rx_response(state);
-        match ret {
+        let ret: essrpc::internal::result::Result<#wire_type, essrpc::RPCError> = (|| {
+            #call
+        })();
+        #convert
+    })
+}
+
+/// How the return type of a remote method is transmitted.
+enum ReturnKind<'a> {
+    /// A `Result` whose error type implements `From<RPCError>` and
+    /// `Serialize`. Sent as is.
+    Result,
+    /// A `Result` whose error type is dynamic (e.g. `anyhow::Error`
+    /// or `Box<dyn Error + Send + Sync>`), with the given success
+    /// type. The error is sent as a `GenericSerializableError`.
+    DynError(&'a Type),
+    /// Any other type. Sent as is, with the given policy for RPC failures.
+    Infallible(ErrorPolicy),
+}
+
+// Crates whose error types (`anyhow::Error`, `eyre::Report`) are dynamic.
+const DYN_ERROR_CRATES: &[&str] = &["anyhow", "eyre", "color_eyre"];
+
+fn return_kind<'a>(method: &'a TraitItemMethod, info: &TraitInfo) -> ReturnKind<'a> {
+    let args = parse_method_args(method);
+    let rettype = get_return_type(method);
+    let path = match rettype {
+        Type::Path(p) if p.qself.is_none() => &p.path,
+        _ => return ReturnKind::Infallible(args.on_error.unwrap_or(info.on_error)),
+    };
+    let last = path.segments.last().unwrap();
+    if last.ident != "Result" {
+        return ReturnKind::Infallible(args.on_error.unwrap_or(info.on_error));
+    }
+    let generic_args: Vec<&Type> = match &last.arguments {
+        PathArguments::AngleBracketed(a) => a
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                GenericArgument::Type(t) => Some(t),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    // A `Result` alias with only a success type, such as
+    // `anyhow::Result`, hides its error type, which is assumed dynamic
+    let dyn_error = args.dyn_error
+        || match generic_args.len() {
+            1 => true,
+            2 => is_dyn_error_type(generic_args[1]),
+            _ => false,
+        };
+    if dyn_error {
+        match generic_args.first() {
+            Some(ok) => ReturnKind::DynError(ok),
+            None => panic!(
+                "Cannot determine the success type of RPC method {}",
+                &method.sig.ident
+            ),
+        }
+    } else {
+        ReturnKind::Result
+    }
+}
+
+// True for `Box<dyn ...>` and the error types of anyhow and eyre.
+fn is_dyn_error_type(ty: &Type) -> bool {
+    let path = match ty {
+        Type::Path(p) if p.qself.is_none() => &p.path,
+        _ => return false,
+    };
+    let last = path.segments.last().unwrap();
+    if last.ident == "Box" {
+        return matches!(&last.arguments, PathArguments::AngleBracketed(a)
+                        if matches!(a.args.first(), Some(GenericArgument::Type(Type::TraitObject(_)))));
+    }
+    path.segments.len() > 1
+        && DYN_ERROR_CRATES.iter().any(|c| path.segments[0].ident == c)
+        && (last.ident == "Error" || last.ident == "Report")
+}
+
+// Type the server transmits for a method's return value and the
+// client reads back.
+fn wire_return_type(method: &TraitItemMethod, info: &TraitInfo) -> TokenStream2 {
+    match return_kind(method, info) {
+        ReturnKind::DynError(ok) => {
+            quote!(essrpc::internal::result::Result<#ok, essrpc::GenericSerializableError>)
+        }
+        _ => get_return_type(method).to_token_stream(),
+    }
+}
+
+// Client conversion of `ret`, a `Result<wire type, RPCError>`, into
+// the method's return type.
+fn client_convert_return(method: &TraitItemMethod, info: &TraitInfo) -> TokenStream2 {
+    match return_kind(method, info) {
+        ReturnKind::Result => quote!(match ret {
             Ok(v) => v,
-            Err(e) => Err(e.into())
+            Err(e) => Err(e.into()),
+        }),
+        ReturnKind::DynError(_) => quote!(match ret {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        }),
+        ReturnKind::Infallible(ErrorPolicy::Panic) => {
+            let ident_literal = make_ident_literal_str(&method.sig.ident);
+            quote!(match ret {
+                Ok(v) => v,
+                Err(e) => panic!("RPC call to {} failed: {}", #ident_literal, e),
+            })
         }
-    })
+        ReturnKind::Infallible(ErrorPolicy::Default) => quote!(match ret {
+            Ok(v) => v,
+            Err(_) => Default::default(),
+        }),
+    }
 }
 
 fn get_return_type(method: &TraitItemMethod) -> &syn::Type {
@@ -236,43 +813,66 @@ fn param_tokens_after_this(method: &TraitItemMethod) -> Punctuated<FnArg, Comma>
     method.sig.inputs.clone().into_pairs().skip(1).collect()
 }
 
-fn impl_async_client_method(method: &TraitItemMethod, id: u32) -> TokenStream2 {
+fn impl_async_client_method(method: &TraitItemMethod, id: u32, info: &TraitInfo) -> TokenStream2 {
     let ident = &method.sig.ident;
 
     // get the parameters without the &self as we want to add a lifetime to that
     let param_tokens = param_tokens_after_this(method);
 
-    if !verify_self_param_or_unneeded(method) {
+    if !is_remote(method) {
         return TokenStream2::new();
     }
 
     let rettype = get_return_type(method);
-    let tx_send = async_client_method_tx_send(method, id);
+    let wire_type = wire_return_type(method, info);
+    let convert = client_convert_return(method, info);
+    let attrs = method_attrs(method);
+    let call = client_method_call(method, id, info, true);
 
     quote!(
+    #(#attrs)*
     async fn #ident(&self, #param_tokens) -> #rettype {
-        #tx_send
-        let ret = tr.rx_response(state).await?;
-        ret
+        let ret: essrpc::internal::result::Result<#wire_type, essrpc::RPCError> = async {
+            #call
+        }.await;
+        #convert
     })
 }
 
-fn create_async_client_trait(trait_ident: &Ident, methods: &[TraitItemMethod]) -> TokenStream2 {
-    let ident = async_client_trait_ident(trait_ident);
+fn create_async_client_trait(
+    ident: &Ident,
+    methods: &[TraitItemMethod],
+    info: &TraitInfo,
+) -> TokenStream2 {
+    let vis = &info.vis;
+    let trait_cfg_attrs = &info.cfg_attrs;
     let mut method_decls: Vec<TokenStream2> = Vec::new();
 
-    for method in methods {
+    // Values returned from async methods must be sendable between threads.
+    let assoc_type_decls = info.assoc_types.iter().map(|t| {
+        let ident = &t.ident;
+        let bounds = t.bounds.iter();
+        quote!(type #ident: Send + 'static #(+ #bounds)*;)
+    });
+
+    // Local methods and associated functions cannot be shared with
+    // the synchronous trait, so are left out.
+    for method in methods.iter().filter(|m| is_remote(m)) {
         let rettype = get_return_type(method);
         let ident = &method.sig.ident;
+        let attrs = method_attrs(method);
         let param_tokens = param_tokens_after_this(method);
         method_decls.push(quote!(
+        #(#attrs)*
         async fn #ident(&self, #param_tokens) -> #rettype;
             ));
     }
 
     quote!(
+        #(#trait_cfg_attrs)*
         #[essrpc::internal::rpc_async_trait]
-        pub trait #ident {
+        #vis trait #ident {
+           #(#assoc_type_decls)*
            #(#method_decls)*
         }
     )
@@ -280,10 +880,13 @@ fn create_async_client_trait(trait_ident: &Ident, methods: &[TraitItemMethod]) -
 
 fn create_client(
     trait_ident: &Ident,
+    client_ident: &Ident,
     methods: &[TraitItemMethod],
+    info: &TraitInfo,
     async_client: bool,
 ) -> TokenStream2 {
-    let client_ident = client_ident(trait_ident);
+    let vis = &info.vis;
+    let trait_cfg_attrs = &info.cfg_attrs;
     let transport_ident = client_transport_ident(async_client);
     let rpcclient_ident = rpcclient_ident(async_client);
 
@@ -292,13 +895,35 @@ fn create_client(
     let mut mcnt = 0;
     for method in methods {
         method_impl_tokens.extend(if async_client {
-            impl_async_client_method(method, mcnt)
+            impl_async_client_method(method, mcnt, info)
         } else {
-            impl_client_method(method, mcnt)
+            impl_client_method(method, mcnt, info)
         });
         mcnt += 1;
     }
 
+    let assoc_idents = info.assoc_type_idents();
+    let assoc_bounds = info.assoc_types.iter().map(|t| {
+        let ident = &t.ident;
+        let bounds = t.bounds.iter();
+        if async_client {
+            quote!(#ident: Send + 'static #(+ #bounds)*,)
+        } else {
+            quote!(#ident: #(#bounds)+*,)
+        }
+    });
+    // Associated types are not otherwise used in the client struct
+    let (phantom_field, phantom_init) = if assoc_idents.is_empty() {
+        (None, None)
+    } else {
+        (
+            Some(
+                quote!(, _assoc_types: essrpc::internal::marker::PhantomData<fn() -> (#(#assoc_idents,)*)>),
+            ),
+            Some(quote!(, _assoc_types: essrpc::internal::marker::PhantomData)),
+        )
+    };
+
     let impl_attrs: Option<TokenStream2>;
     // Since our traits generally take &self, but there's no
     // expectation that our transport is Sync, we do need to use a
@@ -313,106 +938,380 @@ fn create_client(
     };
 
     quote!(
-        pub struct #client_ident<TR: essrpc::#transport_ident> {
-            tr: #mutex_type<TR>
+        #(#trait_cfg_attrs)*
+        #vis struct #client_ident<TR: essrpc::#transport_ident #(, #assoc_idents)*> {
+            tr: #mutex_type<TR>,
+            interceptors: essrpc::internal::SyncMutex<essrpc::client::ClientInterceptors>
+            #phantom_field
+        }
+
+        #(#trait_cfg_attrs)*
+        impl <TR #(, #assoc_idents)*> #client_ident<TR #(, #assoc_idents)*> where
+            TR: essrpc::#transport_ident {
+
+            /// Add an interceptor, invoked around each call after
+            /// those already added. See `essrpc::client::ClientInterceptor`.
+            pub fn with_interceptor(
+                self,
+                interceptor: impl essrpc::client::ClientInterceptor + Send + 'static) -> Self {
+                self.interceptors.lock().push(interceptor);
+                self
+            }
         }
 
-        impl <TR> essrpc::#rpcclient_ident for #client_ident<TR> where
+        #(#trait_cfg_attrs)*
+        impl <TR #(, #assoc_idents)*> essrpc::#rpcclient_ident for #client_ident<TR #(, #assoc_idents)*> where
             TR: essrpc::#transport_ident {
 
             type TR = TR;
 
             fn new(transport: TR) -> Self {
                 //#client_ident{tr: std::sync::Arc::new(essrpc::internal::AtomicRefCell::new(transport))}
-                #client_ident{tr: #mutex_type::new(transport)}
+                #client_ident{tr: #mutex_type::new(transport),
+                              interceptors: essrpc::internal::SyncMutex::new(
+                                  essrpc::client::ClientInterceptors::new())
+                              #phantom_init}
             }
         }
 
+        #(#trait_cfg_attrs)*
         #impl_attrs
-        impl <TR> #trait_ident for #client_ident<TR> where
-            TR: essrpc::#transport_ident {
+        impl <TR #(, #assoc_idents)*> #trait_ident for #client_ident<TR #(, #assoc_idents)*> where
+            TR: essrpc::#transport_ident,
+            #(#assoc_bounds)* {
+
+            #(type #assoc_idents = #assoc_idents;)*
 
             #method_impl_tokens
         }
     )
 }
 
-fn create_server(trait_ident: &Ident, methods: &[TraitItemMethod]) -> TokenStream2 {
-    let server_ident = server_ident(trait_ident);
+fn create_server(
+    trait_ident: &Ident,
+    server_ident: &Ident,
+    methods: &[TraitItemMethod],
+    info: &TraitInfo,
+) -> TokenStream2 {
+    let vis = &info.vis;
+    let trait_cfg_attrs = &info.cfg_attrs;
 
     let mut server_method_matches = TokenStream2::new();
     let mut server_by_name_matches = TokenStream2::new();
+    let mut server_by_num_matches = TokenStream2::new();
 
     let mut mcnt = 0;
     for method in methods {
-        server_method_matches.extend(create_server_match(method, mcnt));
+        if !is_remote(method) {
+            mcnt += 1;
+            continue;
+        }
+        server_method_matches.extend(create_server_match(method, mcnt, info));
         let ident_literal = make_ident_literal_str(&method.sig.ident);
-        server_by_name_matches.extend(quote!(#ident_literal => #mcnt,));
+        let method_cfg_attrs = cfg_attrs(&method.attrs);
+        server_by_name_matches.extend(quote!(#(#method_cfg_attrs)* #ident_literal => #mcnt,));
+        server_by_num_matches.extend(quote!(#(#method_cfg_attrs)* #mcnt => Some(#ident_literal),));
         mcnt += 1;
     }
 
+    // Interceptors, shutdown, connections and panic catching rely on
+    // `essrpc::server`, which requires std.
+    let (std_fields, std_inits, std_methods, before_call, std_rpcserver_methods) = if cfg!(
+        feature = "std"
+    ) {
+        (
+            quote!(
+                shutdown: Option<essrpc::server::ShutdownHandle>,
+                connection: Option<essrpc::server::Connection>,
+                interceptors: essrpc::server::Interceptors<TR>,
+                catch_panics: bool,
+                on_error: Option<essrpc::server::ErrorCallback>,
+            ),
+            quote!(
+                shutdown: None,
+                connection: None,
+                interceptors: essrpc::server::Interceptors::new(),
+                catch_panics: true,
+                on_error: None,
+            ),
+            quote!(
+                /// Add an interceptor, invoked around each call after
+                /// those already added. See `essrpc::server::Interceptor`.
+                pub fn with_interceptor(
+                    mut self,
+                    interceptor: impl essrpc::server::Interceptor<TR> + Send + 'static) -> Self {
+                    self.interceptors.push(interceptor);
+                    self
+                }
+
+                /// Whether to catch a panic in the implementation, responding
+                /// with an `RPCErrorKind::ServerPanic` error and continuing
+                /// to serve (the default). If `false`, a panic unwinds through
+                /// `serve_single_call`.
+                pub fn catch_panics(mut self, catch: bool) -> Self {
+                    self.catch_panics = catch;
+                    self
+                }
+
+                /// Use `shutdown` to stop `serve`. See `essrpc::server::ShutdownHandle`.
+                pub fn with_shutdown(mut self, shutdown: essrpc::server::ShutdownHandle) -> Self {
+                    self.shutdown = Some(shutdown);
+                    self
+                }
+
+                /// Call `f` with an error which serving went on after without the
+                /// client being sent it. See `essrpc::RPCServer::report_error`.
+                pub fn on_error(
+                    mut self,
+                    f: impl Fn(
+                        Option<&essrpc::server::Connection>,
+                        &essrpc::RPCError) + Send + Sync + 'static) -> Self {
+                    self.on_error = Some(std::sync::Arc::new(f));
+                    self
+                }
+
+                fn method_name_from_num(num: u32) -> Option<&'static str> {
+                    match num {
+                        #server_by_num_matches
+                        _ => None
+                    }
+                }
+            ),
+            quote!(
+                let call = essrpc::server::CallInfo::new(
+                    &method, Self::method_name_from_num(id), &metadata, self.connection.as_ref());
+                let scope = essrpc::server::MetadataScope::enter(&metadata);
+                let before = self.interceptors.before_call(&call, &mut rxstate);
+            ),
+            quote!(
+                fn shutdown_handle(&self) -> Option<&essrpc::server::ShutdownHandle> {
+                    self.shutdown.as_ref()
+                }
+
+                fn set_connection(&mut self, connection: &essrpc::server::Connection) {
+                    self.connection = Some(connection.clone());
+                }
+
+                fn report_error(&self, error: &essrpc::RPCError) {
+                    if let Some(f) = &self.on_error {
+                        f(self.connection.as_ref(), error)
+                    }
+                }
+            ),
+        )
+    } else {
+        (
+            TokenStream2::new(),
+            TokenStream2::new(),
+            TokenStream2::new(),
+            // Metadata is still read to keep the transport in step,
+            // but there is nowhere to make it available.
+            quote!(
+                let _ = metadata;
+                let before: essrpc::internal::result::Result<(), essrpc::RPCError> = Ok(());
+            ),
+            TokenStream2::new(),
+        )
+    };
+
     quote!(
-        pub struct #server_ident<T, TR> where
+        #(#trait_cfg_attrs)*
+        #vis struct #server_ident<T, TR> where
             T: #trait_ident,
             TR: essrpc::ServerTransport {
 
             tr: TR,
-            imp: T
+            imp: T,
+            #std_fields
         }
 
+        #(#trait_cfg_attrs)*
         impl <T, TR> #server_ident<T, TR> where
             T: #trait_ident,
             TR: essrpc::ServerTransport {
 
             pub fn new(imp: T, transport: TR) -> Self {
                 #server_ident{tr: transport,
-                              imp: imp}
+                              imp: imp,
+                              #std_inits}
             }
 
+            #std_methods
+
             fn method_num_from_name(name: &str) -> u32 {
                 match name {
                     #server_by_name_matches
-                    _ => std::u32::MAX
+                    _ => u32::MAX
                 }
             }
 
-        }
+            // Respond to a failed call of a method whose return type cannot
+            // carry the error, through the transport if it can. Otherwise
+            // the error is reported and serving goes on.
+            fn reject_infallible(
+                &mut self,
+                e: essrpc::RPCError
+            ) -> essrpc::internal::result::Result<(), essrpc::RPCError> {
+                let kind = e.kind;
+                match self.tr.tx_error(e) {
+                    Err(e) if e.kind == kind => {
+                        essrpc::RPCServer::report_error(self, &e);
+                        Ok(())
+                    }
+                    result => result,
+                }
+            }
 
-        impl <TR, T> essrpc::RPCServer for #server_ident<T, TR> where
-            TR: essrpc::ServerTransport,
-            T: #trait_ident
-        {
-            fn serve_single_call(&mut self) -> std::result::Result<(), essrpc::RPCError> {
-                let (method, mut rxstate) = self.tr.rx_begin_call()?;
+            // Serve a call which has begun, up to transmitting its response.
+            fn serve_call(
+                &mut self,
+                method: essrpc::PartialMethodId,
+                mut rxstate: TR::RXState
+            ) -> essrpc::internal::result::Result<(), essrpc::RPCError> {
+                let metadata = self.tr.rx_metadata(&mut rxstate)?;
                 let id = match &method {
                     essrpc::PartialMethodId::Num(num) => *num,
                     essrpc::PartialMethodId::Name(name) => Self::method_num_from_name(&name),
                 };
+                #before_call
                 match id {
                     #server_method_matches
                     _ => {
+                        before?;
                         Err(essrpc::RPCError::new(
-                            essrpc::RPCErrorKind::UnknownMethod, format!("Unknown rpc method {:?}", method)))
+                            essrpc::RPCErrorKind::UnknownMethod,
+                            essrpc::internal::format!("Unknown rpc method {:?}", method)))
+                    }
+                }
+            }
+        }
+
+        #(#trait_cfg_attrs)*
+        impl <TR, T> essrpc::RPCServer for #server_ident<T, TR> where
+            TR: essrpc::ServerTransport,
+            T: #trait_ident
+        {
+            fn serve_single_call(&mut self) -> essrpc::internal::result::Result<(), essrpc::RPCError> {
+                let (method, rxstate) = match self.tr.rx_begin_call() {
+                    Ok(call) => call,
+                    // The transport has discarded an oversized call, to
+                    // which it may be able to respond.
+                    Err(e) if e.kind == essrpc::RPCErrorKind::MessageTooLarge => {
+                        return self.tr.tx_error(e)
                     }
+                    Err(e) => return Err(e),
+                };
+                match self.serve_call(method, rxstate) {
+                    Err(e) => self.tr.tx_error(e),
+                    result => result,
                 }
             }
+
+            #std_rpcserver_methods
         }
     )
 }
 
-fn create_server_match(method: &TraitItemMethod, id: u32) -> TokenStream2 {
+// Implement the trait for Arc<T> by forwarding to T. Items which
+// cannot be forwarded keep their default implementation.
+fn create_arc_impl(ast_trait: &ItemTrait, info: &TraitInfo) -> TokenStream2 {
+    let trait_ident = &ast_trait.ident;
+    let trait_cfg_attrs = &info.cfg_attrs;
+
+    let mut items = TokenStream2::new();
+    for item in &ast_trait.items {
+        match item {
+            TraitItem::Type(t) => {
+                let ident = &t.ident;
+                items.extend(quote!(type #ident = T::#ident;));
+            }
+            TraitItem::Const(c) => {
+                let ident = &c.ident;
+                let ty = &c.ty;
+                items.extend(quote!(const #ident: #ty = T::#ident;));
+            }
+            TraitItem::Method(m) => match forward_to_inner(m) {
+                Some(tokens) => items.extend(tokens),
+                None if m.default.is_some() => (),
+                None => return TokenStream2::new(),
+            },
+            _ => (),
+        }
+    }
+
+    quote!(
+        #(#trait_cfg_attrs)*
+        impl <T> #trait_ident for std::sync::Arc<T> where
+            T: #trait_ident {
+            #items
+        }
+    )
+}
+
+// A method implementation calling the same method of the Arc's
+// contents, if the method takes `&self`.
+fn forward_to_inner(method: &TraitItemMethod) -> Option<TokenStream2> {
+    let takes_ref_self = matches!(
+        method.sig.inputs.first(),
+        Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_none()
+    );
+    if !takes_ref_self {
+        return None;
+    }
+    let mut params = Vec::new();
+    for p in method.sig.inputs.iter().skip(1) {
+        match p {
+            FnArg::Typed(arg) => match &*arg.pat {
+                Pat::Ident(pat) if pat.by_ref.is_none() => params.push(&pat.ident),
+                _ => return None,
+            },
+            FnArg::Receiver(_) => return None,
+        }
+    }
+    let sig = &method.sig;
+    let ident = &sig.ident;
+    let method_cfg_attrs = cfg_attrs(&method.attrs);
+    Some(quote!(
+        #(#method_cfg_attrs)*
+        #sig {
+            T::#ident(&**self #(, #params)*)
+        }
+    ))
+}
+
+fn create_server_match(method: &TraitItemMethod, id: u32, info: &TraitInfo) -> TokenStream2 {
     let ident = &method.sig.ident;
     let param_tokens = &method.sig.inputs;
+    let method_cfg_attrs = cfg_attrs(&method.attrs);
 
     let mut param_retrieve_tokens = TokenStream2::new();
     let mut param_call_tokens = TokenStream2::new();
     let mut first = true;
 
-    for p in param_tokens.iter() {
+    for (index, p) in param_tokens.iter().enumerate() {
         if let FnArg::Typed(arg) = p {
             let name = &arg.pat;
+            if info.is_context_param(method, index) {
+                // Created before reading any parameter which could
+                // shadow `metadata`.
+                let retrieve = quote!(
+                    let #name = essrpc::CallContext::for_call(
+                        self.connection.as_ref(), &metadata, self.shutdown.as_ref());
+                );
+                param_retrieve_tokens = quote!(#retrieve #param_retrieve_tokens);
+                if !first {
+                    param_call_tokens.extend(quote!(,))
+                }
+                first = false;
+                param_call_tokens.extend(quote!(&#name));
+                continue;
+            }
             let name_literal = make_pat_literal_str(name);
-            let ty = &arg.ty;
+            // Self here would refer to the server rather than the trait implementation
+            let ty = replace_self(
+                arg.ty.to_token_stream(),
+                &Ident::new("T", Span::call_site()),
+            );
             param_retrieve_tokens.extend(
                 quote!(let #name: #ty = self.tr.rx_read_param(#name_literal, &mut rxstate)?;),
             );
@@ -425,11 +1324,62 @@ fn create_server_match(method: &TraitItemMethod, id: u32) -> TokenStream2 {
         }
     }
 
+    let convert_error = match return_kind(method, info) {
+        ReturnKind::DynError(_) => Some(
+            quote!(let ret = ret.map_err(|e| essrpc::GenericSerializableError::from_dyn(&*e));),
+        ),
+        _ => None,
+    };
+    // Response in place of the method's result when an interceptor
+    // rejects the call or the implementation panics.
+    let rejected = match return_kind(method, info) {
+        ReturnKind::Result => quote!(Err(essrpc::internal::convert::From::from(e))),
+        ReturnKind::DynError(_) => quote!(Err(essrpc::GenericSerializableError::new(e))),
+        ReturnKind::Infallible(_) => quote!(return self.reject_infallible(e)),
+    };
+    let ret_type = replace_self(
+        wire_return_type(method, info),
+        &Ident::new("T", Span::call_site()),
+    );
+
+    let (call_imp, tx_response) = if cfg!(feature = "std") {
+        (
+            quote!(essrpc::server::catch_panic(
+                self.catch_panics, || self.imp.#ident(#param_call_tokens))),
+            quote!(
+                self.tr.tx_set_response_metadata(&scope.response_metadata())?;
+                let size = self.tr.tx_response_sized(&ret)?;
+                self.interceptors.after_call(&call, &ret, size);
+                Ok(())
+            ),
+        )
+    } else {
+        (
+            quote!(essrpc::internal::result::Result::<_, essrpc::RPCError>::Ok(
+                self.imp.#ident(#param_call_tokens))),
+            quote!(self.tr.tx_response(ret)),
+        )
+    };
+
     quote!(
+        #(#method_cfg_attrs)*
         #id => {
-            #param_retrieve_tokens
-            let ret = self.imp.#ident(#param_call_tokens);
-            self.tr.tx_response(ret)
+            let ret: #ret_type = match before {
+                Ok(()) => {
+                    #param_retrieve_tokens
+                    self.tr.rx_end_call(&mut rxstate)?;
+                    let ret = #call_imp;
+                    match ret {
+                        Ok(ret) => {
+                            #convert_error
+                            ret
+                        }
+                        Err(e) => #rejected,
+                    }
+                }
+                Err(e) => #rejected,
+            };
+            #tx_response
         },
     )
 }
@@ -0,0 +1,66 @@
+//! Generation of loopback client constructors on servers, for the
+//! `loopback_transport` feature of essrpc.
+
+use proc_macro2::{Ident, TokenStream as TokenStream2};
+use quote::quote;
+
+use crate::TraitInfo;
+
+pub(crate) fn create_loopback(
+    trait_ident: &Ident,
+    server_ident: &Ident,
+    client_ident: Option<&Ident>,
+    async_client_ident: Option<&Ident>,
+    info: &TraitInfo,
+) -> TokenStream2 {
+    let trait_cfg_attrs = &info.cfg_attrs;
+    let assoc_idents = info.assoc_type_idents();
+
+    let sync_constructors = client_ident.map(|client_ident| {
+        let client_type = quote!(#client_ident<essrpc::transports::LoopbackTransport<Self> #(, T::#assoc_idents)*>);
+        quote!(
+            /// Create a client which calls `imp` in this process,
+            /// through a `LoopbackTransport` in `LoopbackMode::Value`.
+            pub fn loopback(imp: T) -> #client_type {
+                Self::loopback_with_mode(imp, essrpc::transports::LoopbackMode::Value)
+            }
+
+            /// Create a client which calls `imp` in this process,
+            /// through a `LoopbackTransport` in the given mode.
+            pub fn loopback_with_mode(imp: T, mode: essrpc::transports::LoopbackMode) -> #client_type {
+                essrpc::RPCClient::new(essrpc::transports::LoopbackTransport::new(
+                    mode, |tr| Self::new(imp, tr)))
+            }
+        )
+    });
+
+    let async_constructors = async_client_ident.map(|client_ident| {
+        let client_type = quote!(#client_ident<essrpc::transports::LoopbackAsyncClientTransport<Self> #(, T::#assoc_idents)*>);
+        quote!(
+            /// Create an async client which calls `imp` in this
+            /// process, through a `LoopbackAsyncClientTransport` in
+            /// `LoopbackMode::Value`.
+            pub fn loopback_async(imp: T) -> #client_type where T: Send {
+                Self::loopback_async_with_mode(imp, essrpc::transports::LoopbackMode::Value)
+            }
+
+            /// Create an async client which calls `imp` in this
+            /// process, through a `LoopbackAsyncClientTransport` in
+            /// the given mode.
+            pub fn loopback_async_with_mode(imp: T, mode: essrpc::transports::LoopbackMode) -> #client_type
+            where T: Send {
+                essrpc::AsyncRPCClient::new(essrpc::transports::LoopbackAsyncClientTransport::new(
+                    mode, |tr| Self::new(imp, tr)))
+            }
+        )
+    });
+
+    quote!(
+        #(#trait_cfg_attrs)*
+        impl <T> #server_ident<T, essrpc::transports::LoopbackServerTransport> where
+            T: #trait_ident {
+            #sync_constructors
+            #async_constructors
+        }
+    )
+}
@@ -0,0 +1,264 @@
+//! Generation of mock implementations of RPC traits, for `#[essrpc(mock)]`.
+
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2, TokenTree};
+use quote::{quote, ToTokens};
+use syn::{FnArg, LitStr, TraitItemMethod};
+
+use crate::{
+    client_convert_return, get_return_type, is_remote, make_ident_literal_str, method_attrs,
+    param_tokens_after_this, return_kind, ReturnKind, TraitInfo,
+};
+
+fn mock_ident(trait_ident: &Ident) -> Ident {
+    Ident::new(&format!("Mock{}", trait_ident), Span::call_site())
+}
+
+fn expect_ident(method: &TraitItemMethod) -> Ident {
+    Ident::new(&format!("expect_{}", method.sig.ident), Span::call_site())
+}
+
+// Remove `Self::` qualification, so that associated types refer to
+// type parameters of the same name (e.g. `Self::Item` becomes `Item`).
+fn strip_self(tokens: TokenStream2) -> TokenStream2 {
+    let mut result = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(tok) = tokens.next() {
+        match tok {
+            TokenTree::Ident(ref ident) if ident == "Self" => {
+                // Skip the `::` following Self
+                while matches!(tokens.peek(), Some(TokenTree::Punct(p)) if p.as_char() == ':') {
+                    tokens.next();
+                }
+            }
+            TokenTree::Group(g) => {
+                let mut new_group = proc_macro2::Group::new(g.delimiter(), strip_self(g.stream()));
+                new_group.set_span(g.span());
+                result.push(TokenTree::Group(new_group));
+            }
+            other => result.push(other),
+        }
+    }
+    result.into_iter().collect()
+}
+
+// Parameter names and types of a method, excluding self and the
+// context parameter.
+fn params<'a>(
+    method: &'a TraitItemMethod,
+    info: &TraitInfo,
+) -> (Vec<&'a syn::Pat>, Vec<&'a syn::Type>) {
+    method
+        .sig
+        .inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, p)| match p {
+            FnArg::Typed(_) if info.is_context_param(method, index) => None,
+            FnArg::Typed(arg) => Some((arg.pat.as_ref(), arg.ty.as_ref())),
+            FnArg::Receiver(_) => None,
+        })
+        .unzip()
+}
+
+// Parameter tuple and return types of a method, for use outside of a
+// trait impl.
+fn expectation_types(method: &TraitItemMethod, info: &TraitInfo) -> (TokenStream2, TokenStream2) {
+    let (_, types) = params(method, info);
+    let rettype = get_return_type(method);
+    (
+        strip_self(quote!((#(#types,)*))),
+        strip_self(rettype.to_token_stream()),
+    )
+}
+
+// Conversion of `ret`, a `Result<return type, RPCError>`, into the
+// return type. Unlike the client, errors of dynamic type are never
+// received as a `GenericSerializableError`.
+fn mock_convert_return(method: &TraitItemMethod, info: &TraitInfo) -> TokenStream2 {
+    match return_kind(method, info) {
+        ReturnKind::Result | ReturnKind::DynError(_) => quote!(match ret {
+            Ok(v) => v,
+            Err(e) => Err(e.into()),
+        }),
+        ReturnKind::Infallible(_) => client_convert_return(method, info),
+    }
+}
+
+fn impl_mock_method(method: &TraitItemMethod, info: &TraitInfo, async_trait: bool) -> TokenStream2 {
+    let ident = &method.sig.ident;
+    let (names, _) = params(method, info);
+    let rettype = get_return_type(method);
+    let attrs = method_attrs(method);
+    let convert = mock_convert_return(method, info);
+    // The context parameter is not passed to expectations
+    let context = method
+        .sig
+        .inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, p)| match p {
+            FnArg::Typed(arg) if info.is_context_param(method, index) => Some(&arg.pat),
+            _ => None,
+        });
+    let body = quote!(
+        #(let _ = #context;)*
+        let ret = self.#ident.lock().call((#(#names,)*));
+        #convert
+    );
+    if async_trait {
+        let param_tokens = param_tokens_after_this(method);
+        quote!(
+            #(#attrs)*
+            async fn #ident(&self, #param_tokens) -> #rettype {
+                #body
+            }
+        )
+    } else {
+        let param_tokens = &method.sig.inputs;
+        quote!(
+            #(#attrs)*
+            fn #ident(#param_tokens) -> #rettype {
+                #body
+            }
+        )
+    }
+}
+
+pub(crate) fn create_mock(
+    trait_ident: &Ident,
+    async_trait_ident: Option<&Ident>,
+    methods: &[TraitItemMethod],
+    info: &TraitInfo,
+) -> TokenStream2 {
+    let vis = &info.vis;
+    let trait_cfg_attrs = &info.cfg_attrs;
+    let mock_ident = mock_ident(trait_ident);
+    let methods: Vec<&TraitItemMethod> = methods.iter().filter(|m| is_remote(m)).collect();
+
+    let assoc_idents = info.assoc_type_idents();
+    let assoc_bounds: Vec<TokenStream2> = info
+        .assoc_types
+        .iter()
+        .map(|t| {
+            let ident = &t.ident;
+            let bounds = t.bounds.iter();
+            quote!(#ident: Send + 'static #(+ #bounds)*,)
+        })
+        .collect();
+    let (phantom_field, phantom_init) = if assoc_idents.is_empty() {
+        (None, None)
+    } else {
+        (
+            Some(quote!(_assoc_types: std::marker::PhantomData<fn() -> (#(#assoc_idents,)*)>,)),
+            Some(quote!(_assoc_types: std::marker::PhantomData,)),
+        )
+    };
+
+    let mut fields = Vec::new();
+    let mut inits = Vec::new();
+    let mut expects = Vec::new();
+    let mut counts = Vec::new();
+    let mut checkpoints = Vec::new();
+    for method in &methods {
+        let ident = &method.sig.ident;
+        let ident_literal = make_ident_literal_str(ident);
+        let qualified_literal =
+            LitStr::new(&format!("{}::{}", trait_ident, ident), Span::call_site());
+        let expect_ident = expect_ident(method);
+        let (params_type, return_type) = expectation_types(method, info);
+        let method_cfg_attrs = crate::cfg_attrs(&method.attrs);
+        let doc = format!(
+            "Add an expectation for a call to `{}`. Expectations are matched in the order they were added.",
+            ident
+        );
+        fields.push(quote!(
+            #(#method_cfg_attrs)*
+            #ident: essrpc::internal::SyncMutex<essrpc::mock::Expectations<#params_type, #return_type>>,
+        ));
+        inits.push(quote!(
+            #(#method_cfg_attrs)*
+            #ident: essrpc::internal::SyncMutex::new(
+                essrpc::mock::Expectations::new(#qualified_literal)),
+        ));
+        expects.push(quote!(
+            #(#method_cfg_attrs)*
+            #[doc = #doc]
+            pub fn #expect_ident(&mut self) -> &mut essrpc::mock::Expectation<#params_type, #return_type> {
+                self.#ident.get_mut().expect()
+            }
+        ));
+        counts.push(quote!(
+            #(#method_cfg_attrs)*
+            #ident_literal => self.#ident.lock().call_count(),
+        ));
+        checkpoints.push(quote!(
+            #(#method_cfg_attrs)*
+            self.#ident.get_mut().checkpoint();
+        ));
+    }
+
+    let sync_methods = methods.iter().map(|m| impl_mock_method(m, info, false));
+    let mock_doc = format!("Mock implementation of `{}` for use in tests.", trait_ident);
+    let mut result = quote!(
+        #(#trait_cfg_attrs)*
+        #[doc = #mock_doc]
+        #vis struct #mock_ident<#(#assoc_idents),*> {
+            #(#fields)*
+            #phantom_field
+        }
+
+        #(#trait_cfg_attrs)*
+        impl <#(#assoc_idents),*> #mock_ident<#(#assoc_idents),*> where #(#assoc_bounds)* {
+            /// Create a mock with no expectations. Any call will panic.
+            pub fn new() -> Self {
+                #mock_ident {
+                    #(#inits)*
+                    #phantom_init
+                }
+            }
+
+            #(#expects)*
+
+            /// Number of calls made to the named method.
+            pub fn call_count(&self, method: &str) -> usize {
+                match method {
+                    #(#counts)*
+                    _ => panic!("Unknown method {}", method),
+                }
+            }
+
+            /// Verify that every expectation with a call count given
+            /// by `times` was satisfied, panicking otherwise, then
+            /// remove all expectations.
+            pub fn checkpoint(&mut self) {
+                #(#checkpoints)*
+            }
+        }
+
+        #(#trait_cfg_attrs)*
+        impl <#(#assoc_idents),*> Default for #mock_ident<#(#assoc_idents),*> where #(#assoc_bounds)* {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        #(#trait_cfg_attrs)*
+        impl <#(#assoc_idents),*> #trait_ident for #mock_ident<#(#assoc_idents),*> where #(#assoc_bounds)* {
+            #(type #assoc_idents = #assoc_idents;)*
+            #(#sync_methods)*
+        }
+    );
+
+    if let Some(async_trait_ident) = async_trait_ident {
+        let async_methods = methods.iter().map(|m| impl_mock_method(m, info, true));
+        result.extend(quote!(
+            #(#trait_cfg_attrs)*
+            #[essrpc::internal::rpc_async_trait]
+            impl <#(#assoc_idents),*> #async_trait_ident for #mock_ident<#(#assoc_idents),*> where #(#assoc_bounds)* {
+                #(type #assoc_idents = #assoc_idents;)*
+                #(#async_methods)*
+            }
+        ));
+    }
+    result.into_token_stream()
+}